@@ -0,0 +1,261 @@
+use axum::body::Body;
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// Size of the chunks handed to the response body
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Archive formats offered for directory downloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Parse the `download` query value (`tar.gz` or `zip`)
+    pub fn from_query(query: Option<&str>) -> Option<Self> {
+        query?.split('&').find_map(|pair| match pair {
+            "download=tar.gz" => Some(Self::TarGz),
+            "download=zip" => Some(Self::Zip),
+            _ => None,
+        })
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::TarGz => "tar.gz",
+            Self::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::TarGz => "application/gzip",
+            Self::Zip => "application/zip",
+        }
+    }
+}
+
+/// Stream an archive of `dir` as a response body
+///
+/// The archive is written on a blocking thread into a bounded channel, so
+/// only a few chunks are held in memory at a time. `include` receives each
+/// entry's path relative to `dir` (with `/` separators) and decides whether it
+/// is archived; excluded directories are not descended into. Symlinks are
+/// skipped, so the archive never reaches outside `dir`.
+pub fn stream_archive<F>(dir: PathBuf, format: ArchiveFormat, include: F) -> Body
+where
+    F: Fn(&str) -> bool + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(4);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter {
+            tx: tx.clone(),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let result = match format {
+            ArchiveFormat::TarGz => write_tar_gz(&dir, &include, &mut writer),
+            ArchiveFormat::Zip => write_zip(&dir, &include, &mut writer),
+        }
+        .and_then(|_| writer.flush());
+        if let Err(e) = result {
+            // Aborts the response; the client sees a truncated download
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+    Body::from_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }))
+}
+
+fn write_tar_gz<F: Fn(&str) -> bool>(
+    dir: &Path,
+    include: &F,
+    out: &mut ChannelWriter,
+) -> io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    for (rel, path, is_dir) in walk(dir, include)? {
+        if is_dir {
+            tar.append_dir(&rel, &path)?;
+        } else {
+            tar.append_path_with_name(&path, &rel)?;
+        }
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn write_zip<F: Fn(&str) -> bool>(
+    dir: &Path,
+    include: &F,
+    out: &mut ChannelWriter,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(out);
+    for (rel, path, is_dir) in walk(dir, include)? {
+        if is_dir {
+            zip.add_directory(rel, SimpleFileOptions::default())?;
+            continue;
+        }
+        let mut file = File::open(&path)?;
+        let large = file.metadata()?.len() >= u32::MAX as u64;
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(large);
+        zip.start_file(rel, options)?;
+        io::copy(&mut file, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Collect the entries below `dir` in depth-first, name order
+fn walk<F: Fn(&str) -> bool>(dir: &Path, include: &F) -> io::Result<Vec<(String, PathBuf, bool)>> {
+    let mut entries = Vec::new();
+    let mut stack = vec![(String::new(), dir.to_path_buf())];
+    while let Some((prefix, path)) = stack.pop() {
+        let mut children: Vec<_> = std::fs::read_dir(&path)?.collect::<io::Result<_>>()?;
+        children.sort_by_key(|e| e.file_name());
+        let mut subdirs = Vec::new();
+        for child in children {
+            let name = child.file_name().to_string_lossy().to_string();
+            let rel = format!("{prefix}{name}");
+            let file_type = child.file_type()?;
+            if file_type.is_symlink() || !include(&rel) {
+                continue;
+            }
+            if file_type.is_dir() {
+                entries.push((format!("{rel}/"), child.path(), true));
+                subdirs.push((format!("{rel}/"), child.path()));
+            } else if file_type.is_file() {
+                entries.push((rel, child.path(), false));
+            }
+        }
+        // Reverse so the stack visits subdirectories in name order
+        stack.extend(subdirs.into_iter().rev());
+    }
+    Ok(entries)
+}
+
+/// `Write` adapter that forwards fixed-size chunks to the response body
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buf,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn temp_tree(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("statiker-archive-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join("a.txt"), "alpha").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), "beta").unwrap();
+        std::fs::write(dir.join(".git/config"), "secret").unwrap();
+        dir
+    }
+
+    async fn collect(body: Body) -> Vec<u8> {
+        axum::body::to_bytes(body, usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    fn no_dotfiles(rel: &str) -> bool {
+        !rel.split('/').any(|s| s.starts_with('.'))
+    }
+
+    #[test]
+    fn test_format_from_query() {
+        assert_eq!(
+            ArchiveFormat::from_query(Some("download=zip")),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(
+            ArchiveFormat::from_query(Some("x=1&download=tar.gz")),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::from_query(Some("download=rar")), None);
+        assert_eq!(ArchiveFormat::from_query(None), None);
+    }
+
+    #[tokio::test]
+    async fn test_tar_gz_archive() {
+        let dir = temp_tree("tar");
+        let bytes = collect(stream_archive(dir, ArchiveFormat::TarGz, no_dotfiles)).await;
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&bytes[..]));
+        let mut files = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            files.push((path, content));
+        }
+        assert_eq!(
+            files,
+            vec![
+                ("a.txt".to_string(), "alpha".to_string()),
+                ("sub/".to_string(), String::new()),
+                ("sub/b.txt".to_string(), "beta".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zip_archive() {
+        let dir = temp_tree("zip");
+        let bytes = collect(stream_archive(dir, ArchiveFormat::Zip, no_dotfiles)).await;
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes)).unwrap();
+        let mut names: Vec<_> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "sub/", "sub/b.txt"]);
+        let mut content = String::new();
+        archive
+            .by_name("sub/b.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "beta");
+    }
+}
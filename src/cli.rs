@@ -1,5 +1,7 @@
 use crate::config::Config;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::{path::PathBuf, time::Duration};
+use tracing::{debug, info, warn};
 
 /// Static file server with proxy support
 #[derive(Parser, Debug)]
@@ -8,38 +10,115 @@ use clap::Parser;
 #[command(about = "A simple, efficient static file hosting server written in Rust", long_about = None)]
 pub struct Cli {
     /// Path to configuration file
-    #[arg(short, long, env = "CONFIG", default_value = "statiker.yaml")]
+    #[arg(short, long, env = "CONFIG", default_value = "statiker.yaml", global = true)]
     pub config: String,
+
+    /// Only log the configuration summary at debug level
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also log the full resolved configuration
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Address to listen on (overrides `server.host`)
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Port to listen on (overrides `server.port`)
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// Directory to serve files from (overrides `server.root`)
+    #[arg(short, long)]
+    pub root: Option<PathBuf>,
+
+    /// Directory to serve; without a config file, also enables directory listings
+    #[arg(value_name = "DIR", conflicts_with = "root")]
+    pub path: Option<PathBuf>,
+
+    /// Run the server when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Check the configuration file and exit without starting the server
+    Validate,
+    /// Write a commented configuration file with the default settings
+    Init {
+        /// Overwrite the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-/// Print configuration summary
-pub fn print_config(cfg: &Config) {
-    println!("=== Configuration ===");
-    println!("Server: {}:{}", cfg.server.host, cfg.server.port);
-    println!("Root: {}", cfg.server.root.display());
-    println!("Index: {}", cfg.server.index);
-    println!("Auto-index: {}", cfg.server.auto_index);
+impl Cli {
+    /// Config used when no config file could be read
+    ///
+    /// With a positional directory (`statiker ./public`) that directory is
+    /// served with directory listings, like `python -m http.server`.
+    pub fn fallback_config(&self) -> Config {
+        let mut cfg = Config::default();
+        if let Some(path) = &self.path {
+            cfg.server.root = path.clone();
+            cfg.server.auto_index = true;
+        }
+        cfg
+    }
+
+    /// Apply the command-line overrides on top of the loaded config
+    pub fn apply_overrides(&self, cfg: &mut Config) {
+        if let Some(host) = &self.host {
+            cfg.server.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            cfg.server.port = port;
+        }
+        if let Some(root) = self.root.as_ref().or(self.path.as_ref()) {
+            cfg.server.root = root.clone();
+        }
+    }
+}
+
+/// Human-readable configuration summary, one line per entry
+pub fn config_summary(cfg: &Config) -> Vec<String> {
+    let mut lines = Vec::new();
+    lines.push("=== Configuration ===".to_string());
+    lines.push(format!("Server: {}:{}", cfg.server.host, cfg.server.port));
+    lines.push(format!("Root: {}", cfg.server.root.display()));
+    lines.push(format!("Index: {}", cfg.server.index));
+    lines.push(format!("Auto-index: {}", cfg.server.auto_index));
 
     if cfg.tls.enabled {
-        println!("TLS: enabled");
+        lines.push("TLS: enabled".to_string());
     }
 
     if cfg.routing.is_empty() {
-        println!("Routes: default (serve static at /)");
+        lines.push("Routes: default (serve static at /)".to_string());
     } else {
-        println!("Routes: {}", cfg.routing.len());
+        lines.push(format!("Routes: {}", cfg.routing.len()));
         for route in &cfg.routing {
             if let Some(serve) = &route.serve {
-                println!("  - {} -> serve: {}", route.path, serve);
+                match &route.root {
+                    Some(root) => lines.push(format!(
+                        "  - {} -> serve: {} (root: {})",
+                        route.path,
+                        serve,
+                        root.display()
+                    )),
+                    None => lines.push(format!("  - {} -> serve: {}", route.path, serve)),
+                }
             }
             if route.proxy.is_some() {
-                println!("  - {} -> proxy", route.path);
+                lines.push(format!("  - {} -> proxy", route.path));
             }
         }
     }
 
     if cfg.spa.enabled {
-        println!("SPA: enabled (fallback: {})", cfg.spa.fallback);
+        lines.push(format!("SPA: enabled (fallback: {})", cfg.spa.fallback));
     }
 
     if cfg.compression.enable {
@@ -51,27 +130,83 @@ pub fn print_config(cfg: &Config) {
             methods.push("brotli");
         }
         let methods_str = methods.join(", ");
-        println!("Compression: enabled ({})", methods_str);
+        lines.push(format!("Compression: enabled ({})", methods_str));
     }
 
     if cfg.security.cors.enabled {
-        println!("CORS: enabled");
+        lines.push("CORS: enabled".to_string());
     }
 
     if cfg.security.rate_limit.enabled {
-        println!("Rate limit: {} req/min", cfg.security.rate_limit.requests_per_min);
+        let rl = &cfg.security.rate_limit;
+        let mut line = if rl.period == Duration::from_secs(60) {
+            format!("Rate limit: {} req/min", rl.requests)
+        } else {
+            format!(
+                "Rate limit: {} req/{}",
+                rl.requests,
+                humantime::format_duration(rl.period)
+            )
+        };
+        if let Some(burst) = rl.burst {
+            line.push_str(&format!(" (burst {burst})"));
+        }
+        lines.push(line);
     }
 
     if !cfg.security.headers.is_empty() {
-        println!("Security headers: {} configured", cfg.security.headers.len());
+        lines.push(format!("Security headers: {} configured", cfg.security.headers.len()));
     }
 
     if cfg.assets.cache.enabled {
-        println!("Asset cache: enabled (max-age: {}s)", cfg.assets.cache.max_age.as_secs());
+        lines.push(format!(
+            "Asset cache: enabled (max-age: {}s)",
+            cfg.assets.cache.max_age.as_secs()
+        ));
     }
 
-    println!("Log level: {}", cfg.obs.level);
-    println!("====================");
+    lines.push(format!("Log level: {}", cfg.obs.level));
+    lines.push("====================".to_string());
+    lines
+}
+
+/// How the configuration is reported at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Banner {
+    /// Summary logged at debug level only (`--quiet` or `obs.print_config: false`)
+    Quiet,
+    /// Summary logged at info level (default)
+    Summary,
+    /// Summary plus the full resolved config as YAML (`--verbose`)
+    Full,
+}
+
+impl Banner {
+    pub fn new(cli: &Cli, cfg: &Config) -> Self {
+        if cli.verbose {
+            Banner::Full
+        } else if cli.quiet || !cfg.obs.print_config {
+            Banner::Quiet
+        } else {
+            Banner::Summary
+        }
+    }
+}
+
+/// Log the configuration summary through tracing
+pub fn print_config(cfg: &Config, banner: Banner) {
+    for line in config_summary(cfg) {
+        match banner {
+            Banner::Quiet => debug!("{line}"),
+            Banner::Summary | Banner::Full => info!("{line}"),
+        }
+    }
+    if banner == Banner::Full {
+        match serde_yaml::to_string(cfg) {
+            Ok(yaml) => info!("Resolved configuration:\n{yaml}"),
+            Err(e) => warn!("could not serialize configuration: {e}"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -82,7 +217,32 @@ mod tests {
     fn test_print_config() {
         let cfg = Config::default();
         // Just verify it doesn't panic
-        print_config(&cfg);
+        print_config(&cfg, Banner::Full);
+    }
+
+    #[test]
+    fn test_config_summary() {
+        let lines = config_summary(&Config::default());
+        assert_eq!(lines.first().unwrap(), "=== Configuration ===");
+        assert!(lines.contains(&"Server: 0.0.0.0:8080".to_string()));
+        assert!(lines.contains(&"Routes: default (serve static at /)".to_string()));
+    }
+
+    #[test]
+    fn test_banner_mode() {
+        let mut cfg = Config::default();
+        let cli = Cli::parse_from(vec!["statiker"]);
+        assert_eq!(Banner::new(&cli, &cfg), Banner::Summary);
+        let cli = Cli::parse_from(vec!["statiker", "--quiet"]);
+        assert_eq!(Banner::new(&cli, &cfg), Banner::Quiet);
+        let cli = Cli::parse_from(vec!["statiker", "-v"]);
+        assert_eq!(Banner::new(&cli, &cfg), Banner::Full);
+
+        cfg.obs.print_config = false;
+        let cli = Cli::parse_from(vec!["statiker"]);
+        assert_eq!(Banner::new(&cli, &cfg), Banner::Quiet);
+
+        assert!(Cli::try_parse_from(vec!["statiker", "-q", "-v"]).is_err());
     }
 
     #[test]
@@ -99,6 +259,89 @@ mod tests {
         assert_eq!(cli.config, "custom.yaml");
     }
 
+    #[test]
+    fn test_cli_overrides() {
+        let mut cfg = Config::default();
+        cfg.server.port = 8080;
+        cfg.server.host = "127.0.0.1".into();
+        let cli = Cli::parse_from(vec!["statiker", "--port", "9000", "-r", "public"]);
+        cli.apply_overrides(&mut cfg);
+        assert_eq!(cfg.server.port, 9000);
+        assert_eq!(cfg.server.root, PathBuf::from("public"));
+        // Flags that aren't given keep the config value
+        assert_eq!(cfg.server.host, "127.0.0.1");
+
+        let cli = Cli::parse_from(vec!["statiker", "--host", "::1", "-p", "443"]);
+        cli.apply_overrides(&mut cfg);
+        assert_eq!(cfg.server.host, "::1");
+        assert_eq!(cfg.server.port, 443);
+        assert!(Cli::try_parse_from(vec!["statiker", "--port", "http"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_positional_path() {
+        let cli = Cli::parse_from(vec!["statiker", "./public"]);
+        assert_eq!(cli.path, Some(PathBuf::from("./public")));
+        assert_eq!(cli.command, None);
+        let cli = Cli::parse_from(vec!["statiker", "-p", "9000", "dist"]);
+        assert_eq!(cli.path, Some(PathBuf::from("dist")));
+        assert_eq!(cli.port, Some(9000));
+        assert!(Cli::try_parse_from(vec!["statiker", "--root", "a", "b"]).is_err());
+
+        // Without a config file: serve the directory with listings
+        let cfg = cli.fallback_config();
+        assert_eq!(cfg.server.root, PathBuf::from("dist"));
+        assert!(cfg.server.auto_index);
+
+        // With a config file: only the root is overridden
+        let mut cfg = Config::default();
+        cli.apply_overrides(&mut cfg);
+        assert_eq!(cfg.server.root, PathBuf::from("dist"));
+        assert!(!cfg.server.auto_index);
+    }
+
+    #[tokio::test]
+    async fn test_positional_path_serves_files() {
+        use axum::body::Body;
+        use http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let dir = std::env::temp_dir().join(format!("statiker-cli-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join("docs/readme.txt"), "hello").unwrap();
+
+        let cli = Cli::parse_from(vec!["statiker".into(), dir.clone().into_os_string()]);
+        let cfg = cli.fallback_config();
+        let state = crate::state::test_state(cfg);
+        let app = crate::router::build_router(&state).unwrap();
+
+        let req = Request::get("/docs/readme.txt").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+
+        // Directories without an index get a listing
+        let req = Request::get("/docs/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("readme.txt"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cli_parse_validate() {
+        let cli = Cli::parse_from(vec!["statiker"]);
+        assert_eq!(cli.command, None);
+        let cli = Cli::parse_from(vec!["statiker", "validate", "-c", "ci.yaml"]);
+        assert_eq!(cli.command, Some(Command::Validate));
+        assert_eq!(cli.config, "ci.yaml");
+        let cli = Cli::parse_from(vec!["statiker", "init", "--force"]);
+        assert_eq!(cli.command, Some(Command::Init { force: true }));
+    }
+
     #[test]
     fn test_cli_parse_short_flag() {
         // Test short flag
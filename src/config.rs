@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use ipnet::IpNet;
+use std::{collections::HashMap, net::IpAddr, path::PathBuf, time::Duration};
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Config {
@@ -10,6 +11,8 @@ pub struct Config {
     #[serde(default)]
     pub routing: Vec<Route>,
     #[serde(default)]
+    pub proxy_client: ProxyClient,
+    #[serde(default)]
     pub spa: Spa,
     #[serde(default)]
     pub assets: Assets,
@@ -21,14 +24,254 @@ pub struct Config {
     pub obs: Obs,
 }
 
+impl Config {
+    /// Parse a config file's text, expanding environment variables first
+    pub fn from_yaml(text: &str) -> anyhow::Result<Config> {
+        let expanded = expand_env(text)?;
+        Ok(serde_yaml::from_str(&expanded)?)
+    }
+
+    /// Apply the reloadable sections of `new` on top of `self`
+    ///
+    /// `security.headers`, `assets.cache` and the `obs` log levels take effect
+    /// immediately. The returned flag is set when `new` also changes anything
+    /// else; those sections keep their current values until a restart.
+    pub fn reload_from(&self, new: &Config) -> (Config, bool) {
+        let mut merged = self.clone();
+        merged.security.headers = new.security.headers.clone();
+        merged.assets.cache = new.assets.cache.clone();
+        merged.obs.level = new.obs.level.clone();
+        merged.obs.filters = new.obs.filters.clone();
+        let ignored = serde_json::to_value(&merged).ok() != serde_json::to_value(new).ok();
+        (merged, ignored)
+    }
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` against the process environment
+///
+/// The default applies when the variable is unset or empty. `$$` produces a
+/// literal `$`; a `$` not followed by `{` or `$` is kept as-is. Referencing an
+/// unset variable without a default is an error, so typos don't silently
+/// turn into empty values. YAML comments are copied unchanged, so a
+/// commented-out `${VAR}` doesn't need to be set.
+pub fn expand_env(raw: &str) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(raw.len());
+    for line in raw.split_inclusive('\n') {
+        let (text, comment) = line.split_at(comment_start(line).unwrap_or(line.len()));
+        expand_env_line(text, &mut out)?;
+        out.push_str(comment);
+    }
+    Ok(out)
+}
+
+/// Byte offset of the `#` starting a YAML comment on `line`, if any
+///
+/// A `#` only starts a comment at the beginning of the line or after
+/// whitespace, and not inside a quoted scalar.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut prev = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some('"'), '\\') => {
+                chars.next();
+            }
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            // Quotes only open a scalar, e.g. not the one in `it's`
+            (None, '"' | '\'') if matches!(prev, None | Some(' ' | '\t' | '[' | '{' | ',')) => {
+                quote = Some(c);
+            }
+            (None, '#') if prev.is_none_or(char::is_whitespace) => return Some(i),
+            (None, _) => {}
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+fn expand_env_line(line: &str, out: &mut String) -> anyhow::Result<()> {
+    let mut rest = line;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("unterminated '${{' in config"))?;
+            let expr = &after[..end];
+            let (name, default) = match expr.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expr, None),
+            };
+            let valid = !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                anyhow::bail!("invalid environment variable reference '${{{expr}}}' in config");
+            }
+            match (std::env::var(name).ok().filter(|v| !v.is_empty()), default) {
+                (Some(value), _) => out.push_str(&value),
+                (None, Some(default)) => out.push_str(default),
+                (None, None) => anyhow::bail!(
+                    "environment variable '{name}' referenced in config is not set"
+                ),
+            }
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(())
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Server {
     pub host: String,
     pub port: u16,
     pub root: PathBuf,
-    pub index: String,
+    pub index: IndexFiles,
     #[serde(default)]
     pub auto_index: bool,
+    /// Always render the listing, even if the index file exists (requires `auto_index`)
+    #[serde(default)]
+    pub force_auto_index: bool,
+    /// Methods accepted by static routes; only GET, HEAD and OPTIONS are implemented
+    #[serde(default = "Server::default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Message shown in directory listings for empty directories
+    #[serde(default = "Server::default_index_empty_message")]
+    pub index_empty_message: String,
+    /// HTML file with `{{title}}` and `{{entries}}` placeholders for directory listings
+    #[serde(default)]
+    pub listing_template: Option<PathBuf>,
+    /// Alternate files served to matching User-Agents (e.g. crawlers)
+    #[serde(default)]
+    pub ua_rules: Vec<UaRule>,
+    /// How request paths are checked for directory traversal
+    #[serde(default)]
+    pub traversal_mode: TraversalMode,
+    /// Serve symlinks that resolve to a location outside `root`
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// How requests for dotfiles such as `.env` or `.git/` are answered
+    #[serde(default)]
+    pub dotfiles: Dotfiles,
+    /// Expand `<!--#include file="..." -->` directives in HTML files
+    #[serde(default)]
+    pub ssi: bool,
+    /// Extra headers added to auto-generated directory listings only
+    #[serde(default)]
+    pub index_headers: HashMap<String, String>,
+    /// Page served at `/` when the root has no index file and auto-index is off
+    #[serde(default)]
+    pub empty_root_page: Option<PathBuf>,
+    /// Client Hints requested via `Accept-CH` on HTML responses, e.g. "DPR"
+    #[serde(default)]
+    pub client_hints: Vec<String>,
+    /// Maximum number of static file reads in flight at once (unlimited if unset)
+    #[serde(default)]
+    pub max_concurrent_reads: Option<usize>,
+    /// Locations tried in order when a requested file is missing
+    #[serde(default)]
+    pub fallback_paths: Vec<String>,
+    /// `Retry-After` added to 503 responses that lack one (zero disables)
+    #[serde(default, with = "humantime_serde")]
+    pub default_retry_after: Duration,
+    /// Allow `?download=tar.gz|zip` on directories to download them as an archive
+    #[serde(default)]
+    pub archive_download: bool,
+    /// Sibling file suffixes served to clients accepting a media type, e.g. `image/webp: .webp`
+    #[serde(default)]
+    pub accept_variants: HashMap<String, String>,
+    /// Content types by file extension, taking precedence over the built-in guess
+    #[serde(default)]
+    pub mime_overrides: HashMap<String, String>,
+    /// Content type for files whose extension is unknown or missing
+    #[serde(default = "Server::default_mime")]
+    pub default_mime: String,
+    /// HTML pages (relative to `root`) served for 404 and 5xx responses
+    #[serde(default)]
+    pub error_pages: HashMap<u16, String>,
+    /// Order of entries in directory listings (directories always come first)
+    #[serde(default)]
+    pub listing_sort: ListingSort,
+    /// How long to wait for in-flight requests on SIGTERM/SIGINT before exiting
+    #[serde(default = "Server::default_shutdown_timeout", with = "humantime_serde")]
+    pub shutdown_timeout: Duration,
+    /// Largest request body accepted by any route; larger ones get 413 (0 = no limit)
+    #[serde(default = "Server::default_max_body_size")]
+    pub max_body_size: ByteSize,
+    /// Peers whose `X-Forwarded-For` is honored (empty = `security.forwarded_hops`)
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpRange>,
+}
+
+/// Directory traversal defense for static file paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TraversalMode {
+    /// Reject any `..`, `.` or absolute component before touching the filesystem
+    #[default]
+    Strict,
+    /// Resolve the final path (including symlinks) and require it to stay under the root
+    Canonical,
+}
+
+/// Handling of paths with a segment starting with `.` (`.well-known` excepted)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Dotfiles {
+    /// Pretend they don't exist (404) and hide them from listings
+    #[default]
+    Ignore,
+    /// Answer 403 and hide them from listings
+    Deny,
+    /// Serve and list them like any other file
+    Allow,
+}
+
+/// Sort order for directory listings; `_desc` variants reverse it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingSort {
+    /// Case-insensitive name
+    #[default]
+    Name,
+    NameDesc,
+    /// File size
+    Size,
+    SizeDesc,
+    /// Last modification time
+    Modified,
+    ModifiedDesc,
+}
+
+impl Server {
+    fn default_allowed_methods() -> Vec<String> {
+        vec!["GET".into(), "HEAD".into()]
+    }
+
+    fn default_index_empty_message() -> String {
+        "This directory is empty.".into()
+    }
+
+    fn default_shutdown_timeout() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn default_mime() -> String {
+        "application/octet-stream".into()
+    }
+
+    fn default_max_body_size() -> ByteSize {
+        ByteSize(2 << 20)
+    }
 }
 
 impl Default for Server {
@@ -39,15 +282,81 @@ impl Default for Server {
             root: PathBuf::from("."),
             index: "index.html".into(),
             auto_index: false,
+            force_auto_index: false,
+            allowed_methods: Server::default_allowed_methods(),
+            index_empty_message: Server::default_index_empty_message(),
+            listing_template: None,
+            ua_rules: Vec::new(),
+            traversal_mode: TraversalMode::Strict,
+            follow_symlinks: false,
+            dotfiles: Dotfiles::default(),
+            ssi: false,
+            index_headers: HashMap::new(),
+            empty_root_page: None,
+            client_hints: Vec::new(),
+            max_concurrent_reads: None,
+            fallback_paths: Vec::new(),
+            default_retry_after: Duration::ZERO,
+            archive_download: false,
+            accept_variants: HashMap::new(),
+            mime_overrides: HashMap::new(),
+            default_mime: Server::default_mime(),
+            trusted_proxies: Vec::new(),
+            error_pages: HashMap::new(),
+            listing_sort: ListingSort::Name,
+            shutdown_timeout: Server::default_shutdown_timeout(),
+            max_body_size: Server::default_max_body_size(),
         }
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UaRule {
+    /// Case-insensitive substring of the `User-Agent` header
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// File to serve instead, relative to the root
+    pub serve: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Tls {
     pub enabled: bool,
+    #[serde(default)]
     pub cert_path: PathBuf,
+    #[serde(default)]
     pub key_path: PathBuf,
+    /// Serve a generated certificate while the cert/key files are missing
+    #[serde(default)]
+    pub self_signed: bool,
+    /// Directory served over plain HTTP alongside the TLS listener
+    #[serde(default)]
+    pub http_root: Option<PathBuf>,
+    /// Port of the `http_root` listener (`redirect_http.from_port` wins if set)
+    #[serde(default = "Tls::default_http_port")]
+    pub http_port: u16,
+    /// Plain HTTP listener that redirects to the TLS listener
+    #[serde(default)]
+    pub redirect_http: Option<RedirectHttp>,
+    /// How often to check the cert/key files for changes (zero disables)
+    #[serde(default, with = "humantime_serde")]
+    pub reload_interval: Duration,
+    /// Offer HTTP/2 via ALPN next to HTTP/1.1
+    #[serde(default = "Tls::default_http2")]
+    pub http2: bool,
+    /// Client certificate authentication (mutual TLS)
+    #[serde(default)]
+    pub client_auth: ClientAuth,
+}
+
+impl Tls {
+    fn default_http2() -> bool {
+        true
+    }
+
+    fn default_http_port() -> u16 {
+        80
+    }
 }
 
 impl Default for Tls {
@@ -56,6 +365,55 @@ impl Default for Tls {
             enabled: false,
             cert_path: PathBuf::new(),
             key_path: PathBuf::new(),
+            self_signed: false,
+            http_root: None,
+            http_port: Self::default_http_port(),
+            redirect_http: None,
+            reload_interval: Duration::ZERO,
+            http2: Self::default_http2(),
+            client_auth: ClientAuth::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ClientAuth {
+    #[serde(default)]
+    pub enabled: bool,
+    /// PEM bundle of the CAs client certificates must chain up to
+    #[serde(default)]
+    pub ca_path: PathBuf,
+    #[serde(default)]
+    pub mode: ClientAuthMode,
+}
+
+/// Whether clients must present a certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMode {
+    /// Refuse the handshake without a valid client certificate
+    #[default]
+    Require,
+    /// Accept clients without a certificate; one that is sent must be valid
+    Optional,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RedirectHttp {
+    #[serde(default = "RedirectHttp::default_from_port")]
+    pub from_port: u16,
+}
+
+impl RedirectHttp {
+    fn default_from_port() -> u16 {
+        80
+    }
+}
+
+impl Default for RedirectHttp {
+    fn default() -> Self {
+        Self {
+            from_port: RedirectHttp::default_from_port(),
         }
     }
 }
@@ -65,6 +423,15 @@ pub struct Route {
     pub path: String,
     #[serde(default)]
     pub serve: Option<String>,
+    /// Directory a static route serves from instead of `server.root`
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    /// Overrides `server.auto_index` for a static route
+    #[serde(default)]
+    pub auto_index: Option<bool>,
+    /// Send files of a static route as downloads (`Content-Disposition: attachment`)
+    #[serde(default)]
+    pub download: bool,
     #[serde(default)]
     pub proxy: Option<Proxy>,
 }
@@ -74,6 +441,9 @@ impl Default for Route {
         Self {
             path: "/".into(),
             serve: None,
+            root: None,
+            auto_index: None,
+            download: false,
             proxy: None,
         }
     }
@@ -81,19 +451,367 @@ impl Default for Route {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Proxy {
+    #[serde(default)]
     pub url: String,
+    /// Additional upstreams; requests are spread over `url` and these
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// How an upstream is picked when several are configured
+    #[serde(default)]
+    pub strategy: LbStrategy,
+    /// How long to wait for the upstream's response head
     #[serde(default, with = "humantime_serde")]
     pub timeout: Duration,
+    /// How long connecting may take; overrides `proxy_client.connect_timeout`
+    #[serde(default, with = "humantime_serde")]
+    pub connect_timeout: Option<Duration>,
+    #[serde(default)]
+    pub add_headers: AddHeaders,
+    /// Local files served in place of specific upstream error statuses
+    #[serde(default)]
+    pub error_overrides: HashMap<u16, ErrorOverride>,
+    /// Interval for TCP keepalive probes on upstream connections
+    #[serde(default, with = "humantime_serde")]
+    pub tcp_keepalive: Option<Duration>,
+    /// Add `Secure` to upstream `Set-Cookie` headers when serving over TLS
+    #[serde(default)]
+    pub force_secure_cookies: bool,
+    /// Tunnel `Upgrade: websocket` requests to the upstream
+    #[serde(default = "Proxy::default_websocket")]
+    pub websocket: bool,
+    /// Add `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+    #[serde(default = "Proxy::default_forward_headers")]
+    pub forward_headers: bool,
+    /// Extra attempts after a connection error or a 502/503/504 from upstream
+    #[serde(default)]
+    pub retries: u32,
+    /// Delay between retry attempts
+    #[serde(default, with = "humantime_serde")]
+    pub retry_backoff: Duration,
+    /// Also retry methods other than GET and HEAD (bodiless requests only)
     #[serde(default)]
-    pub add_headers: HashMap<String, String>,
+    pub retry_non_idempotent: bool,
+    /// Consecutive failures after which an upstream is ejected (0 = never)
+    #[serde(default)]
+    pub max_failures: u32,
+    /// How long an ejected upstream is skipped (0 = 30s)
+    #[serde(default, with = "humantime_serde")]
+    pub eject_duration: Duration,
+    /// Drop the mount path from the upstream request path
+    #[serde(default = "Proxy::default_strip_prefix")]
+    pub strip_prefix: bool,
+    /// Regex rewrite of the upstream request path
+    #[serde(default)]
+    pub rewrite: Option<Rewrite>,
+    /// Point `Location` headers aimed at the upstream back at this route
+    #[serde(default = "Proxy::default_rewrite_redirects")]
+    pub rewrite_redirects: bool,
+    /// Largest request body forwarded upstream; larger ones get 413
+    #[serde(default)]
+    pub max_body_size: Option<ByteSize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Rewrite {
+    /// Regex matched against the path (without query)
+    pub from: String,
+    /// Replacement for the first match; supports `$1`-style captures
+    pub to: String,
+}
+
+impl Proxy {
+    fn default_websocket() -> bool {
+        true
+    }
+
+    fn default_forward_headers() -> bool {
+        true
+    }
+
+    fn default_strip_prefix() -> bool {
+        true
+    }
+
+    fn default_rewrite_redirects() -> bool {
+        true
+    }
 }
 
 impl Default for Proxy {
     fn default() -> Self {
         Self {
             url: String::new(),
+            urls: Vec::new(),
+            strategy: LbStrategy::RoundRobin,
             timeout: Duration::ZERO,
-            add_headers: HashMap::new(),
+            connect_timeout: None,
+            add_headers: AddHeaders::default(),
+            error_overrides: HashMap::new(),
+            tcp_keepalive: None,
+            force_secure_cookies: false,
+            websocket: Proxy::default_websocket(),
+            forward_headers: Proxy::default_forward_headers(),
+            retries: 0,
+            retry_backoff: Duration::ZERO,
+            retry_non_idempotent: false,
+            max_failures: 0,
+            eject_duration: Duration::ZERO,
+            strip_prefix: Proxy::default_strip_prefix(),
+            rewrite: None,
+            rewrite_redirects: Proxy::default_rewrite_redirects(),
+            max_body_size: None,
+        }
+    }
+}
+
+/// Connection settings shared by all proxy routes and `forward_auth`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ProxyClient {
+    /// Offer HTTP/2 to TLS upstreams via ALPN
+    #[serde(default = "ProxyClient::default_http2")]
+    pub http2: bool,
+    /// Idle connections kept open per upstream host (unset = no limit)
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept for reuse (zero keeps them open)
+    #[serde(
+        default = "ProxyClient::default_pool_idle_timeout",
+        with = "humantime_serde"
+    )]
+    pub pool_idle_timeout: Duration,
+    /// Limit for establishing an upstream connection (zero = OS default)
+    #[serde(default, with = "humantime_serde")]
+    pub connect_timeout: Duration,
+}
+
+impl ProxyClient {
+    fn default_http2() -> bool {
+        true
+    }
+
+    fn default_pool_idle_timeout() -> Duration {
+        Duration::from_secs(90)
+    }
+}
+
+impl Default for ProxyClient {
+    fn default() -> Self {
+        Self {
+            http2: Self::default_http2(),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: Self::default_pool_idle_timeout(),
+            connect_timeout: Duration::ZERO,
+        }
+    }
+}
+
+/// Index file names for directory requests, tried in order
+///
+/// Written as a single name (`index.html`) or a list (`[index.html, index.htm]`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "IndexFilesRepr", into = "IndexFilesRepr")]
+pub struct IndexFiles(pub Vec<String>);
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum IndexFilesRepr {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<IndexFilesRepr> for IndexFiles {
+    fn from(repr: IndexFilesRepr) -> Self {
+        match repr {
+            IndexFilesRepr::One(name) => IndexFiles(vec![name]),
+            IndexFilesRepr::Many(names) => IndexFiles(names),
+        }
+    }
+}
+
+impl From<IndexFiles> for IndexFilesRepr {
+    fn from(index: IndexFiles) -> Self {
+        match <[String; 1]>::try_from(index.0) {
+            Ok([name]) => IndexFilesRepr::One(name),
+            Err(names) => IndexFilesRepr::Many(names),
+        }
+    }
+}
+
+impl From<&str> for IndexFiles {
+    fn from(name: &str) -> Self {
+        IndexFiles(vec![name.to_string()])
+    }
+}
+
+impl IndexFiles {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl std::fmt::Display for IndexFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join(", "))
+    }
+}
+
+/// A size in bytes, written as a number or with a unit: `512`, `64KiB`, `2MB`
+///
+/// `KB`, `MB` and `GB` are powers of 1000, `KiB`, `MiB` and `GiB` powers of
+/// 1024; units are case-insensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "ByteSizeRepr", into = "String")]
+pub struct ByteSize(pub u64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ByteSizeRepr {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<ByteSizeRepr> for ByteSize {
+    type Error = String;
+
+    fn try_from(repr: ByteSizeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ByteSizeRepr::Bytes(n) => Ok(ByteSize(n)),
+            ByteSizeRepr::Text(s) => s.parse(),
+        }
+    }
+}
+
+impl std::str::FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid size '{s}', expected e.g. 512, 64KiB or 2MB"))?;
+        let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "kb" => 1000,
+            "kib" => 1 << 10,
+            "mb" => 1000 * 1000,
+            "mib" => 1 << 20,
+            "gb" => 1000 * 1000 * 1000,
+            "gib" => 1 << 30,
+            other => return Err(format!("unknown size unit '{other}' in '{s}'")),
+        };
+        number
+            .checked_mul(factor)
+            .map(ByteSize)
+            .ok_or_else(|| format!("size '{s}' is too large"))
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let units = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+        match units.iter().find(|(n, _)| self.0 >= *n && self.0.is_multiple_of(*n)) {
+            Some((n, unit)) => write!(f, "{}{unit}", self.0 / n),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl From<ByteSize> for String {
+    fn from(size: ByteSize) -> Self {
+        size.to_string()
+    }
+}
+
+/// An IP range in CIDR notation (`10.0.0.0/8`, `2001:db8::/32`) or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange(pub IpNet);
+
+impl IpRange {
+    /// Whether `ip` is in the range; IPv4-mapped IPv6 addresses match as IPv4
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.contains(&ip.to_canonical())
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::str::FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.parse::<IpNet>()
+            .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+            .map(IpRange)
+            .map_err(|_| format!("invalid IP range '{s}', expected e.g. 10.0.0.0/8 or ::1"))
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.0.to_string()
+    }
+}
+
+/// Upstream selection for proxies with several upstreams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LbStrategy {
+    #[default]
+    RoundRobin,
+    Random,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ErrorOverride {
+    pub file: PathBuf,
+    /// Status to respond with; defaults to the upstream status
+    #[serde(default)]
+    pub status: Option<u16>,
+}
+
+/// Headers added to proxied requests, either as a map or as an ordered list
+///
+/// The list form (`- [name, value]`) preserves order and allows repeating a
+/// header name; the map form is applied in name order.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AddHeaders {
+    Map(HashMap<String, String>),
+    List(Vec<(String, String)>),
+}
+
+impl Default for AddHeaders {
+    fn default() -> Self {
+        AddHeaders::Map(HashMap::new())
+    }
+}
+
+impl From<HashMap<String, String>> for AddHeaders {
+    fn from(map: HashMap<String, String>) -> Self {
+        AddHeaders::Map(map)
+    }
+}
+
+impl AddHeaders {
+    /// Flatten into an ordered list of (name, value) pairs
+    pub fn into_entries(self) -> Vec<(String, String)> {
+        match self {
+            AddHeaders::Map(map) => {
+                let mut entries: Vec<_> = map.into_iter().collect();
+                entries.sort();
+                entries
+            }
+            AddHeaders::List(list) => list,
         }
     }
 }
@@ -117,6 +835,70 @@ impl Default for Spa {
 pub struct Assets {
     #[serde(default)]
     pub cache: Cache,
+    /// Assets announced via `Link: rel=preload` headers on HTML responses
+    #[serde(default)]
+    pub preload: Vec<Preload>,
+    /// Serve `.br`/`.gz` siblings of static files based on Accept-Encoding
+    #[serde(default)]
+    pub precompressed: bool,
+    /// File extensions that get `assets.cache` headers; replaces the built-in list if set
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Per-path `Cache-Control` values; the first matching rule wins over `cache`
+    #[serde(default)]
+    pub rules: Vec<CacheRule>,
+    /// Keep small, frequently served files in memory
+    #[serde(default)]
+    pub memcache: MemCache,
+}
+
+/// In-memory LRU cache for static files, checked against mtime and size
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MemCache {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Budget for cached file contents; least recently used files are evicted beyond it
+    #[serde(default = "MemCache::default_max_bytes")]
+    pub max_bytes: ByteSize,
+    /// Larger files are always read from disk
+    #[serde(default = "MemCache::default_max_file_size")]
+    pub max_file_size: ByteSize,
+}
+
+impl MemCache {
+    fn default_max_bytes() -> ByteSize {
+        ByteSize(64 << 20)
+    }
+
+    fn default_max_file_size() -> ByteSize {
+        ByteSize(1 << 20)
+    }
+}
+
+impl Default for MemCache {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: MemCache::default_max_bytes(),
+            max_file_size: MemCache::default_max_file_size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheRule {
+    /// Glob matched against the request path, e.g. `/static/**`
+    pub pattern: String,
+    /// `Cache-Control` header value, e.g. `no-cache`
+    pub cache_control: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Preload {
+    pub path: String,
+    /// Destination type, e.g. "style", "script", "font"
+    #[serde(rename = "as")]
+    pub kind: String,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -125,7 +907,7 @@ pub struct Cache {
     #[serde(default, with = "humantime_serde")]
     pub max_age: Duration,
     #[serde(default)]
-    pub etag: bool, // NOTE: not computed in this MVP (toggle ignored if false)
+    pub etag: bool, // weak ETag from size + mtime on static files
 }
 
 impl Default for Cache {
@@ -143,6 +925,9 @@ pub struct Compression {
     pub enable: bool,
     pub gzip: bool,
     pub br: bool,
+    /// Trade-off between CPU time and response size
+    #[serde(default)]
+    pub level: CompressionLevel,
 }
 
 impl Default for Compression {
@@ -151,11 +936,37 @@ impl Default for Compression {
             enable: false,
             gzip: true,
             br: true,
+            level: CompressionLevel::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Compression quality: a preset or an algorithm-specific number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CompressionLevel {
+    Preset(CompressionPreset),
+    /// Brotli 0-11, gzip 0-9; values above an algorithm's maximum are clamped
+    Quality(u8),
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        CompressionLevel::Preset(CompressionPreset::Default)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionPreset {
+    Fastest,
+    /// Each algorithm's own default
+    #[default]
+    Default,
+    Best,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Security {
     #[serde(default)]
     pub cors: Cors,
@@ -163,18 +974,93 @@ pub struct Security {
     pub rate_limit: RateLimit,
     #[serde(default)]
     pub headers: HashMap<String, String>,
+    /// Request paths to refuse, matched as regular expressions
+    #[serde(default)]
+    pub deny_paths: Vec<DenyPath>,
+    /// Number of trusted reverse proxies in front of statiker (0 = ignore X-Forwarded-For)
+    #[serde(default)]
+    pub forwarded_hops: usize,
+    /// Delegate authentication to an external service for every request
+    #[serde(default)]
+    pub forward_auth: Option<ForwardAuth>,
+    /// Maximum requests in flight per client IP (unlimited if unset)
+    #[serde(default)]
+    pub per_ip_concurrency: Option<usize>,
+    /// HTTP Basic authentication
+    #[serde(default)]
+    pub basic_auth: BasicAuth,
+    /// Client IP allowlist / denylist
+    #[serde(default)]
+    pub ip_filter: IpFilter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct IpFilter {
+    /// IPs or CIDR ranges allowed in; empty allows every client not denied
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// IPs or CIDR ranges refused, checked before `allow`
+    #[serde(default)]
+    pub deny: Vec<String>,
 }
 
-impl Default for Security {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BasicAuth {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "BasicAuth::default_realm")]
+    pub realm: String,
+    /// Username -> bcrypt hash (`$2b$...`) or plaintext password
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+    /// Path prefixes to protect; empty protects every path
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+impl BasicAuth {
+    fn default_realm() -> String {
+        "statiker".into()
+    }
+}
+
+impl Default for BasicAuth {
     fn default() -> Self {
         Self {
-            cors: Cors::default(),
-            rate_limit: RateLimit::default(),
-            headers: HashMap::new(),
+            enabled: false,
+            realm: BasicAuth::default_realm(),
+            users: HashMap::new(),
+            paths: Vec::new(),
         }
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct ForwardAuth {
+    /// Auth endpoint receiving a GET subrequest with the original headers
+    pub url: String,
+    /// Auth response headers copied onto the request when access is granted
+    #[serde(default)]
+    pub copy_headers: Vec<String>,
+    /// Path prefixes to protect; empty protects every path
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DenyPath {
+    pub pattern: String,
+    /// Response status: 404 hides the path's existence, 403 signals refusal
+    #[serde(default = "DenyPath::default_status")]
+    pub status: u16,
+}
+
+impl DenyPath {
+    fn default_status() -> u16 {
+        404
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Cors {
     pub enabled: bool,
@@ -182,6 +1068,18 @@ pub struct Cors {
     pub allowed_origins: Vec<String>,
     #[serde(default)]
     pub allowed_methods: Vec<String>,
+    /// Method-specific overrides, keyed by HTTP method (e.g. "POST")
+    #[serde(default)]
+    pub methods: HashMap<String, CorsMethod>,
+    /// How long browsers may cache preflight responses (zero omits the header)
+    #[serde(default = "Cors::default_max_age", with = "humantime_serde")]
+    pub max_age: Duration,
+}
+
+impl Cors {
+    fn default_max_age() -> Duration {
+        Duration::from_secs(3600)
+    }
 }
 
 impl Default for Cors {
@@ -190,21 +1088,70 @@ impl Default for Cors {
             enabled: false,
             allowed_origins: Vec::new(),
             allowed_methods: Vec::new(),
+            methods: HashMap::new(),
+            max_age: Self::default_max_age(),
         }
     }
 }
 
+/// CORS policy for a single method; empty lists inherit the global policy
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct CorsMethod {
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RateLimit {
     pub enabled: bool,
-    pub requests_per_min: u32,
+    /// Requests allowed per `period` (per minute by default); also accepted
+    /// under its old name `requests_per_min`
+    #[serde(alias = "requests_per_min")]
+    pub requests: u32,
+    /// Window `requests` applies to (default: 1 minute)
+    #[serde(default = "RateLimit::default_period", with = "humantime_serde")]
+    pub period: Duration,
+    /// Requests a client may send at once before being throttled (default: `requests`)
+    #[serde(default)]
+    pub burst: Option<u32>,
+    /// How often stale per-IP limiter state is pruned (0 = default of 60s)
+    #[serde(default, with = "humantime_serde")]
+    pub gc_interval: Duration,
+    /// How to treat clients whose IP cannot be determined
+    #[serde(default)]
+    pub fallback: RateLimitFallback,
+}
+
+/// Rate limiting policy for requests without an identifiable client IP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitFallback {
+    /// Let the request through without rate limiting
+    Allow,
+    /// Reject the request with 403 Forbidden
+    Deny,
+    /// Track all unknown clients in a single shared bucket (0.0.0.0)
+    #[default]
+    Shared,
+}
+
+impl RateLimit {
+    fn default_period() -> Duration {
+        Duration::from_secs(60)
+    }
 }
 
 impl Default for RateLimit {
     fn default() -> Self {
         Self {
             enabled: false,
-            requests_per_min: 60,
+            requests: 60,
+            period: RateLimit::default_period(),
+            burst: None,
+            gc_interval: Duration::from_secs(60),
+            fallback: RateLimitFallback::Shared,
         }
     }
 }
@@ -212,12 +1159,224 @@ impl Default for RateLimit {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Obs {
     pub level: String, // "info", "debug", ...
+    /// Log line format; changes require a restart
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Per-module log levels, e.g. `statiker::proxy: warn`
+    #[serde(default)]
+    pub filters: HashMap<String, String>,
+    /// Record the route that handled each request in its tracing span
+    #[serde(default)]
+    pub log_route: bool,
+    /// Log the configuration summary at startup (at debug level when false)
+    #[serde(default = "Obs::default_print_config")]
+    pub print_config: bool,
+    /// Liveness/readiness endpoint that never touches the filesystem
+    #[serde(default)]
+    pub health: Health,
+    /// Apache-style access log, one line per request
+    #[serde(default)]
+    pub access_log: AccessLog,
+    /// Tag each request with an `X-Request-Id`, generated when the client sends none
+    #[serde(default)]
+    pub request_id: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Compact human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct AccessLog {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+    /// File to append to; stdout when unset
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    /// Common Log Format
+    #[default]
+    Common,
+    /// Common Log Format plus referer and user agent
+    Combined,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Health {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "Health::default_path")]
+    pub path: String,
+}
+
+impl Health {
+    fn default_path() -> String {
+        "/healthz".into()
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: Health::default_path(),
+        }
+    }
+}
+
+impl Obs {
+    fn default_print_config() -> bool {
+        true
+    }
 }
 
 impl Default for Obs {
     fn default() -> Self {
         Self {
             level: "info".into(),
+            log_format: LogFormat::default(),
+            filters: HashMap::new(),
+            log_route: false,
+            print_config: Obs::default_print_config(),
+            health: Health::default(),
+            access_log: AccessLog::default(),
+            request_id: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_env_substitutes() {
+        std::env::set_var("STATIKER_TEST_PORT", "9090");
+        assert_eq!(expand_env("port: ${STATIKER_TEST_PORT}").unwrap(), "port: 9090");
+        let yaml = "server:\n  host: 0.0.0.0\n  port: ${STATIKER_TEST_PORT}\n  \
+                    root: .\n  index: index.html\n";
+        let cfg = Config::from_yaml(yaml).unwrap();
+        assert_eq!(cfg.server.port, 9090);
+    }
+
+    #[test]
+    fn test_expand_env_default() {
+        std::env::remove_var("STATIKER_TEST_UNSET");
+        std::env::set_var("STATIKER_TEST_EMPTY", "");
+        assert_eq!(
+            expand_env("url: ${STATIKER_TEST_UNSET:-http://localhost:3000}").unwrap(),
+            "url: http://localhost:3000"
+        );
+        assert_eq!(expand_env("${STATIKER_TEST_EMPTY:-x}").unwrap(), "x");
+        assert_eq!(expand_env("${STATIKER_TEST_UNSET:-}").unwrap(), "");
+    }
+
+    #[test]
+    fn test_index_files() {
+        let server = |index: &str| {
+            let yaml = format!("host: 0.0.0.0\nport: 80\nroot: .\nindex: {index}\n");
+            serde_yaml::from_str::<Server>(&yaml).unwrap().index
+        };
+        assert_eq!(server("index.html"), IndexFiles::from("index.html"));
+        let list = server("[index.html, index.htm]");
+        assert_eq!(list.iter().collect::<Vec<_>>(), ["index.html", "index.htm"]);
+        // A single name is written back as a plain string
+        assert_eq!(serde_yaml::to_string(&IndexFiles::from("index.html")).unwrap(), "index.html\n");
+        assert_eq!(serde_yaml::to_string(&list).unwrap(), "- index.html\n- index.htm\n");
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("192.0.2.1".parse().unwrap()));
+        let single: IpRange = " ::1 ".parse().unwrap();
+        assert_eq!(String::from(single), "::1/128");
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+
+        let server: Server =
+            serde_yaml::from_str("host: 0.0.0.0\nport: 80\nroot: .\nindex: index.html\n\
+                                  trusted_proxies: [10.0.0.0/8, 127.0.0.1]\n")
+                .unwrap();
+        assert_eq!(server.trusted_proxies.len(), 2);
+        let err = serde_yaml::from_str::<Server>(
+            "host: 0.0.0.0\nport: 80\nroot: .\nindex: index.html\ntrusted_proxies: [nope]\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid IP range 'nope'"), "{err}");
+    }
+
+    #[test]
+    fn test_byte_size() {
+        let parse = |s: &str| s.parse::<ByteSize>().map(|b| b.0);
+        assert_eq!(parse("512"), Ok(512));
+        assert_eq!(parse("64KiB"), Ok(64 * 1024));
+        assert_eq!(parse("2 MB"), Ok(2_000_000));
+        assert_eq!(parse("1gib"), Ok(1 << 30));
+        assert!(parse("1.5MB").is_err());
+        assert!(parse("10 parsecs").is_err());
+        assert!(parse("99999999999GiB").is_err());
+
+        let proxy: Proxy = serde_yaml::from_str("max_body_size: 10MiB").unwrap();
+        assert_eq!(proxy.max_body_size, Some(ByteSize(10 << 20)));
+        let proxy: Proxy = serde_yaml::from_str("max_body_size: 1000").unwrap();
+        assert_eq!(proxy.max_body_size, Some(ByteSize(1000)));
+        assert_eq!(ByteSize(10 << 20).to_string(), "10MiB");
+        assert_eq!(ByteSize(1000).to_string(), "1000");
+    }
+
+    #[test]
+    fn test_rate_limit_requests_alias() {
+        let rl: RateLimit = serde_yaml::from_str("enabled: true\nrequests: 5").unwrap();
+        assert_eq!(rl.requests, 5);
+        let rl: RateLimit = serde_yaml::from_str("enabled: true\nrequests_per_min: 7").unwrap();
+        assert_eq!(rl.requests, 7);
+        assert!(serde_yaml::to_string(&rl).unwrap().contains("requests: 7"));
+    }
+
+    #[test]
+    fn test_expand_env_escape() {
+        assert_eq!(expand_env("price: $$5 and $${HOME}").unwrap(), "price: $5 and ${HOME}");
+        assert_eq!(expand_env("cost: $5").unwrap(), "cost: $5");
+    }
+
+    #[test]
+    fn test_expand_env_errors() {
+        std::env::remove_var("STATIKER_TEST_MISSING");
+        let err = expand_env("${STATIKER_TEST_MISSING}").unwrap_err();
+        assert!(err.to_string().contains("STATIKER_TEST_MISSING"), "{err}");
+        assert!(expand_env("${UNTERMINATED").is_err());
+        assert!(expand_env("${}").is_err());
+        assert!(expand_env("${NOT-A-NAME}").is_err());
+    }
+
+    #[test]
+    fn test_expand_env_skips_comments() {
+        std::env::remove_var("STATIKER_TEST_COMMENTED");
+        std::env::set_var("STATIKER_TEST_SET", "on");
+        let yaml = "# password: ${STATIKER_TEST_COMMENTED}\n\
+                    a: ${STATIKER_TEST_SET} # was ${STATIKER_TEST_COMMENTED}\n\
+                    b: \"x # ${STATIKER_TEST_SET}\" # ${STATIKER_TEST_COMMENTED}\n\
+                    c: it's ${STATIKER_TEST_SET}#not a comment\n";
+        assert_eq!(
+            expand_env(yaml).unwrap(),
+            "# password: ${STATIKER_TEST_COMMENTED}\n\
+             a: on # was ${STATIKER_TEST_COMMENTED}\n\
+             b: \"x # on\" # ${STATIKER_TEST_COMMENTED}\n\
+             c: it's on#not a comment\n"
+        );
+    }
+}
@@ -1,169 +1,1029 @@
-use crate::state::AppState;
+use crate::archive::{self, ArchiveFormat};
+use crate::config::{Dotfiles, ListingSort, TraversalMode, UaRule};
+use crate::router::resolve_path_within_root;
+use crate::ssi;
+use crate::state::{AppState, CachedFile};
+use crate::utils::{
+    attachment_disposition, guess_mime, is_asset_path, normalize_path, with_charset, Vary,
+};
 use axum::{
     body::Body,
-    http::{header::CONTENT_LENGTH, HeaderValue, Method, StatusCode},
+    http::{
+        header::{
+            ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES, ALLOW, CONTENT_DISPOSITION, CONTENT_ENCODING,
+            CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, IF_RANGE, LINK, RANGE,
+            USER_AGENT, VARY,
+        },
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
     response::{IntoResponse, Response},
 };
 use http::Request;
-use mime_guess;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
 
 /// Serve static files with auto-index support
 pub async fn serve_static(state: AppState, tail: String, req: Request<Body>) -> Response {
-    // Only allow GET and HEAD for static files
+    let vary_ua = !state.cfg.server.ua_rules.is_empty();
+    let head = req.method() == Method::HEAD;
+    let mut res = serve_static_inner(state.clone(), tail, req).await;
+    let status = res.status();
+    if status == StatusCode::NOT_FOUND || status.is_server_error() {
+        res = error_page(&state, res, head).await;
+    }
+    // Responses depend on the User-Agent once UA rules are configured
+    if vary_ua {
+        let mut vary = Vary::from_headers(res.headers());
+        vary.add(USER_AGENT.as_str());
+        vary.write(res.headers_mut());
+    }
+    res
+}
+
+/// Replace an error response with its `server.error_pages` entry
+///
+/// The status and other headers are kept; the body becomes the page read from
+/// `root`. A missing page leaves the response untouched.
+async fn error_page(state: &AppState, res: Response, head: bool) -> Response {
+    let Some(page) = state.cfg.server.error_pages.get(&res.status().as_u16()) else {
+        return res;
+    };
+    let Ok(html) = tokio::fs::read(state.root.join(page.trim_start_matches('/'))).await else {
+        warn!("error page {page} for status {} not readable", res.status());
+        return res;
+    };
+    let (mut parts, _) = res.into_parts();
+    parts.headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(html.len()));
+    let body = if head { Body::empty() } else { Body::from(html) };
+    Response::from_parts(parts, body)
+}
+
+async fn serve_static_inner(state: AppState, tail: String, req: Request<Body>) -> Response {
+    // Only accept the configured methods (GET and HEAD by default)
+    let allowed = &state.cfg.server.allowed_methods;
+    if !allowed.iter().any(|m| m.eq_ignore_ascii_case(req.method().as_str())) {
+        return (StatusCode::METHOD_NOT_ALLOWED, [(ALLOW, allow_header(allowed))]).into_response();
+    }
     match *req.method() {
         Method::GET | Method::HEAD => {}
-        _ => {
-            return StatusCode::METHOD_NOT_ALLOWED.into_response();
+        Method::OPTIONS => {
+            return (StatusCode::NO_CONTENT, [(ALLOW, allow_header(allowed))]).into_response();
         }
+        // Write and WebDAV methods are not implemented
+        _ => return StatusCode::NOT_IMPLEMENTED.into_response(),
     }
 
-    // Security: disallow path traversal attempts like ".."
-    if tail.split('/').any(|p| p == "..") {
-        return StatusCode::FORBIDDEN.into_response();
+    // Configured denylist, evaluated before any filesystem access. Rules see the
+    // decoded path that is served, so `/index.ph%70` can't slip past `\.php$`
+    let path = normalize_path(req.uri().path());
+    if let Some((_, status)) = state.deny_paths.iter().find(|(re, _)| re.is_match(&path)) {
+        return (*status).into_response();
+    }
+
+    // Reject paths some filesystems would alias (e.g. `index.html.` on Windows)
+    if has_unsafe_segment(&tail) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    // Dotfiles (`.env`, `.git/...`) are hidden unless explicitly allowed
+    if tail.split('/').any(is_dotfile) {
+        match state.cfg.server.dotfiles {
+            Dotfiles::Ignore => return StatusCode::NOT_FOUND.into_response(),
+            Dotfiles::Deny => return StatusCode::FORBIDDEN.into_response(),
+            Dotfiles::Allow => {}
+        }
+    }
+
+    // User-Agent rules replace the requested file entirely
+    if let Some(rule) = matching_ua_rule(&state, &req) {
+        return match resolve_path_within_root(&state.root, rule.serve.trim_start_matches('/')) {
+            Ok(path) => match tokio::fs::metadata(&path).await {
+                Ok(meta) if meta.is_file() => {
+                    file_response(&state, &path, req.method(), req.headers()).await
+                }
+                _ => StatusCode::NOT_FOUND.into_response(),
+            },
+            Err(_) => StatusCode::FORBIDDEN.into_response(),
+        };
     }
 
     // Compute normalized path relative to root
     let rel = tail.trim_start_matches('/');
-    let mut fs_path = state.root.clone();
-    if !rel.is_empty() {
-        // Safely join path, preventing directory traversal
-        for component in std::path::Path::new(rel).components() {
-            match component {
-                std::path::Component::Normal(os_str) => {
-                    fs_path.push(os_str);
-                }
-                _ => {
-                    return StatusCode::FORBIDDEN.into_response();
+    let fs_path = match state.cfg.server.traversal_mode {
+        TraversalMode::Strict => {
+            // Security: disallow path traversal attempts like ".."
+            if tail.split('/').any(|p| p == "..") {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            let mut fs_path = state.root.clone();
+            if !rel.is_empty() {
+                // Safely join path, preventing directory traversal
+                for component in std::path::Path::new(rel).components() {
+                    match component {
+                        std::path::Component::Normal(os_str) => {
+                            fs_path.push(os_str);
+                        }
+                        _ => {
+                            return StatusCode::FORBIDDEN.into_response();
+                        }
+                    }
                 }
             }
+            // The `..` checks above don't see symlinks pointing out of the root
+            if !state.cfg.server.follow_symlinks && escapes_root(&state.root, &fs_path).await {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            fs_path
         }
-    }
+        TraversalMode::Canonical => match canonical_path_within_root(&state.root, rel).await {
+            Ok(Some(path)) => path,
+            Ok(None) => return not_found(&state, rel, req.method(), req.headers()).await,
+            Err(status) => return status.into_response(),
+        },
+    };
 
-    // If path exists and is a file -> serve it
+    // If path exists and is a file -> serve it (or an `Accept` variant of it)
     match tokio::fs::metadata(&fs_path).await {
         Ok(meta) if meta.is_file() => {
-            let file_size = meta.len();
-            match tokio::fs::read(&fs_path).await {
-                Ok(bytes) => {
-                    let mime = mime_guess::from_path(&fs_path).first_or_octet_stream();
-                    let mut builder = Response::builder().status(StatusCode::OK);
-                    if let Ok(hv) = HeaderValue::from_str(&mime.to_string()) {
-                        builder = builder.header("content-type", hv);
-                    }
-                    // Set Content-Length header for both GET and HEAD (required by HTTP spec)
-                    if let Ok(cl_hv) = HeaderValue::from_str(&file_size.to_string()) {
-                        builder = builder.header(CONTENT_LENGTH, cl_hv);
-                    }
-                    // For HEAD, return empty body but with Content-Length header
-                    if req.method() == Method::HEAD {
-                        builder.body(Body::empty()).unwrap()
-                    } else {
-                        builder.body(Body::from(bytes)).unwrap()
-                    }
-                }
-                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            let mut res = file_or_variant(&state, &fs_path, req.method(), req.headers()).await;
+            // Download routes ask the browser to save the file under its requested name
+            if req.extensions().get::<Download>().is_some() && res.status().is_success() {
+                let name = rel.rsplit('/').next().unwrap_or(rel);
+                res.headers_mut().insert(CONTENT_DISPOSITION, attachment_disposition(name));
             }
+            res
         }
         // If it's a directory or doesn't exist, handle accordingly
         Ok(meta) if meta.is_dir() => {
-            // try index file first
-            let index_name = &state.cfg.server.index;
-            let index_path = fs_path.join(index_name);
-            // Get metadata to check if file exists and get its size
-            if let Ok(index_meta) = tokio::fs::metadata(&index_path).await {
-                if index_meta.is_file() {
-                    let file_size = index_meta.len();
-                    match tokio::fs::read(&index_path).await {
-                        Ok(bytes) => {
-                            let mime = mime_guess::from_path(&index_path).first_or_octet_stream();
+            // `?download=tar.gz|zip` streams the directory as an archive instead
+            if state.cfg.server.archive_download {
+                if let Some(format) = ArchiveFormat::from_query(req.uri().query()) {
+                    return archive_response(&state, fs_path, rel, format, &req);
+                }
+            }
+            // Precedence for directories:
+            //   1. the first `server.index` candidate that resolves to a regular file
+            //      (symlinks are followed), unless bypassed (see below)
+            //   2. an auto-generated listing, if `auto_index` is enabled
+            //   3. 404
+            // An index path that is a directory, a symlink to a directory or a
+            // dangling symlink is never served; it is treated as missing.
+            // With auto_index on, the index lookup can be skipped entirely
+            // via `force_auto_index` or a `?index=off` query
+            let skip_index = state.cfg.server.auto_index
+                && (state.cfg.server.force_auto_index || index_disabled_by_query(&req));
+            let index = match skip_index {
+                true => None,
+                false => find_index(&state, &fs_path).await,
+            };
+            match index {
+                Some((index_path, _))
+                    if !state.cfg.server.follow_symlinks
+                        && escapes_root(&state.root, &index_path).await =>
+                {
+                    StatusCode::FORBIDDEN.into_response()
+                }
+                Some((index_path, _)) => {
+                    file_response(&state, &index_path, req.method(), req.headers()).await
+                }
+                _ if state.cfg.server.auto_index => {
+                    let empty_message = &state.cfg.server.index_empty_message;
+                    let sort = state.cfg.server.listing_sort;
+                    let dotfiles = state.cfg.server.dotfiles;
+                    // Programmatic clients can ask for the listing as JSON
+                    let json = req
+                        .headers()
+                        .get(ACCEPT)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|accept| accept_position(accept, "application/json"))
+                        .is_some();
+                    match read_listing(&fs_path, sort, dotfiles).await {
+                        Ok(items) => {
+                            let template = match json {
+                                true => Cow::Borrowed(""),
+                                false => listing_template(&state).await,
+                            };
+                            // Unchanged directories are revalidated without rendering
+                            let etag = match state.current_cfg().assets.cache.etag {
+                                true => Some(listing_etag(&items, json, empty_message, &template)),
+                                false => None,
+                            };
+                            if let Some(etag) = &etag {
+                                let matches = req
+                                    .headers()
+                                    .get(IF_NONE_MATCH)
+                                    .and_then(|v| v.to_str().ok())
+                                    .is_some_and(|inm| etag_matches(inm, etag));
+                                if matches {
+                                    let headers = [(ETAG, etag.clone()), (VARY, "accept".into())];
+                                    return (StatusCode::NOT_MODIFIED, headers).into_response();
+                                }
+                            }
+                            let (html, content_type) = if json {
+                                (render_directory_listing_json(&items), "application/json")
+                            } else {
+                                let html =
+                                    render_directory_listing(&items, rel, empty_message, &template);
+                                (html, "text/html; charset=utf-8")
+                            };
+                            let html_len = html.len();
                             let mut builder = Response::builder().status(StatusCode::OK);
-                            if let Ok(hv) = HeaderValue::from_str(&mime.to_string()) {
-                                builder = builder.header("content-type", hv);
+                            builder = builder
+                                .header("content-type", content_type)
+                                .header(VARY, "accept");
+                            if let Some(etag) = etag {
+                                builder = builder.header(ETAG, etag);
+                            }
+                            // Listing-only headers, e.g. X-Robots-Tag: noindex
+                            for (k, v) in &state.cfg.server.index_headers {
+                                if let (Ok(name), Ok(val)) =
+                                    (HeaderName::from_str(k), HeaderValue::from_str(v))
+                                {
+                                    builder = builder.header(name, val);
+                                }
                             }
-                            // Set Content-Length header for both GET and HEAD (required by HTTP spec)
-                            if let Ok(cl_hv) = HeaderValue::from_str(&file_size.to_string()) {
+                            // Set Content-Length header for both GET and HEAD
+                            if let Ok(cl_hv) = HeaderValue::from_str(&html_len.to_string()) {
                                 builder = builder.header(CONTENT_LENGTH, cl_hv);
                             }
-                            // For HEAD, return empty body but with Content-Length header
                             if req.method() == Method::HEAD {
                                 builder.body(Body::empty()).unwrap()
                             } else {
-                                builder.body(Body::from(bytes)).unwrap()
+                                builder.body(Body::from(html)).unwrap()
                             }
                         }
                         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
                     }
-                } else {
-                    // Index path exists but is not a file, fall through to auto-index or 404
-                    if state.cfg.server.auto_index {
-                        match render_directory_listing(&fs_path, rel).await {
-                            Ok(html) => {
-                                let html_len = html.len();
-                                let mut builder = Response::builder().status(StatusCode::OK);
-                                builder = builder.header("content-type", "text/html; charset=utf-8");
-                                // Set Content-Length header for both GET and HEAD
-                                if let Ok(cl_hv) = HeaderValue::from_str(&html_len.to_string()) {
-                                    builder = builder.header(CONTENT_LENGTH, cl_hv);
-                                }
-                                if req.method() == Method::HEAD {
-                                    builder.body(Body::empty()).unwrap()
-                                } else {
-                                    builder.body(Body::from(html)).unwrap()
-                                }
-                            }
-                            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-                        }
-                    } else {
-                        StatusCode::NOT_FOUND.into_response()
-                    }
                 }
-            } else if state.cfg.server.auto_index {
-                // Index file doesn't exist, try auto-index
-                match render_directory_listing(&fs_path, rel).await {
+                // Root without index or listing: show a first-run page instead of a bare 404
+                _ if rel.is_empty() => empty_root_response(&state, req.method()).await,
+                _ => not_found(&state, rel, req.method(), req.headers()).await,
+            }
+        }
+        // Path doesn't exist: try the configured fallback chain, then 404
+        _ => not_found(&state, rel, req.method(), req.headers()).await,
+    }
+}
+
+/// Find a sibling of `path` for a media type the client accepts
+///
+/// Candidates are `path` plus a `server.accept_variants` suffix (e.g.
+/// `photo.jpg.webp`). Returns whether any variant exists, so the caller can
+/// add `Vary: Accept`, and the variant to serve: the one whose type appears
+/// first in the `Accept` header.
+async fn accept_variant(
+    state: &AppState,
+    headers: &HeaderMap,
+    path: &Path,
+) -> (bool, Option<PathBuf>) {
+    let accept = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let mut has_variants = false;
+    let mut best: Option<(usize, PathBuf)> = None;
+    for (media_type, suffix) in &state.cfg.server.accept_variants {
+        let mut candidate = path.as_os_str().to_owned();
+        candidate.push(suffix);
+        let candidate = PathBuf::from(candidate);
+        let Ok(meta) = tokio::fs::metadata(&candidate).await else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        has_variants = true;
+        if let Some(pos) = accept_position(accept, media_type) {
+            if best.as_ref().is_none_or(|(p, _)| pos < *p) {
+                best = Some((pos, candidate));
+            }
+        }
+    }
+    (has_variants, best.map(|(_, path)| path))
+}
+
+/// Find a pre-compressed sibling of `path` the client accepts
+///
+/// Looks for `path.br` and `path.gz`, preferring brotli when both exist and
+/// are accepted. Returns whether any sibling exists, so the caller can add
+/// `Vary: Accept-Encoding`, and the sibling to serve with its encoding.
+async fn precompressed_sibling(
+    headers: &HeaderMap,
+    path: &Path,
+) -> (bool, Option<(PathBuf, &'static str)>) {
+    let accept = headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let mut has_siblings = false;
+    let mut best = None;
+    for (suffix, encoding) in [(".br", "br"), (".gz", "gzip")] {
+        let mut candidate = path.as_os_str().to_owned();
+        candidate.push(suffix);
+        let candidate = PathBuf::from(candidate);
+        let Ok(meta) = tokio::fs::metadata(&candidate).await else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        has_siblings = true;
+        let accepted = accept_position(accept, encoding).is_some()
+            || accept_position(accept, "*").is_some();
+        if accepted && best.is_none() {
+            best = Some((candidate, encoding));
+        }
+    }
+    (has_siblings, best)
+}
+
+/// Position of `media_type` in an `Accept` header, ignoring `q=0` entries
+fn accept_position(accept: &str, media_type: &str) -> Option<usize> {
+    accept.split(',').position(|entry| {
+        let mut parts = entry.split(';').map(str::trim);
+        let matches = parts
+            .next()
+            .is_some_and(|t| t.eq_ignore_ascii_case(media_type));
+        let refused = parts.any(|p| {
+            p.strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        matches && !refused
+    })
+}
+
+/// Stream a directory as a downloadable archive
+///
+/// Dotfiles and entries whose URL path matches `security.deny_paths` are left out.
+fn archive_response(
+    state: &AppState,
+    dir: PathBuf,
+    rel: &str,
+    format: ArchiveFormat,
+    req: &Request<Body>,
+) -> Response {
+    let name = Path::new(rel)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "download".into());
+    let disposition = attachment_disposition(&format!("{name}.{}", format.extension()));
+    let builder = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", format.content_type())
+        .header(CONTENT_DISPOSITION, disposition);
+    if req.method() == Method::HEAD {
+        return builder.body(Body::empty()).unwrap();
+    }
+
+    let base = normalize_path(req.uri().path()).trim_end_matches('/').to_string();
+    let deny_paths = state.deny_paths.clone();
+    let dotfiles = state.cfg.server.dotfiles;
+    let include = move |entry: &str| {
+        let url = format!("{base}/{entry}");
+        (dotfiles == Dotfiles::Allow || !entry.split('/').any(is_dotfile))
+            && !deny_paths.iter().any(|(re, _)| re.is_match(&url))
+    };
+    builder
+        .body(archive::stream_archive(dir, format, include))
+        .unwrap()
+}
+
+/// Answer a request that resolved to no file
+///
+/// Precedence: `server.fallback_paths`, then the SPA fallback for navigational
+/// requests, then 404.
+async fn not_found(state: &AppState, rel: &str, method: &Method, headers: &HeaderMap) -> Response {
+    if let Some(res) = fallback_response(state, rel, method, headers).await {
+        return res;
+    }
+    match spa_fallback(state, rel, method, headers).await {
+        Some(res) => res,
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Serve `spa.fallback` if SPA mode is on and the request is a navigation
+///
+/// Only `GET`/`HEAD` requests for `path` qualify, see [`is_navigation`], so
+/// API clients and asset loaders keep getting their 404. A fallback path with
+/// traversal is replaced by `index.html`, as warned about at startup.
+pub async fn spa_fallback(
+    state: &AppState,
+    path: &str,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    if !state.cfg.spa.enabled || !matches!(*method, Method::GET | Method::HEAD) {
+        return None;
+    }
+    if !is_navigation(path, headers, &state.cfg.assets.extensions) {
+        return None;
+    }
+    let fallback = state.cfg.spa.fallback.trim_start_matches('/');
+    let path = resolve_path_within_root(&state.root, fallback)
+        .unwrap_or_else(|_| state.root.join("index.html"));
+    if !state.cfg.server.follow_symlinks && escapes_root(&state.root, &path).await {
+        return None;
+    }
+    match tokio::fs::metadata(&path).await {
+        Ok(meta) if meta.is_file() => {
+            Some(file_response(state, &path, method, headers).await)
+        }
+        _ => None,
+    }
+}
+
+/// Whether a request for `path` looks like a browser navigation
+///
+/// Asset paths (see [`is_asset_path`]) never are. Otherwise the `Accept`
+/// header must list `text/html`, or the last segment has no extension and
+/// `Accept` is missing or allows `*/*`.
+fn is_navigation(path: &str, headers: &HeaderMap, asset_exts: &[String]) -> bool {
+    if is_asset_path(path, asset_exts) {
+        return false;
+    }
+    let accept = headers.get(ACCEPT).and_then(|v| v.to_str().ok());
+    if accept.is_some_and(|a| accept_position(a, "text/html").is_some()) {
+        return true;
+    }
+    let name = path.rsplit('/').next().unwrap_or_default();
+    !name.contains('.') && accept.is_none_or(|a| accept_position(a, "*/*").is_some())
+}
+
+/// Try `server.fallback_paths` in order after the requested path missed
+///
+/// A directory entry is searched for the requested path (e.g. a shared
+/// `/common` tree), a file entry is served as-is (e.g. an SPA index).
+/// Entries and lookups are resolved within the root; invalid ones are skipped.
+async fn fallback_response(
+    state: &AppState,
+    rel: &str,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    for entry in &state.cfg.server.fallback_paths {
+        let Ok(base) = resolve_path_within_root(&state.root, entry.trim_start_matches('/')) else {
+            continue;
+        };
+        let candidate = match tokio::fs::metadata(&base).await {
+            Ok(meta) if meta.is_file() => Some(base),
+            Ok(meta) if meta.is_dir() && !rel.is_empty() => {
+                resolve_path_within_root(&base, rel).ok()
+            }
+            _ => None,
+        };
+        let Some(path) = candidate else {
+            continue;
+        };
+        if !state.cfg.server.follow_symlinks && escapes_root(&state.root, &path).await {
+            continue;
+        }
+        if let Ok(meta) = tokio::fs::metadata(&path).await {
+            if meta.is_file() {
+                return Some(file_response(state, &path, method, headers).await);
+            }
+        }
+    }
+    None
+}
+
+/// Check a decoded request path for control characters (0x00-0x1F) or
+/// segments ending in a dot or space, which also covers segments made only
+/// of dots and spaces. `.` and `..` are left to the traversal checks.
+fn has_unsafe_segment(path: &str) -> bool {
+    path.bytes().any(|b| b < 0x20)
+        || path
+            .split('/')
+            .filter(|seg| *seg != "." && *seg != "..")
+            .any(|seg| seg.ends_with('.') || seg.ends_with(' '))
+}
+
+/// Whether a path segment names a dotfile subject to `server.dotfiles`
+///
+/// `.` and `..` are left to the traversal checks, and `.well-known` (RFC 8615,
+/// e.g. ACME challenges) is always reachable.
+fn is_dotfile(segment: &str) -> bool {
+    segment.starts_with('.') && !matches!(segment, "." | ".." | ".well-known")
+}
+
+/// Resolve `rel` under `root` by canonicalization, following `..` and symlinks
+///
+/// Returns `Ok(None)` if the path doesn't exist and `Err(FORBIDDEN)` if the
+/// resolved path escapes the root or contains absolute components.
+///
+/// `..` segments are first resolved lexically: a path leaving the root is
+/// refused before touching the filesystem, so clients can't tell existing
+/// files outside the root from missing ones.
+async fn canonical_path_within_root(
+    root: &Path,
+    rel: &str,
+) -> Result<Option<PathBuf>, StatusCode> {
+    let Ok(root) = tokio::fs::canonicalize(root).await else {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    let mut lexical = root.clone();
+    for component in Path::new(rel).components() {
+        match component {
+            std::path::Component::Normal(name) => lexical.push(name),
+            std::path::Component::ParentDir => {
+                lexical.pop();
+            }
+            std::path::Component::CurDir => {}
+            std::path::Component::RootDir | std::path::Component::Prefix(_) => {
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+    }
+    if !lexical.starts_with(&root) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match tokio::fs::canonicalize(lexical).await {
+        Ok(resolved) if resolved.starts_with(&root) => Ok(Some(resolved)),
+        Ok(_) => Err(StatusCode::FORBIDDEN),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Whether `path` resolves, through symlinks, to a location outside `root`
+///
+/// Paths that don't exist don't escape; callers treat them as missing.
+async fn escapes_root(root: &Path, path: &Path) -> bool {
+    let (Ok(root), Ok(resolved)) =
+        (tokio::fs::canonicalize(root).await, tokio::fs::canonicalize(path).await)
+    else {
+        return false;
+    };
+    !resolved.starts_with(root)
+}
+
+/// Serve a regular file with content type, length and preload headers
+///
+/// A single `Range` in the request is answered with `206 Partial Content`.
+async fn file_response(
+    state: &AppState,
+    path: &Path,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Response {
+    // Wait for a read slot when `server.max_concurrent_reads` is set; the
+    // permit is held until the file (and any includes) have been read
+    let _permit = match &state.read_limit {
+        Some(limit) => limit.acquire().await.ok(),
+        None => None,
+    };
+    // Pre-compressed sibling (`app.js.br`, `app.js.gz`) chosen by Accept-Encoding
+    let (has_siblings, encoded) = match state.cfg.assets.precompressed {
+        true => precompressed_sibling(headers, path).await,
+        false => (false, None),
+    };
+    let read_path = match &encoded {
+        Some((sibling, _)) => sibling.as_path(),
+        None => path,
+    };
+    // Callers check the requested path; siblings, variants and UA-rule
+    // targets are only known here, so check the file actually read as well
+    if !state.cfg.server.follow_symlinks && escapes_root(&state.root, read_path).await {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Ok(meta) = tokio::fs::metadata(read_path).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    // From the requested path: a cached file may also be served under other
+    // names, e.g. `app.js.gz` directly and as `app.js`'s sibling
+    let mime = guess_mime(path, &state.cfg.server);
+    let ssi = state.cfg.server.ssi && mime == mime_guess::mime::TEXT_HTML && encoded.is_none();
+    // The ETag only reflects this file, not its includes, so expanded pages get none
+    let etag = match state.current_cfg().assets.cache.etag && !ssi {
+        true => weak_etag(&meta),
+        false => None,
+    };
+    if let Some(etag) = &etag {
+        let matches = headers
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|inm| etag_matches(inm, etag));
+        if matches {
+            return (StatusCode::NOT_MODIFIED, [(ETAG, etag.clone())]).into_response();
+        }
+    }
+    match read_file(state, read_path, &meta).await {
+        Ok(file) => {
+            let mut bytes = file.bytes.clone();
+            // Expand server-side includes in HTML documents (UTF-8 only)
+            if ssi {
+                bytes = match String::from_utf8(bytes.to_vec()) {
                     Ok(html) => {
-                        let html_len = html.len();
-                        let mut builder = Response::builder().status(StatusCode::OK);
-                        builder = builder.header("content-type", "text/html; charset=utf-8");
-                        // Set Content-Length header for both GET and HEAD
-                        if let Ok(cl_hv) = HeaderValue::from_str(&html_len.to_string()) {
-                            builder = builder.header(CONTENT_LENGTH, cl_hv);
-                        }
-                        if req.method() == Method::HEAD {
-                            builder.body(Body::empty()).unwrap()
-                        } else {
-                            builder.body(Body::from(html)).unwrap()
-                        }
+                        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                        ssi::expand(html, dir, 0).await.into_bytes().into()
                     }
-                    Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                    Err(e) => e.into_bytes().into(),
+                };
+            }
+            // Ranges and Content-Length follow the bytes actually read, which
+            // may differ from an earlier `metadata()` if the file changed since
+            let mut file_size = bytes.len() as u64;
+            let mut builder = Response::builder()
+                .status(StatusCode::OK)
+                .header(ACCEPT_RANGES, "bytes");
+            if let Ok(hv) = HeaderValue::from_str(&with_charset(&mime)) {
+                builder = builder.header("content-type", hv);
+            }
+            if let Some(etag) = etag {
+                builder = builder.header(ETAG, etag);
+            }
+            if let Some((_, encoding)) = encoded {
+                builder = builder.header(CONTENT_ENCODING, encoding);
+            }
+            // Ranges apply to the bytes of the chosen encoding, so caches must
+            // keep partial responses apart per encoding as well as per range
+            if has_siblings {
+                builder = builder.header(VARY, "accept-encoding, range");
+            }
+            if mime == mime_guess::mime::TEXT_HTML {
+                builder = with_preload_links(builder, state);
+                builder = with_client_hints(builder, state);
+            }
+            // If-Range needs a strong validator and our ETags are weak, so a
+            // conditional range request always gets the full body
+            let range = match headers.get(RANGE).and_then(|v| v.to_str().ok()) {
+                Some(range) if !headers.contains_key(IF_RANGE) => parse_range(range, file_size),
+                _ => Ok(None),
+            };
+            match range {
+                Ok(Some((start, end))) => {
+                    bytes = bytes.slice(start as usize..=end as usize);
+                    builder = builder.status(StatusCode::PARTIAL_CONTENT).header(
+                        CONTENT_RANGE,
+                        format!("bytes {start}-{end}/{file_size}"),
+                    );
+                    file_size = end - start + 1;
                 }
+                Ok(None) => {}
+                Err(()) => {
+                    return (
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        [(CONTENT_RANGE, format!("bytes */{file_size}"))],
+                    )
+                        .into_response();
+                }
+            }
+            // Set Content-Length header for both GET and HEAD (required by HTTP spec)
+            if let Ok(cl_hv) = HeaderValue::from_str(&file_size.to_string()) {
+                builder = builder.header(CONTENT_LENGTH, cl_hv);
+            }
+            // For HEAD, return empty body but with Content-Length header
+            if method == Method::HEAD {
+                builder.body(Body::empty()).unwrap()
             } else {
-                StatusCode::NOT_FOUND.into_response()
+                builder.body(Body::from(bytes)).unwrap()
             }
         }
-        // Path doesn't exist
-        _ => {
-            // If SPA is enabled, the router may fallback to SPA index. But here return 404.
-            StatusCode::NOT_FOUND.into_response()
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Contents of `read_path`, through `assets.memcache` if enabled
+///
+/// `meta` is the file's current metadata; cache entries are checked against it.
+async fn read_file(
+    state: &AppState,
+    read_path: &Path,
+    meta: &std::fs::Metadata,
+) -> std::io::Result<Arc<CachedFile>> {
+    let cache = state.memcache.as_ref().filter(|cache| cache.admits(meta.len()));
+    let key = match cache {
+        Some(_) => tokio::fs::canonicalize(read_path).await.ok(),
+        None => None,
+    };
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        if let Some(file) = cache.get(key, meta) {
+            return Ok(file);
         }
     }
+    let file = Arc::new(CachedFile {
+        bytes: tokio::fs::read(read_path).await?.into(),
+        modified: meta.modified().ok(),
+    });
+    if let (Some(cache), Some(key)) = (cache, key) {
+        cache.insert(key, file.clone());
+    }
+    Ok(file)
 }
 
-/// Render HTML directory listing
-pub async fn render_directory_listing(dir: &PathBuf, rel_path: &str) -> std::io::Result<String> {
+/// Weak ETag derived from the file's size and modification time
+fn weak_etag(meta: &std::fs::Metadata) -> Option<String> {
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("W/\"{}-{}\"", meta.len(), mtime.as_nanos()))
+}
+
+/// Weak comparison of an `If-None-Match` list against `etag`
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// Parse a `Range` header against a representation of `size` bytes
+///
+/// Returns the inclusive byte range to serve, `Ok(None)` when the header
+/// should be ignored (other units, multiple ranges, malformed syntax) and
+/// `Err(())` when the range is unsatisfiable.
+fn parse_range(header: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        // Suffix range: the last N bytes
+        ("", suffix) => {
+            let Ok(n) = suffix.parse::<u64>() else {
+                return Ok(None);
+            };
+            if n == 0 || size == 0 {
+                return Err(());
+            }
+            (size.saturating_sub(n), size - 1)
+        }
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return Ok(None);
+            };
+            let end = match end {
+                "" => size.saturating_sub(1),
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(size.saturating_sub(1)),
+                    _ => return Ok(None),
+                },
+            };
+            if start >= size {
+                return Err(());
+            }
+            (start, end)
+        }
+    };
+    Ok(Some((start, end)))
+}
+
+/// Built-in page served at `/` when the root has no index file
+const EMPTY_ROOT_PAGE: &str = "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>statiker</title><style>body { font-family: sans-serif; margin: 40px; color: #333; } code { background: #f4f4f4; padding: 2px 4px; }</style></head><body><h1>statiker is running</h1><p>No content has been published yet. Put an <code>index.html</code> into the configured root directory, or enable <code>server.auto_index</code> to list its files.</p><hr><address>statiker</address></body></html>";
+
+/// Built-in directory listing, see [`render_directory_listing`] for the placeholders
+const LISTING_TEMPLATE: &str = "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Index of {{title}}</title><style>body { font-family: monospace; margin: 20px; } h1 { color: #333; } ul { list-style: none; padding: 0; } li { padding: 5px 0; } a { color: #0066cc; text-decoration: none; } a:hover { text-decoration: underline; } hr { margin-top: 20px; border: none; border-top: 1px solid #ccc; } li.entry { display: grid; grid-template-columns: 1fr 12ch 18ch; } .size { text-align: right; } .date { text-align: right; color: #666; }</style></head><body><h1>Index of {{title}}</h1><ul>{{entries}}</ul><hr><address>statiker</address></body></html>";
+
+/// The `server.listing_template` contents, or the built-in template
+async fn listing_template(state: &AppState) -> Cow<'static, str> {
+    match &state.cfg.server.listing_template {
+        Some(path) => match tokio::fs::read_to_string(path).await {
+            Ok(template) => Cow::Owned(template),
+            Err(e) => {
+                warn!("listing_template {:?} unavailable: {e}", path);
+                Cow::Borrowed(LISTING_TEMPLATE)
+            }
+        },
+        None => Cow::Borrowed(LISTING_TEMPLATE),
+    }
+}
+
+/// Respond with `server.empty_root_page` or the built-in first-run page
+async fn empty_root_response(state: &AppState, method: &Method) -> Response {
+    let body = match &state.cfg.server.empty_root_page {
+        Some(path) => match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("empty_root_page {:?} unavailable: {e}", path);
+                EMPTY_ROOT_PAGE.as_bytes().to_vec()
+            }
+        },
+        None => EMPTY_ROOT_PAGE.as_bytes().to_vec(),
+    };
+    let builder = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/html; charset=utf-8")
+        .header(CONTENT_LENGTH, body.len());
+    if method == Method::HEAD {
+        builder.body(Body::empty()).unwrap()
+    } else {
+        builder.body(Body::from(body)).unwrap()
+    }
+}
+
+/// Find the first UA rule whose pattern occurs in the request's `User-Agent`
+fn matching_ua_rule<'a, B>(state: &'a AppState, req: &Request<B>) -> Option<&'a UaRule> {
+    let ua = req.headers().get(USER_AGENT)?.to_str().ok()?.to_ascii_lowercase();
+    state
+        .cfg
+        .server
+        .ua_rules
+        .iter()
+        .find(|r| ua.contains(&r.pattern.to_ascii_lowercase()))
+}
+
+/// Build the `Allow` header value from the configured methods
+fn allow_header(methods: &[String]) -> String {
+    methods
+        .iter()
+        .map(|m| m.to_ascii_uppercase())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Serve `path`, or the `server.accept_variants` variant the request prefers
+async fn file_or_variant(
+    state: &AppState,
+    path: &Path,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Response {
+    if state.cfg.server.accept_variants.is_empty() {
+        return file_response(state, path, method, headers).await;
+    }
+    let (has_variants, variant) = accept_variant(state, headers, path).await;
+    let mut res = match variant {
+        Some(path) => file_response(state, &path, method, headers).await,
+        None => file_response(state, path, method, headers).await,
+    };
+    if has_variants {
+        let mut vary = Vary::from_headers(res.headers());
+        vary.add(ACCEPT.as_str());
+        vary.write(res.headers_mut());
+    }
+    res
+}
+
+/// Request extension set by static routes with `download: true`
+#[derive(Debug, Clone, Copy)]
+pub struct Download;
+
+/// The first `server.index` candidate in `dir` that is a regular file
+///
+/// Symlinks are followed; candidates that are directories or dangling
+/// symlinks are skipped.
+async fn find_index(state: &AppState, dir: &Path) -> Option<(PathBuf, std::fs::Metadata)> {
+    for name in state.cfg.server.index.iter() {
+        let path = dir.join(name);
+        if let Ok(meta) = tokio::fs::metadata(&path).await {
+            if meta.is_file() {
+                return Some((path, meta));
+            }
+        }
+    }
+    None
+}
+
+/// Whether the request asks to bypass the index file with `?index=off`
+fn index_disabled_by_query<B>(req: &Request<B>) -> bool {
+    req.uri()
+        .query()
+        .map(|q| q.split('&').any(|pair| pair == "index=off"))
+        .unwrap_or(false)
+}
+
+/// Attach configured `Link: rel=preload` headers to an HTML response
+fn with_preload_links(
+    mut builder: http::response::Builder,
+    state: &AppState,
+) -> http::response::Builder {
+    for p in &state.cfg.assets.preload {
+        let link = format!("<{}>; rel=preload; as={}", p.path, p.kind);
+        if let Ok(hv) = HeaderValue::from_str(&link) {
+            builder = builder.header(LINK, hv);
+        }
+    }
+    builder
+}
+
+/// Advertise configured Client Hints with `Accept-CH` and vary on them
+fn with_client_hints(
+    builder: http::response::Builder,
+    state: &AppState,
+) -> http::response::Builder {
+    let hints = &state.cfg.server.client_hints;
+    if hints.is_empty() {
+        return builder;
+    }
+    let list = hints.join(", ");
+    match HeaderValue::from_str(&list) {
+        Ok(hv) => builder.header("accept-ch", hv.clone()).header(VARY, hv),
+        Err(_) => builder,
+    }
+}
+
+/// Directory entry shown in listings: (name, is_dir, size, modified)
+type ListingItem = (String, bool, u64, Option<SystemTime>);
+
+/// Read the entries of `dir`, directories first, each group in `sort` order
+///
+/// Dotfiles are left out unless `dotfiles` is [`Dotfiles::Allow`].
+async fn read_listing(
+    dir: &Path,
+    sort: ListingSort,
+    dotfiles: Dotfiles,
+) -> std::io::Result<Vec<ListingItem>> {
     let mut entries = tokio::fs::read_dir(dir).await?;
-    let mut items: Vec<(String, bool)> = Vec::new(); // (name, is_dir)
+    let mut items: Vec<ListingItem> = Vec::new();
     while let Some(entry) = entries.next_entry().await? {
         let file_name = entry.file_name().to_string_lossy().to_string();
+        if dotfiles != Dotfiles::Allow && is_dotfile(&file_name) {
+            continue;
+        }
         let meta = entry.metadata().await?;
-        items.push((file_name, meta.is_dir()));
+        items.push((file_name, meta.is_dir(), meta.len(), meta.modified().ok()));
     }
-    // sort: directories first, then files, both alphabetically
+    let by_name = |a: &ListingItem, b: &ListingItem| a.0.to_lowercase().cmp(&b.0.to_lowercase());
+    let order = |a: &ListingItem, b: &ListingItem| match sort {
+        ListingSort::Name => by_name(a, b),
+        ListingSort::NameDesc => by_name(b, a),
+        ListingSort::Size => a.2.cmp(&b.2).then_with(|| by_name(a, b)),
+        ListingSort::SizeDesc => b.2.cmp(&a.2).then_with(|| by_name(a, b)),
+        ListingSort::Modified => a.3.cmp(&b.3).then_with(|| by_name(a, b)),
+        ListingSort::ModifiedDesc => b.3.cmp(&a.3).then_with(|| by_name(a, b)),
+    };
+    // sort: directories first, then files, each group in the configured order
     items.sort_by(|a, b| match (a.1, b.1) {
         (true, false) => std::cmp::Ordering::Less,
         (false, true) => std::cmp::Ordering::Greater,
-        _ => a.0.to_lowercase().cmp(&b.0.to_lowercase()),
+        _ => order(a, b),
     });
+    Ok(items)
+}
+
+/// Render a directory listing as a JSON array of
+/// `{ "name", "is_dir", "size", "modified" }` objects
+///
+/// `modified` is an RFC 3339 timestamp (UTC, whole seconds) or `null`.
+pub fn render_directory_listing_json(items: &[ListingItem]) -> String {
+    let items: Vec<_> = items
+        .iter()
+        .map(|(name, is_dir, size, modified)| {
+            serde_json::json!({
+                "name": name,
+                "is_dir": is_dir,
+                "size": size,
+                "modified": modified.map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+            })
+        })
+        .collect();
+    serde_json::Value::Array(items).to_string()
+}
+
+/// Weak ETag for a directory listing
+///
+/// Hashes the entries (name, type, size, mtime, in listing order) and
+/// everything else that shapes the rendered body.
+fn listing_etag(items: &[ListingItem], json: bool, empty_message: &str, template: &str) -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (items, json, empty_message, template).hash(&mut hasher);
+    format!("W/\"listing-{:016x}\"", hasher.finish())
+}
+
+/// Human-readable size with binary units, e.g. `512 B` or `4.2 KiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Listing timestamp in UTC, e.g. `2024-05-01 13:37`
+fn format_listing_date(time: SystemTime) -> String {
+    let rfc3339 = humantime::format_rfc3339_seconds(time).to_string();
+    rfc3339[..16].replacen('T', " ", 1)
+}
+
+/// Render HTML directory listing
+///
+/// Empty directories show `empty_message` below the parent link. In `template`,
+/// `{{title}}` becomes the escaped directory path and `{{entries}}` the `<li>`
+/// items (parent link and entries) for the list.
+pub fn render_directory_listing(
+    items: &[ListingItem],
+    rel_path: &str,
+    empty_message: &str,
+    template: &str,
+) -> String {
 
     // Build simple HTML
     let title = if rel_path.is_empty() {
@@ -172,11 +1032,6 @@ pub async fn render_directory_listing(dir: &PathBuf, rel_path: &str) -> std::io:
         format!("/{}", rel_path)
     };
     let mut html = String::new();
-    html.push_str("<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Index of ");
-    html.push_str(&html_escape::encode_text(&title));
-    html.push_str("</title><style>body { font-family: monospace; margin: 20px; } h1 { color: #333; } ul { list-style: none; padding: 0; } li { padding: 5px 0; } a { color: #0066cc; text-decoration: none; } a:hover { text-decoration: underline; } hr { margin-top: 20px; border: none; border-top: 1px solid #ccc; }</style></head><body><h1>Index of ");
-    html.push_str(&html_escape::encode_text(&title));
-    html.push_str("</h1><ul>");
 
     // parent link if not root
     if !rel_path.is_empty() {
@@ -196,7 +1051,14 @@ pub async fn render_directory_listing(dir: &PathBuf, rel_path: &str) -> std::io:
         ));
     }
 
-    for (name, is_dir) in items {
+    if items.is_empty() {
+        html.push_str(&format!(
+            "<li class=\"empty\"><em>{}</em></li>",
+            html_escape::encode_text(empty_message)
+        ));
+    }
+
+    for (name, is_dir, size, modified) in items.iter().cloned() {
         // Construct URL path
         let mut url = String::new();
         if rel_path.is_empty() {
@@ -214,30 +1076,59 @@ pub async fn render_directory_listing(dir: &PathBuf, rel_path: &str) -> std::io:
         // Escape for safety
         let esc_url = html_escape::encode_double_quoted_attribute(&url);
         let esc_name = html_escape::encode_text(&name);
+        let size = if is_dir { "-".to_string() } else { format_size(size) };
+        let date = modified.map(format_listing_date).unwrap_or_else(|| "-".into());
         html.push_str(&format!(
-            "<li><a href=\"{}\">{}</a></li>",
-            esc_url, esc_name
+            "<li class=\"entry\"><a href=\"{}\">{}</a><span class=\"size\">{}</span>\
+             <span class=\"date\">{}</span></li>",
+            esc_url, esc_name, size, date
         ));
     }
 
-    html.push_str("</ul><hr><address>statiker</address></body></html>");
-    Ok(html)
+    let title = html_escape::encode_text(&title);
+    fill_template(template, &[("title", &title), ("entries", &html)])
+}
+
+/// Substitute `{{name}}` placeholders in `template` in a single pass
+///
+/// Unknown placeholders are kept as they are, and substituted values are
+/// never scanned again, so file names can't inject placeholders.
+fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            let name = after[..end].trim();
+            values.iter().find(|(key, _)| *key == name).map(|(_, value)| (*value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
+    use crate::config::{Config, IndexFiles};
+    use crate::state::{test_state, FileCache};
     use axum::http::{header::CONTENT_LENGTH, Method};
     use std::sync::Arc;
 
     #[tokio::test]
     async fn test_serve_static_path_traversal() {
-        let state = AppState {
-            cfg: Arc::new(Config::default()),
-            root: std::path::PathBuf::from("."),
-            limiter: None,
-        };
+        let state = test_state(Config::default());
         let req = Request::builder()
             .method(Method::GET)
             .uri("/")
@@ -249,11 +1140,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_serve_static_method_not_allowed() {
-        let state = AppState {
-            cfg: Arc::new(Config::default()),
-            root: std::path::PathBuf::from("."),
-            limiter: None,
-        };
+        let state = test_state(Config::default());
         let req = Request::builder()
             .method(Method::POST)
             .uri("/")
@@ -266,11 +1153,7 @@ mod tests {
     #[tokio::test]
     async fn test_head_request_has_content_length() {
         // Test with Cargo.toml which should exist in the project root
-        let state = AppState {
-            cfg: Arc::new(Config::default()),
-            root: std::path::PathBuf::from("."),
-            limiter: None,
-        };
+        let state = test_state(Config::default());
         let req = Request::builder()
             .method(Method::HEAD)
             .uri("/Cargo.toml")
@@ -290,11 +1173,7 @@ mod tests {
     #[tokio::test]
     async fn test_get_request_has_content_length() {
         // Test with Cargo.toml which should exist in the project root
-        let state = AppState {
-            cfg: Arc::new(Config::default()),
-            root: std::path::PathBuf::from("."),
-            limiter: None,
-        };
+        let state = test_state(Config::default());
         let req = Request::builder()
             .method(Method::GET)
             .uri("/Cargo.toml")
@@ -310,8 +1189,1328 @@ mod tests {
             // Verify body contains the file content and matches Content-Length
             let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
             assert_eq!(body_bytes.len(), cl_num, "Body length should match Content-Length header");
-            assert!(body_bytes.len() > 0, "GET response should have non-empty body");
+            assert!(!body_bytes.is_empty(), "GET response should have non-empty body");
         }
     }
-}
 
+    /// Create a fresh, empty directory under the system temp dir
+    fn temp_root(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("statiker-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn state_with(root: std::path::PathBuf, auto_index: bool) -> AppState {
+        let mut cfg = Config::default();
+        cfg.server.auto_index = auto_index;
+        AppState { root, ..test_state(cfg) }
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_reads_waits_for_permit() {
+        let root = temp_root("read-limit");
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        let limit = Arc::new(tokio::sync::Semaphore::new(1));
+        let state = AppState {
+            read_limit: Some(limit.clone()),
+            ..state_with(root, false)
+        };
+
+        let held = limit.clone().acquire_owned().await.unwrap();
+        let pending = tokio::spawn(serve_static(state, "a.txt".into(), get("/a.txt")));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!pending.is_finished());
+
+        drop(held);
+        let res = pending.await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(limit.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_paths_chain() {
+        let root = temp_root("fallback-chain");
+        std::fs::create_dir_all(root.join("common/css")).unwrap();
+        std::fs::create_dir_all(root.join("site/css")).unwrap();
+        std::fs::write(root.join("common/css/base.css"), "shared").unwrap();
+        std::fs::write(root.join("site/css/base.css"), "own").unwrap();
+        std::fs::write(root.join("app.html"), "spa").unwrap();
+        let mut cfg = Config::default();
+        cfg.server.fallback_paths =
+            vec!["/common".into(), "/../outside".into(), "/app.html".into()];
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+
+        let body = |res: Response| async move {
+            let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+        // Primary path wins when it exists
+        let res = serve_static(state.clone(), "site/css/base.css".into(), get("/")).await;
+        assert_eq!(body(res).await, "own");
+        // Missing files are looked up in the shared directory...
+        let res = serve_static(state.clone(), "css/base.css".into(), get("/")).await;
+        assert_eq!(body(res).await, "shared");
+        // ...then fall through to the final file entry
+        let res = serve_static(state.clone(), "css/missing.css".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(res).await, "spa");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_paths_empty_is_404() {
+        let root = temp_root("fallback-none");
+        let res = serve_static(state_with(root, false), "missing".into(), get("/missing")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_spa_fallback_skips_assets() {
+        let root = temp_root("spa-assets");
+        std::fs::write(root.join("index.html"), "app").unwrap();
+        let mut cfg = Config::default();
+        cfg.spa.enabled = true;
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+
+        let res = serve_static(state.clone(), "app/route".into(), get("/app/route")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"app");
+        let res = serve_static(state.clone(), "missing.js".into(), get("/missing.js")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        // Even when a browser asks for HTML
+        let req = Request::get("/missing.js")
+            .header(ACCEPT, "text/html")
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state, "missing.js".into(), req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_is_navigation() {
+        let accept = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        let none = HeaderMap::new();
+        assert!(is_navigation("/app/route", &none, &[]));
+        assert!(is_navigation("/app/route", &accept("*/*"), &[]));
+        assert!(is_navigation("/docs/v1.2", &accept("text/html,*/*;q=0.8"), &[]));
+        assert!(!is_navigation("/app/route", &accept("application/json"), &[]));
+        assert!(!is_navigation("/docs/v1.2", &accept("*/*"), &[]));
+        assert!(!is_navigation("/missing.js", &accept("text/html"), &[]));
+        assert!(!is_navigation("/logo.svg", &none, &[]));
+        // Custom asset extensions replace the built-in list
+        let exts = ["wasm".to_string()];
+        assert!(!is_navigation("/app.wasm", &accept("text/html"), &exts));
+        assert!(is_navigation("/missing.js", &accept("text/html"), &exts));
+    }
+
+    #[test]
+    fn test_has_unsafe_segment() {
+        assert!(has_unsafe_segment("index.html\0.txt"));
+        assert!(has_unsafe_segment("a\nb"));
+        assert!(has_unsafe_segment("index.html."));
+        assert!(has_unsafe_segment("dir /index.html"));
+        assert!(has_unsafe_segment("a/.../b"));
+        assert!(has_unsafe_segment(". ./b"));
+        assert!(!has_unsafe_segment(""));
+        assert!(!has_unsafe_segment("a/.well-known/b.txt"));
+        assert!(!has_unsafe_segment("my file.html"));
+        assert!(!has_unsafe_segment("../etc/passwd"));
+    }
+
+    #[tokio::test]
+    async fn test_unsafe_segment_is_bad_request() {
+        let root = temp_root("unsafe-segment");
+        std::fs::write(root.join("index.html"), "home").unwrap();
+        let state = state_with(root, false);
+        let res = serve_static(state.clone(), "index.html.".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = serve_static(state, "index.html".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_archive_download() {
+        let root = temp_root("archive-download");
+        std::fs::create_dir_all(root.join("share")).unwrap();
+        std::fs::write(root.join("share/index.html"), "index").unwrap();
+        std::fs::write(root.join("share/a.txt"), "alpha").unwrap();
+
+        // Disabled by default: the query is ignored
+        let state = state_with(root.clone(), false);
+        let req = get("/share/?download=zip");
+        let res = serve_static(state, "share/".into(), req).await;
+        assert_eq!(res.headers().get("content-type").unwrap(), "text/html; charset=utf-8");
+
+        let mut cfg = Config::default();
+        cfg.server.archive_download = true;
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+        let res = serve_static(state, "share/".into(), get("/share/?download=zip")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("content-type").unwrap(), "application/zip");
+        assert_eq!(
+            res.headers().get(CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"share.zip\""
+        );
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+        assert_eq!(archive.len(), 2);
+    }
+
+    #[test]
+    fn test_accept_position() {
+        let accept = "image/avif,image/webp;q=0.9,*/*;q=0.8";
+        assert_eq!(accept_position(accept, "image/webp"), Some(1));
+        assert_eq!(accept_position(accept, "image/avif"), Some(0));
+        assert_eq!(accept_position(accept, "image/png"), None);
+        assert_eq!(accept_position("image/webp;q=0", "image/webp"), None);
+    }
+
+    #[tokio::test]
+    async fn test_accept_variants() {
+        let root = temp_root("accept-variants");
+        std::fs::write(root.join("photo.jpg"), "jpeg").unwrap();
+        std::fs::write(root.join("photo.jpg.webp"), "webp").unwrap();
+        std::fs::write(root.join("plain.jpg"), "jpeg").unwrap();
+        let mut cfg = Config::default();
+        cfg.server
+            .accept_variants
+            .insert("image/webp".into(), ".webp".into());
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+        let with_accept = |accept: &str| {
+            Request::builder()
+                .uri("/photo.jpg")
+                .header(ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let req = with_accept("image/webp,*/*");
+        let res = serve_static(state.clone(), "photo.jpg".into(), req).await;
+        assert_eq!(res.headers().get("content-type").unwrap(), "image/webp");
+        assert_eq!(res.headers().get(VARY).unwrap(), "accept");
+
+        let req = with_accept("image/png,*/*");
+        let res = serve_static(state.clone(), "photo.jpg".into(), req).await;
+        assert_eq!(res.headers().get("content-type").unwrap(), "image/jpeg");
+        assert_eq!(res.headers().get(VARY).unwrap(), "accept");
+
+        // Files without a variant don't vary
+        let res = serve_static(state, "plain.jpg".into(), with_accept("image/webp")).await;
+        assert_eq!(res.headers().get("content-type").unwrap(), "image/jpeg");
+        assert!(res.headers().get(VARY).is_none());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-4", 10), Ok(Some((0, 4))));
+        assert_eq!(parse_range("bytes=5-", 10), Ok(Some((5, 9))));
+        assert_eq!(parse_range("bytes=-3", 10), Ok(Some((7, 9))));
+        assert_eq!(parse_range("bytes=-30", 10), Ok(Some((0, 9))));
+        assert_eq!(parse_range("bytes=8-20", 10), Ok(Some((8, 9))));
+        assert_eq!(parse_range("bytes=10-", 10), Err(()));
+        assert_eq!(parse_range("bytes=-0", 10), Err(()));
+        assert_eq!(parse_range("bytes=5-2", 10), Ok(None));
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), Ok(None));
+        assert_eq!(parse_range("items=0-1", 10), Ok(None));
+    }
+
+    fn ranged(uri: &str, range: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .header(RANGE, range)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_range_requests() {
+        let root = temp_root("range");
+        std::fs::write(root.join("data.txt"), "0123456789").unwrap();
+        let state = state_with(root, false);
+        let body = |res: Response| async move {
+            let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let res = serve_static(state.clone(), "data.txt".into(), get("/data.txt")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+
+        // Single range
+        let req = ranged("/data.txt", "bytes=2-5");
+        let res = serve_static(state.clone(), "data.txt".into(), req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(CONTENT_RANGE).unwrap(), "bytes 2-5/10");
+        assert_eq!(res.headers().get(CONTENT_LENGTH).unwrap(), "4");
+        assert_eq!(body(res).await, "2345");
+
+        // Suffix range
+        let req = ranged("/data.txt", "bytes=-3");
+        let res = serve_static(state.clone(), "data.txt".into(), req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(CONTENT_RANGE).unwrap(), "bytes 7-9/10");
+        assert_eq!(body(res).await, "789");
+
+        // Unsatisfiable range
+        let req = ranged("/data.txt", "bytes=20-");
+        let res = serve_static(state.clone(), "data.txt".into(), req).await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers().get(CONTENT_RANGE).unwrap(), "bytes */10");
+
+        // If-Range can't be validated yet: full body
+        let mut req = ranged("/data.txt", "bytes=2-5");
+        req.headers_mut()
+            .insert(IF_RANGE, HeaderValue::from_static("\"abc\""));
+        let res = serve_static(state, "data.txt".into(), req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(res).await, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_etag_revalidation() {
+        let root = temp_root("etag");
+        std::fs::write(root.join("app.js"), "let a = 1;").unwrap();
+        let state = state_with(root, false);
+
+        let res = serve_static(state.clone(), "app.js".into(), get("/app.js")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers().get(ETAG).unwrap().to_str().unwrap().to_string();
+        assert!(etag.starts_with("W/\"10-"));
+
+        let req = Request::builder()
+            .uri("/app.js")
+            .header(IF_NONE_MATCH, format!("\"other\", {etag}"))
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state.clone(), "app.js".into(), req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(ETAG).unwrap(), etag.as_str());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+
+        let req = Request::builder()
+            .uri("/app.js")
+            .header(IF_NONE_MATCH, "W/\"stale\"")
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state.clone(), "app.js".into(), req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut cfg = (*state.cfg).clone();
+        cfg.assets.cache.etag = false;
+        let state = AppState { cfg: Arc::new(cfg), ..state };
+        let res = serve_static(state, "app.js".into(), get("/app.js")).await;
+        assert!(res.headers().get(ETAG).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_siblings() {
+        let root = temp_root("precompressed");
+        std::fs::write(root.join("app.js"), "plain").unwrap();
+        std::fs::write(root.join("app.js.br"), "brotli").unwrap();
+        std::fs::write(root.join("app.js.gz"), "gzipped").unwrap();
+        std::fs::write(root.join("style.css"), "plain").unwrap();
+        std::fs::write(root.join("style.css.gz"), "gzipped").unwrap();
+        let mut cfg = Config::default();
+        cfg.assets.precompressed = true;
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+        let request = |uri: &str, accept: &str| {
+            Request::builder()
+                .uri(uri)
+                .header(ACCEPT_ENCODING, accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let body = |res: Response| async move {
+            let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        // Brotli preferred when both are accepted
+        let req = request("/app.js", "gzip, deflate, br");
+        let res = serve_static(state.clone(), "app.js".into(), req).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "br");
+        assert_eq!(res.headers().get(VARY).unwrap(), "accept-encoding, range");
+        assert_eq!(res.headers().get("content-type").unwrap(), "text/javascript; charset=utf-8");
+        assert_eq!(body(res).await, "brotli");
+
+        // Gzip when brotli is not accepted
+        let req = request("/app.js", "gzip");
+        let res = serve_static(state.clone(), "app.js".into(), req).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(body(res).await, "gzipped");
+
+        // Gzip when only the .gz sibling exists
+        let req = request("/style.css", "br, gzip");
+        let res = serve_static(state.clone(), "style.css".into(), req).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(res.headers().get("content-type").unwrap(), "text/css; charset=utf-8");
+        assert_eq!(body(res).await, "gzipped");
+
+        // Identity when nothing matching is accepted
+        let req = request("/app.js", "deflate");
+        let res = serve_static(state, "app.js".into(), req).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(VARY).unwrap(), "accept-encoding, range");
+        assert_eq!(body(res).await, "plain");
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_range() {
+        use tower::ServiceExt;
+
+        let root = temp_root("precompressed-range");
+        std::fs::write(root.join("app.js"), "plain text").unwrap();
+        std::fs::write(root.join("app.js.br"), "brotli").unwrap();
+        let mut cfg = Config::default();
+        cfg.assets.precompressed = true;
+        cfg.compression.enable = true;
+        let comp = crate::router::build_compression(&cfg).unwrap();
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+        // Behind the compression layer, which must leave the range alone
+        let app = crate::router::mount_static_route(axum::Router::new(), &state, "/").layer(comp);
+        let request = |accept: &str, range: &str| {
+            Request::builder()
+                .uri("/app.js")
+                .header(ACCEPT_ENCODING, accept)
+                .header(RANGE, range)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = app.clone().oneshot(request("br, gzip", "bytes=1-3")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 1-3/6");
+        assert_eq!(res.headers()[CONTENT_LENGTH], "3");
+        assert_eq!(res.headers()[VARY], "accept-encoding, range");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"rot");
+
+        // Unsatisfiable ranges are judged against the compressed length
+        let res = app.clone().oneshot(request("br", "bytes=8-")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes */6");
+
+        // Without a matching encoding the range applies to the plain file
+        let res = app.oneshot(request("deflate", "bytes=6-")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 6-9/10");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"text");
+    }
+
+    #[tokio::test]
+    async fn test_memcache() {
+        let root = temp_root("memcache");
+        let path = root.join("hot.txt");
+        std::fs::write(&path, "first").unwrap();
+        let mut state = state_with(root, false);
+        state.memcache = Some(Arc::new(FileCache::new(1 << 20, 1 << 10)));
+        let body = |state: AppState| async move {
+            let res = serve_static(state, "hot.txt".into(), get("/hot.txt")).await;
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/plain; charset=utf-8");
+            let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+        let set_modified = |time: SystemTime| {
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(time).unwrap();
+        };
+        assert_eq!(body(state.clone()).await, "first");
+
+        // Rewritten with the same size and mtime, the file is still served from memory
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, "fresh").unwrap();
+        set_modified(modified);
+        assert_eq!(body(state.clone()).await, "first");
+
+        // A new mtime invalidates the entry
+        set_modified(modified + std::time::Duration::from_secs(1));
+        assert_eq!(body(state.clone()).await, "fresh");
+        assert_eq!(state.memcache.unwrap().bytes(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_memcache_precompressed_sibling() {
+        let root = temp_root("memcache-sibling");
+        std::fs::write(root.join("app.js"), "plain").unwrap();
+        std::fs::write(root.join("app.js.gz"), "gzipped").unwrap();
+        let state = || {
+            let mut cfg = Config::default();
+            cfg.assets.precompressed = true;
+            AppState {
+                cfg: Arc::new(cfg),
+                memcache: Some(Arc::new(FileCache::new(1 << 20, 1 << 10))),
+                ..state_with(root.clone(), false)
+            }
+        };
+        let sibling = |state: AppState| async move {
+            let req = Request::builder()
+                .uri("/app.js")
+                .header(ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+            let res = serve_static(state, "app.js".into(), req).await;
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/javascript; charset=utf-8");
+            assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        };
+        let direct = |state: AppState| async move {
+            let res = serve_static(state, "app.js.gz".into(), get("/app.js.gz")).await;
+            assert_eq!(res.headers()[CONTENT_TYPE], "application/gzip");
+            assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        };
+
+        // Both names share one cache entry; each keeps its own headers
+        let state1 = state();
+        direct(state1.clone()).await;
+        sibling(state1.clone()).await;
+        assert_eq!(state1.memcache.unwrap().bytes(), 7);
+        let state2 = state();
+        sibling(state2.clone()).await;
+        direct(state2.clone()).await;
+        assert_eq!(state2.memcache.unwrap().bytes(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_mime_overrides() {
+        let root = temp_root("mime-overrides");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/README.md"), "# Docs").unwrap();
+        std::fs::write(root.join("level.data"), "data").unwrap();
+        std::fs::write(root.join("level.data.gz"), "gzipped").unwrap();
+        std::fs::write(root.join("style.css"), "body {}").unwrap();
+        let mut cfg = Config::default();
+        cfg.server.index = "README.md".into();
+        cfg.server.mime_overrides.insert("md".into(), "text/plain".into());
+        cfg.server.mime_overrides.insert(".data".into(), "application/x-level".into());
+        cfg.assets.precompressed = true;
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+        let content_type = |res: Response| res.headers()[CONTENT_TYPE].clone();
+
+        let req = get("/docs/README.md");
+        let res = serve_static(state.clone(), "docs/README.md".into(), req).await;
+        assert_eq!(content_type(res), "text/plain; charset=utf-8");
+        // Index files
+        let res = serve_static(state.clone(), "docs/".into(), get("/docs/")).await;
+        assert_eq!(content_type(res), "text/plain; charset=utf-8");
+        // Precompressed variants keep the original file's type
+        let req = Request::builder()
+            .uri("/level.data")
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state.clone(), "level.data".into(), req).await;
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(content_type(res), "application/x-level");
+        // Other extensions are still guessed
+        let res = serve_static(state, "style.css".into(), get("/style.css")).await;
+        assert_eq!(content_type(res), "text/css; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_default_mime() {
+        let root = temp_root("default-mime");
+        std::fs::write(root.join("LICENSE"), "MIT").unwrap();
+        std::fs::write(root.join("index.html"), "<p>hi</p>").unwrap();
+        let state = state_with(root.clone(), false);
+        let res = serve_static(state, "LICENSE".into(), get("/LICENSE")).await;
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/octet-stream");
+
+        let mut cfg = Config::default();
+        cfg.server.default_mime = "text/plain".into();
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+        let res = serve_static(state.clone(), "LICENSE".into(), get("/LICENSE")).await;
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/plain; charset=utf-8");
+        // Known extensions are unaffected
+        let res = serve_static(state, "index.html".into(), get("/index.html")).await;
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_error_pages() {
+        let root = temp_root("error-pages");
+        std::fs::write(root.join("404.html"), "<h1>Lost</h1>").unwrap();
+        let mut cfg = Config::default();
+        cfg.server.error_pages.insert(404, "/404.html".into());
+        cfg.server.error_pages.insert(500, "50x.html".into());
+        let state = AppState {
+            cfg: Arc::new(cfg),
+            ..state_with(root, false)
+        };
+
+        let res = serve_static(state.clone(), "missing.txt".into(), get("/missing.txt")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"<h1>Lost</h1>");
+
+        // Configured but missing page: plain status response
+        let res = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        let res = error_page(&state, res, false).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(res.headers().get(CONTENT_TYPE).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_index_candidates() {
+        let root = temp_root("index-candidates");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/index.htm"), "htm").unwrap();
+        let mut state = state_with(root.clone(), false);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.index = IndexFiles(vec!["index.html".into(), "index.htm".into()]);
+        state.cfg = Arc::new(cfg);
+        let body = |res: Response| async move {
+            axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap()
+        };
+
+        let res = serve_static(state.clone(), "sub".into(), get("/sub/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(&body(res).await[..], b"htm");
+
+        // Earlier candidates win, and directories among them are skipped
+        std::fs::create_dir_all(root.join("sub/index.html")).unwrap();
+        let res = serve_static(state.clone(), "sub".into(), get("/sub/")).await;
+        assert_eq!(&body(res).await[..], b"htm");
+        std::fs::remove_dir(root.join("sub/index.html")).unwrap();
+        std::fs::write(root.join("sub/index.html"), "html").unwrap();
+        let res = serve_static(state.clone(), "sub".into(), get("/sub/")).await;
+        assert_eq!(&body(res).await[..], b"html");
+
+        // With the default `index.html` alone, index.htm is not an index
+        std::fs::remove_file(root.join("sub/index.html")).unwrap();
+        let res = serve_static(state_with(root, false), "sub".into(), get("/sub/")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_index_is_directory_auto_index() {
+        let root = temp_root("index-dir-auto");
+        std::fs::create_dir_all(root.join("sub/index.html")).unwrap();
+        let res = serve_static(state_with(root, true), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/html; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_is_directory_no_auto_index() {
+        let root = temp_root("index-dir-404");
+        std::fs::create_dir_all(root.join("sub/index.html")).unwrap();
+        let res = serve_static(state_with(root, false), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_index_symlink_to_file_is_served() {
+        let root = temp_root("index-link-file");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("real.html"), "hello").unwrap();
+        std::os::unix::fs::symlink(root.join("real.html"), root.join("sub/index.html")).unwrap();
+        let res = serve_static(state_with(root, true), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_index_symlink_to_directory() {
+        let root = temp_root("index-link-dir");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::create_dir_all(root.join("other")).unwrap();
+        std::os::unix::fs::symlink(root.join("other"), root.join("sub/index.html")).unwrap();
+
+        let res = serve_static(state_with(root.clone(), true), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = serve_static(state_with(root, false), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_index_dangling_symlink() {
+        let root = temp_root("index-link-dangling");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::os::unix::fs::symlink(root.join("missing"), root.join("sub/index.html")).unwrap();
+
+        let res = serve_static(state_with(root.clone(), true), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = serve_static(state_with(root, false), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_escaping_root() {
+        let outside = temp_root("symlink-outside");
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        let root = temp_root("symlink-root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("inside.txt"), "inside").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("leak.txt")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("leakdir")).unwrap();
+        std::os::unix::fs::symlink(root.join("inside.txt"), root.join("alias.txt")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("sub/index.html"))
+            .unwrap();
+
+        let state = state_with(root.clone(), true);
+        let serve = |tail: &str| serve_static(state.clone(), tail.into(), get(&format!("/{tail}")));
+        assert_eq!(serve("leak.txt").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(serve("leakdir/secret.txt").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(serve("leakdir").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(serve("sub").await.status(), StatusCode::FORBIDDEN);
+        // Symlinks staying inside the root are still served
+        let res = serve("alias.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"inside");
+
+        let mut state = state_with(root, true);
+        Arc::make_mut(&mut state.cfg).server.follow_symlinks = true;
+        for tail in ["leak.txt", "leakdir/secret.txt", "sub"] {
+            let res = serve_static(state.clone(), tail.into(), get(&format!("/{tail}"))).await;
+            assert_eq!(res.status(), StatusCode::OK, "{tail}");
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(&body[..], b"secret");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinked_sibling_escaping_root() {
+        let outside = temp_root("sibling-outside");
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        let root = temp_root("sibling-root");
+        std::fs::write(root.join("app.js"), "plain").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("app.js.gz")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("bot.html")).unwrap();
+        let mut cfg = Config::default();
+        cfg.assets.precompressed = true;
+        cfg.server.ua_rules = vec![UaRule {
+            pattern: "bot".into(),
+            serve: "/bot.html".into(),
+        }];
+        let state = AppState { cfg: Arc::new(cfg), ..state_with(root, false) };
+
+        let req = Request::builder()
+            .uri("/app.js")
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state.clone(), "app.js".into(), req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let req = Request::builder()
+            .uri("/app.js")
+            .header(USER_AGENT, "bot")
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state, "app.js".into(), req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_preload_links_on_html_only() {
+        let root = temp_root("preload");
+        std::fs::write(root.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(root.join("app.css"), "body{}").unwrap();
+        let mut cfg = Config::default();
+        cfg.assets.preload = vec![
+            crate::config::Preload {
+                path: "/app.css".into(),
+                kind: "style".into(),
+            },
+            crate::config::Preload {
+                path: "/app.js".into(),
+                kind: "script".into(),
+            },
+        ];
+        let state = AppState { root, ..test_state(cfg) };
+
+        let res = serve_static(state.clone(), "".into(), get("/")).await;
+        let links: Vec<_> = res.headers().get_all(LINK).iter().collect();
+        assert_eq!(
+            links,
+            vec![
+                "</app.css>; rel=preload; as=style",
+                "</app.js>; rel=preload; as=script"
+            ]
+        );
+
+        let res = serve_static(state, "app.css".into(), get("/app.css")).await;
+        assert!(res.headers().get(LINK).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deny_paths() {
+        let root = temp_root("deny-paths");
+        std::fs::write(root.join("index.php"), "<?php").unwrap();
+        std::fs::write(root.join("secret.txt"), "x").unwrap();
+        let mut state = state_with(root, false);
+        state.deny_paths = Arc::new(vec![
+            (regex::Regex::new(r"\.php$").unwrap(), StatusCode::NOT_FOUND),
+            (regex::Regex::new("^/secret").unwrap(), StatusCode::FORBIDDEN),
+        ]);
+
+        let res = serve_static(state.clone(), "index.php".into(), get("/index.php")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = serve_static(state.clone(), "secret.txt".into(), get("/secret.txt")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // Rules apply to the decoded path that is actually served
+        let res = serve_static(state.clone(), "index.php".into(), get("/index.ph%70")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = serve_static(state.clone(), "secret.txt".into(), get("/%73ecret.txt")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = serve_static(state, "secret.txt".into(), get("//./secret.txt")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_force_auto_index() {
+        let root = temp_root("force-auto-index");
+        std::fs::write(root.join("index.html"), "index").unwrap();
+
+        // Index wins by default
+        let res = serve_static(state_with(root.clone(), true), "".into(), get("/")).await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"index");
+
+        // ?index=off renders the listing instead
+        let res = serve_static(state_with(root.clone(), true), "".into(), get("/?index=off")).await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Index of /"));
+
+        // ...but only when auto_index is enabled
+        let state = state_with(root.clone(), false);
+        let res = serve_static(state, "".into(), get("/?index=off")).await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"index");
+
+        // force_auto_index always renders the listing
+        let mut state = state_with(root, true);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.force_auto_index = true;
+        state.cfg = Arc::new(cfg);
+        let res = serve_static(state, "".into(), get("/")).await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Index of /"));
+    }
+
+    #[tokio::test]
+    async fn test_allowed_methods() {
+        let mut cfg = Config::default();
+        cfg.server.allowed_methods = ["GET", "head", "OPTIONS", "PUT"]
+            .iter()
+            .map(|m| m.to_string())
+            .collect();
+        let state = test_state(cfg);
+        let req = |method: Method| {
+            Request::builder()
+                .method(method)
+                .uri("/")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = serve_static(state.clone(), "".into(), req(Method::OPTIONS)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers().get(ALLOW).unwrap(), "GET, HEAD, OPTIONS, PUT");
+
+        let res = serve_static(state.clone(), "".into(), req(Method::PUT)).await;
+        assert_eq!(res.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let res = serve_static(state, "".into(), req(Method::DELETE)).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(ALLOW).unwrap(), "GET, HEAD, OPTIONS, PUT");
+    }
+
+    #[tokio::test]
+    async fn test_render_directory_listing_empty() {
+        let root = temp_root("listing-empty");
+        let sub = root.join("sub");
+        std::fs::create_dir_all(&sub).unwrap();
+        let items = read_listing(&sub, ListingSort::Name, Dotfiles::Ignore).await.unwrap();
+        let html = render_directory_listing(&items, "sub", "Nothing <here>", LISTING_TEMPLATE);
+        assert!(html.contains("<a href=\"/\">..</a>"));
+        assert!(html.contains("<em>Nothing &lt;here&gt;</em>"));
+
+        std::fs::write(root.join("sub/file.txt"), "x").unwrap();
+        let items = read_listing(&sub, ListingSort::Name, Dotfiles::Ignore).await.unwrap();
+        let html = render_directory_listing(&items, "sub", "Nothing <here>", LISTING_TEMPLATE);
+        assert!(!html.contains("Nothing"));
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing_json() {
+        let root = temp_root("listing-json");
+        std::fs::create_dir_all(root.join("docs/assets")).unwrap();
+        std::fs::write(root.join("docs/readme.txt"), "hello").unwrap();
+        let state = state_with(root, true);
+
+        let req = Request::builder()
+            .uri("/docs/")
+            .header(ACCEPT, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state.clone(), "docs/".into(), req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "application/json");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let entries: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["name"], "assets");
+        assert_eq!(entries[0]["is_dir"], true);
+        assert_eq!(entries[1]["name"], "readme.txt");
+        assert_eq!(entries[1]["is_dir"], false);
+        assert_eq!(entries[1]["size"], 5);
+        assert!(entries[1]["modified"].as_str().unwrap().ends_with('Z'));
+
+        // Browsers still get HTML
+        let req = Request::builder()
+            .uri("/docs/")
+            .header(ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8")
+            .body(Body::empty())
+            .unwrap();
+        let res = serve_static(state, "docs/".into(), req).await;
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dotfiles() {
+        let root = temp_root("dotfiles");
+        std::fs::create_dir_all(root.join(".hidden")).unwrap();
+        std::fs::create_dir_all(root.join(".well-known")).unwrap();
+        std::fs::write(root.join(".env"), "SECRET=1").unwrap();
+        std::fs::write(root.join(".hidden/page.html"), "hidden").unwrap();
+        std::fs::write(root.join(".well-known/security.txt"), "contact").unwrap();
+        std::fs::write(root.join("visible.txt"), "visible").unwrap();
+
+        let with_mode = |dotfiles| {
+            let mut state = state_with(root.clone(), true);
+            Arc::make_mut(&mut state.cfg).server.dotfiles = dotfiles;
+            state
+        };
+        let status = |state: AppState, tail: &str| {
+            let req = get(&format!("/{tail}"));
+            let tail = tail.to_string();
+            async move { serve_static(state, tail, req).await.status() }
+        };
+        let listing = |state: AppState| async move {
+            let res = serve_static(state, "".into(), get("/")).await;
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        for (mode, expected) in [
+            (Dotfiles::Ignore, StatusCode::NOT_FOUND),
+            (Dotfiles::Deny, StatusCode::FORBIDDEN),
+            (Dotfiles::Allow, StatusCode::OK),
+        ] {
+            for tail in [".env", ".hidden/page.html"] {
+                assert_eq!(status(with_mode(mode), tail).await, expected, "{mode:?} {tail}");
+            }
+            // A listing of the hidden directory itself
+            let dir_status = status(with_mode(mode), ".hidden/").await;
+            assert_eq!(dir_status, expected, "{mode:?} .hidden/");
+            assert_eq!(status(with_mode(mode), ".well-known/security.txt").await, StatusCode::OK);
+
+            let html = listing(with_mode(mode)).await;
+            assert!(html.contains("visible.txt"));
+            let shown = mode == Dotfiles::Allow;
+            assert_eq!(html.contains(".env"), shown, "{mode:?}");
+            assert_eq!(html.contains(".hidden"), shown, "{mode:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing_etag() {
+        let root = temp_root("listing-etag");
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        let state = state_with(root.clone(), true);
+        let request = |inm: Option<&str>, accept: &str| {
+            let mut req = Request::builder().uri("/").header(ACCEPT, accept);
+            if let Some(inm) = inm {
+                req = req.header(IF_NONE_MATCH, inm);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        let res = serve_static(state.clone(), "".into(), request(None, "text/html")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers().get(ETAG).unwrap().to_str().unwrap().to_string();
+        assert!(etag.starts_with("W/\""));
+
+        let res = serve_static(state.clone(), "".into(), request(Some(&etag), "text/html")).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(ETAG).unwrap(), etag.as_str());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+
+        // The JSON representation has its own validator
+        let json = "application/json";
+        let res = serve_static(state.clone(), "".into(), request(Some(&etag), json)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.headers().get(ETAG).unwrap(), etag.as_str());
+
+        // A new entry changes the listing
+        std::fs::write(root.join("b.txt"), "b").unwrap();
+        let res = serve_static(state, "".into(), request(Some(&etag), "text/html")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.headers().get(ETAG).unwrap(), etag.as_str());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(4300), "4.2 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+
+    #[tokio::test]
+    async fn test_render_directory_listing_details() {
+        let root = temp_root("listing-details");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("data.bin"), vec![0u8; 4300]).unwrap();
+        let items = read_listing(&root, ListingSort::Name, Dotfiles::Ignore).await.unwrap();
+        let html = render_directory_listing(&items, "", "", LISTING_TEMPLATE);
+        assert!(html.contains("<span class=\"size\">4.2 KiB</span>"));
+        assert!(html.contains("<a href=\"/sub/\">sub</a><span class=\"size\">-</span>"));
+        let date = format_listing_date(SystemTime::now());
+        assert!(html.contains(&format!("<span class=\"date\">{}", &date[..10])));
+    }
+
+    #[tokio::test]
+    async fn test_listing_sort() {
+        let root = temp_root("listing-sort");
+        std::fs::create_dir_all(root.join("zdir")).unwrap();
+        let now = SystemTime::now();
+        for (name, size, age) in [("a.txt", 30, 20), ("b.txt", 10, 0), ("c.txt", 20, 10)] {
+            let path = root.join(name);
+            std::fs::write(&path, vec![b'x'; size]).unwrap();
+            let mtime = now - std::time::Duration::from_secs(age * 60);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+        let names = |sort| {
+            let root = root.clone();
+            async move {
+                read_listing(&root, sort, Dotfiles::Ignore)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|(name, ..)| name)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(names(ListingSort::Name).await, ["zdir", "a.txt", "b.txt", "c.txt"]);
+        assert_eq!(names(ListingSort::NameDesc).await, ["zdir", "c.txt", "b.txt", "a.txt"]);
+        assert_eq!(names(ListingSort::Size).await, ["zdir", "b.txt", "c.txt", "a.txt"]);
+        assert_eq!(
+            names(ListingSort::ModifiedDesc).await,
+            ["zdir", "b.txt", "c.txt", "a.txt"]
+        );
+        assert_eq!(names(ListingSort::Modified).await, ["zdir", "a.txt", "c.txt", "b.txt"]);
+
+        let sort: ListingSort = serde_yaml::from_str("modified_desc").unwrap();
+        assert_eq!(sort, ListingSort::ModifiedDesc);
+    }
+
+    #[tokio::test]
+    async fn test_ua_rules() {
+        let root = temp_root("ua-rules");
+        std::fs::write(root.join("index.html"), "app").unwrap();
+        std::fs::write(root.join("bot.html"), "prerendered").unwrap();
+        let mut cfg = Config::default();
+        cfg.server.ua_rules = vec![UaRule {
+            pattern: "Googlebot".into(),
+            serve: "/bot.html".into(),
+        }];
+        let state = AppState { root, ..test_state(cfg) };
+        let req = |ua: &str| {
+            Request::builder()
+                .uri("/")
+                .header(USER_AGENT, ua)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let bot = req("Mozilla/5.0 (compatible; googlebot/2.1)");
+        let res = serve_static(state.clone(), "".into(), bot).await;
+        assert_eq!(res.headers().get(VARY).unwrap(), "user-agent");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"prerendered");
+
+        let res = serve_static(state, "".into(), req("Mozilla/5.0 Firefox")).await;
+        assert_eq!(res.headers().get(VARY).unwrap(), "user-agent");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"app");
+    }
+
+    #[tokio::test]
+    async fn test_traversal_mode_canonical() {
+        let root = temp_root("traversal-canonical");
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("b.txt"), "b").unwrap();
+        std::fs::write(root.join("a/..weird..name"), "w").unwrap();
+        let mut state = state_with(root.clone(), false);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.traversal_mode = TraversalMode::Canonical;
+        // `..weird..name` would otherwise be hidden as a dotfile
+        cfg.server.dotfiles = Dotfiles::Allow;
+        state.cfg = Arc::new(cfg);
+
+        // ".." that stays within the root is resolved
+        let res = serve_static(state.clone(), "a/../b.txt".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        // Dots inside names are fine
+        let res = serve_static(state.clone(), "a/..weird..name".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        // Leaving and re-entering the root is fine, only the final path matters
+        let name = root.file_name().unwrap().to_string_lossy().to_string();
+        let res = serve_static(state.clone(), format!("../{name}/b.txt"), get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        // Escapes are blocked
+        let res = serve_static(state.clone(), "../../etc/passwd".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = serve_static(state.clone(), "a/../../".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // Whether a file outside the root exists is not revealed
+        let outside = temp_root("traversal-canonical-outside-probe");
+        std::fs::write(outside.join("secret.txt"), "s").unwrap();
+        let outside = outside.file_name().unwrap().to_string_lossy().to_string();
+        for file in ["secret.txt", "nothere.txt"] {
+            let tail = format!("../{outside}/{file}");
+            let res = serve_static(state.clone(), tail, get("/")).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{file}");
+        }
+        // Missing files are 404
+        let res = serve_static(state, "missing.txt".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_traversal_mode_canonical_blocks_symlink_escape() {
+        let root = temp_root("traversal-canonical-link");
+        let outside = temp_root("traversal-canonical-outside");
+        std::fs::write(outside.join("secret.txt"), "s").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        let mut state = state_with(root, false);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.traversal_mode = TraversalMode::Canonical;
+        state.cfg = Arc::new(cfg);
+
+        let res = serve_static(state, "link/secret.txt".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_ssi() {
+        let root = temp_root("ssi");
+        std::fs::write(root.join("header.html"), "<h1>Hi</h1>").unwrap();
+        let page = r#"<!--#include file="header.html" --><p>body</p>"#;
+        std::fs::write(root.join("page.html"), page).unwrap();
+        std::fs::write(root.join("page.txt"), page).unwrap();
+        let mut state = state_with(root, false);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.ssi = true;
+        state.cfg = Arc::new(cfg);
+
+        let res = serve_static(state.clone(), "page.html".into(), get("/page.html")).await;
+        assert_eq!(res.headers().get(CONTENT_LENGTH).unwrap(), "22");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"<h1>Hi</h1><p>body</p>");
+
+        // Non-HTML files are served unchanged
+        let res = serve_static(state, "page.txt".into(), get("/page.txt")).await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], page.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_ssi_no_etag() {
+        let root = temp_root("ssi-etag");
+        std::fs::write(root.join("header.html"), "<h1>Hi</h1>").unwrap();
+        let page = r#"<!--#include file="header.html" -->"#;
+        std::fs::write(root.join("page.html"), page).unwrap();
+        std::fs::write(root.join("page.txt"), page).unwrap();
+        let mut state = state_with(root.clone(), false);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.ssi = true;
+        state.cfg = Arc::new(cfg);
+
+        // Expanded pages carry no ETag, so If-None-Match can't return a stale 304
+        let res = serve_static(state.clone(), "page.html".into(), get("/page.html")).await;
+        assert!(res.headers().get(ETAG).is_none());
+        let req = Request::builder()
+            .uri("/page.html")
+            .header(IF_NONE_MATCH, "*")
+            .body(Body::empty())
+            .unwrap();
+        std::fs::write(root.join("header.html"), "<h1>Bye</h1>").unwrap();
+        let res = serve_static(state.clone(), "page.html".into(), req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"<h1>Bye</h1>");
+
+        // Files without includes keep theirs
+        let res = serve_static(state, "page.txt".into(), get("/page.txt")).await;
+        assert!(res.headers().get(ETAG).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_index_headers_only_on_listings() {
+        let root = temp_root("index-headers");
+        std::fs::write(root.join("file.txt"), "x").unwrap();
+        let mut state = state_with(root, true);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server
+            .index_headers
+            .insert("X-Robots-Tag".into(), "noindex".into());
+        state.cfg = Arc::new(cfg);
+
+        let res = serve_static(state.clone(), "".into(), get("/")).await;
+        assert_eq!(res.headers().get("x-robots-tag").unwrap(), "noindex");
+        let res = serve_static(state, "file.txt".into(), get("/file.txt")).await;
+        assert!(res.headers().get("x-robots-tag").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_empty_root_page() {
+        let root = temp_root("empty-root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        let state = state_with(root.clone(), false);
+
+        let res = serve_static(state.clone(), "".into(), get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("statiker is running"));
+
+        // Only the root gets the page
+        let res = serve_static(state.clone(), "sub".into(), get("/sub")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // Custom page
+        let custom = root.join("..").join("statiker-custom-empty.html");
+        std::fs::write(&custom, "custom").unwrap();
+        let mut state = state;
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.empty_root_page = Some(custom);
+        state.cfg = Arc::new(cfg);
+        let res = serve_static(state, "".into(), get("/")).await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"custom");
+    }
+
+    #[tokio::test]
+    async fn test_listing_template() {
+        let root = temp_root("listing-template");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/{{title}}.txt"), "x").unwrap();
+        let template = root.join("..").join("statiker-listing-template.html");
+        std::fs::write(&template, "<main><h2>{{ title }}</h2><ol>{{entries}}</ol>{{other}}</main>")
+            .unwrap();
+        let mut state = state_with(root, true);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.listing_template = Some(template);
+        state.cfg = Arc::new(cfg);
+
+        let res = serve_static(state.clone(), "sub".into(), get("/sub/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8_lossy(&body);
+        assert!(html.starts_with("<main><h2>/sub</h2><ol><li><a href=\"/\">..</a></li>"));
+        // Placeholders in file names and unknown ones are left alone
+        assert!(html.contains(">{{title}}.txt</a>"));
+        assert!(html.ends_with("</ol>{{other}}</main>"));
+
+        // An unreadable template falls back to the built-in one
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.listing_template = Some("/nonexistent/listing.html".into());
+        state.cfg = Arc::new(cfg);
+        let res = serve_static(state, "sub".into(), get("/sub/")).await;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<h1>Index of /sub</h1>"));
+    }
+
+    #[tokio::test]
+    async fn test_client_hints_on_html() {
+        let root = temp_root("client-hints");
+        std::fs::write(root.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(root.join("app.js"), "").unwrap();
+        let mut state = state_with(root, false);
+        let mut cfg = (*state.cfg).clone();
+        cfg.server.client_hints = vec!["DPR".into(), "Width".into(), "Viewport-Width".into()];
+        state.cfg = Arc::new(cfg);
+
+        let res = serve_static(state.clone(), "".into(), get("/")).await;
+        assert_eq!(res.headers().get("accept-ch").unwrap(), "DPR, Width, Viewport-Width");
+        assert_eq!(res.headers().get(VARY).unwrap(), "DPR, Width, Viewport-Width");
+
+        let res = serve_static(state, "app.js".into(), get("/app.js")).await;
+        assert!(res.headers().get("accept-ch").is_none());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::config::Config;
+use anyhow::Context;
+use std::path::Path;
+
+/// Leading comment of the scaffolded file
+const HEADER: &str = "\
+# statiker configuration
+#
+# Generated by `statiker init` with the built-in defaults. Every setting is
+# optional; delete what you don't need. See docs/configuration.md for details.
+";
+
+/// Comments placed above keys, addressed by their dotted path
+const COMMENTS: &[(&str, &str)] = &[
+    ("server", "Listener and static file serving"),
+    ("server.root", "Directory files are served from"),
+    ("server.index", "File served for directory requests, or a list tried in order"),
+    ("server.auto_index", "Render a listing for directories without an index file"),
+    ("server.allowed_methods", "Methods accepted by static routes (GET, HEAD, OPTIONS)"),
+    ("server.traversal_mode", "How paths escaping the root are handled"),
+    ("server.dotfiles", "ignore (404), deny (403) or allow requests for .env, .git/ and co."),
+    ("server.shutdown_timeout", "Grace period for in-flight requests on SIGTERM/SIGINT"),
+    ("server.max_body_size", "Largest request body, e.g. 2MiB or 500KB; larger gets 413 (0 = off)"),
+    ("server.follow_symlinks", "Serve symlinks pointing outside root (403 when false)"),
+    ("server.ssi", "Expand server-side includes in HTML files"),
+    ("server.default_retry_after", "Retry-After added to 503 responses (0s = off)"),
+    ("server.mime_overrides", "Content-Type by file extension, e.g. wasm: application/wasm"),
+    ("server.trusted_proxies", "Only honor X-Forwarded-For from these peers, e.g. [10.0.0.0/8]"),
+    ("server.default_mime", "Content-Type for unknown extensions, e.g. text/plain"),
+    ("server.error_pages", "Custom pages by status code, e.g. 404: 404.html"),
+    ("server.listing_sort", "Listing order: name, size or modified, optionally with _desc"),
+    ("server.listing_template", "Listing HTML with {{title}} and {{entries}} placeholders"),
+    ("tls", "HTTPS; cert_path and key_path are PEM files"),
+    ("tls.self_signed", "Without cert/key files, serve a generated certificate (development only)"),
+    ("tls.reload_interval", "Check the cert/key for changes this often and reload them"),
+    ("tls.http_port", "Port of the plain HTTP listener serving http_root (default 80)"),
+    ("tls.http2", "Offer HTTP/2 next to HTTP/1.1 (ALPN)"),
+    ("tls.client_auth", "Mutual TLS: verify client certificates against ca_path"),
+    ("tls.client_auth.mode", "require, or optional to also accept clients without a certificate"),
+    ("routing", "Routes, matched in order; empty serves static files at /"),
+    ("proxy_client", "Upstream connections, shared by all proxy routes and forward_auth"),
+    ("proxy_client.http2", "Offer HTTP/2 to HTTPS upstreams (ALPN); plain HTTP stays HTTP/1.1"),
+    ("proxy_client.pool_idle_timeout", "Close pooled connections idle this long (0s = never)"),
+    ("proxy_client.connect_timeout", "Give up connecting to an upstream after this long (0s = OS)"),
+    ("spa", "Serve the fallback file for unknown paths (single page apps)"),
+    ("assets", "Static asset caching"),
+    ("assets.cache", "Cache-Control for css, js, images, fonts and media"),
+    ("assets.rules", "Per-path Cache-Control, e.g. {pattern: /static/**, cache_control: no-cache}"),
+    ("assets.extensions", "Extensions that get asset caching, e.g. [css, js, wasm]; [] = built-in"),
+    ("assets.precompressed", "Serve prebuilt .br/.gz siblings"),
+    ("assets.memcache", "Keep small files in memory (LRU, revalidated by mtime and size)"),
+    ("compression", "On-the-fly response compression"),
+    ("compression.level", "fastest, default, best, or a number (brotli 0-11, gzip 0-9)"),
+    ("security", "Access control and response headers"),
+    ("security.cors", "Cross-origin resource sharing"),
+    ("security.cors.max_age", "How long browsers may cache preflight responses (0s = no header)"),
+    ("security.rate_limit", "Per-IP rate limiting: requests per period"),
+    ("security.headers", "Headers added to every response"),
+    ("security.deny_paths", "Request paths to refuse, as regular expressions"),
+    ("security.forwarded_hops", "Trusted reverse proxies in front of statiker"),
+    ("security.basic_auth", "HTTP Basic authentication; users map names to bcrypt hashes"),
+    ("security.ip_filter", "Client IP / CIDR allowlist and denylist"),
+    ("obs", "Logging and health checks"),
+    ("obs.level", "debug, info, warn or error"),
+    ("obs.log_format", "text, or json for one JSON object per line"),
+    ("obs.filters", "Per-module log levels, e.g. statiker::proxy: warn"),
+    ("obs.health", "Health check endpoint for load balancers and probes"),
+    ("obs.access_log", "Apache-style access log: common or combined format, stdout or path"),
+    ("obs.request_id", "Tag requests with X-Request-Id, echoed and forwarded to upstreams"),
+];
+
+/// Default config as commented YAML
+///
+/// Unset optional settings (`null`) are left out; they stay unset when the
+/// file is parsed again.
+pub fn render() -> anyhow::Result<String> {
+    let yaml = serde_yaml::to_string(&Config::default())?;
+    let mut out = String::from(HEADER);
+    let mut path: Vec<(usize, &str)> = Vec::new();
+    for line in yaml.lines() {
+        if line.ends_with(": null") {
+            continue;
+        }
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let key = match trimmed.starts_with('-') {
+            true => None,
+            false => trimmed.split_once(':').map(|(key, _)| key),
+        };
+        if let Some(key) = key {
+            path.retain(|&(i, _)| i < indent);
+            path.push((indent, key));
+            let dotted: Vec<&str> = path.iter().map(|&(_, k)| k).collect();
+            let dotted = dotted.join(".");
+            if indent == 0 {
+                out.push('\n');
+            }
+            if let Some((_, comment)) = COMMENTS.iter().find(|(k, _)| *k == dotted) {
+                out.push_str(&format!("{}# {comment}\n", " ".repeat(indent)));
+            }
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// `statiker init`: write the scaffolded config to `path`
+///
+/// Refuses to replace an existing file unless `force` is set.
+pub async fn run(path: &str, force: bool) -> anyhow::Result<()> {
+    if !force && Path::new(path).exists() {
+        anyhow::bail!("{path} already exists; pass --force to overwrite it");
+    }
+    tokio::fs::write(path, render()?)
+        .await
+        .with_context(|| format!("writing {path}"))?;
+    println!("Wrote {path}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_init_round_trips() {
+        let dir = std::env::temp_dir().join("statiker_test_init");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("statiker.yaml");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        run(path, false).await.unwrap();
+        let text = std::fs::read_to_string(path).unwrap();
+        assert!(text.contains("# Listener and static file serving\nserver:\n"));
+        assert!(text.contains("  # Directory files are served from\n  root: .\n"));
+        let parsed = Config::from_yaml(&text).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(Config::default()).unwrap()
+        );
+
+        // Existing files are only replaced with --force
+        std::fs::write(path, "# mine\n").unwrap();
+        assert!(run(path, false).await.is_err());
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "# mine\n");
+        run(path, true).await.unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), text);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
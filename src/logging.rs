@@ -0,0 +1,195 @@
+use crate::config::{LogFormat, Obs};
+use tracing::{warn, Subscriber};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+/// Handle used to swap the active log filter on config reload
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Initialize the tracing subscriber from the loaded config
+///
+/// Must run after the config is loaded and before anything is logged, so
+/// that every line uses `obs.log_format`. The filter is `RUST_LOG` (or
+/// `obs.level`) plus `obs.filters`, and stays reloadable via the handle.
+pub fn init(obs: &Obs) -> FilterHandle {
+    let (filter, warnings) = build_filter(obs);
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer(obs.log_format, std::io::stdout))
+        .init();
+    for warning in warnings {
+        warn!("{warning}");
+    }
+    handle
+}
+
+/// Formatting layer for `format`, writing to `writer`
+pub fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer().compact().with_writer(writer).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+    }
+}
+
+/// Filter from `RUST_LOG` (or `obs.level`) and the per-module filters
+///
+/// Invalid settings are skipped and reported as warnings, to be logged once
+/// the subscriber is up: invalid per-module filters leave just the base.
+fn build_filter(obs: &Obs) -> (EnvFilter, Vec<String>) {
+    let rust_log = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    let (base, warning) = base_directive(rust_log, &obs.level);
+    let mut warnings: Vec<String> = warning.into_iter().collect();
+    let directives = build_directives(&base, obs);
+    let filter = EnvFilter::try_new(&directives).unwrap_or_else(|e| {
+        warnings.push(format!("invalid log filters '{directives}': {e}"));
+        EnvFilter::new(&base)
+    });
+    (filter, warnings)
+}
+
+/// Base directive: `RUST_LOG` if set, else `obs.level`
+///
+/// An invalid `obs.level` falls back to `info` with a warning, so a typo in
+/// the level doesn't also throw away the per-module filters.
+pub fn base_directive(rust_log: Option<String>, level: &str) -> (String, Option<String>) {
+    if let Some(rust_log) = rust_log {
+        return (rust_log, None);
+    }
+    match level.trim().parse::<LevelFilter>() {
+        Ok(level) => (level.to_string().to_lowercase(), None),
+        Err(_) => (
+            "info".into(),
+            Some(format!("invalid obs.level '{level}', falling back to info")),
+        ),
+    }
+}
+
+/// Build an `EnvFilter` directive string from a base directive and per-module filters
+///
+/// Filters are sorted by module so the resulting directive is deterministic.
+pub fn build_directives(base: &str, obs: &Obs) -> String {
+    let mut filters: Vec<_> = obs.filters.iter().collect();
+    filters.sort();
+    let mut directives = vec![base.to_string()];
+    directives.extend(
+        filters
+            .into_iter()
+            .map(|(module, level)| format!("{module}={level}")),
+    );
+    directives.join(",")
+}
+
+/// Re-apply `obs.level` and the per-module filters on config reload
+///
+/// `RUST_LOG` still takes precedence over `obs.level` as the base directive.
+pub fn apply_config(handle: &FilterHandle, obs: &Obs) {
+    let (filter, warnings) = build_filter(obs);
+    for warning in warnings {
+        warn!("{warning}");
+    }
+    if let Err(e) = handle.reload(filter) {
+        warn!("failed to apply log filters: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Log output of a single `info!` through `fmt_layer(format)`
+    fn log_line(format: LogFormat) -> String {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let buf = buf.clone();
+            move || SharedBuf(buf.clone())
+        };
+        let subscriber = tracing_subscriber::registry().with(fmt_layer(format, writer));
+        tracing::subscriber::with_default(subscriber, || tracing::info!(port = 8080, "listening"));
+        let out = buf.lock().unwrap().clone();
+        String::from_utf8(out).unwrap()
+    }
+
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_base_directive() {
+        assert_eq!(base_directive(None, "debug"), ("debug".into(), None));
+        assert_eq!(base_directive(None, "WARN"), ("warn".into(), None));
+        assert_eq!(base_directive(None, " error "), ("error".into(), None));
+        assert_eq!(base_directive(None, "off"), ("off".into(), None));
+
+        let (base, warning) = base_directive(None, "verbose");
+        assert_eq!(base, "info");
+        assert!(warning.unwrap().contains("verbose"));
+
+        // RUST_LOG wins, even over an invalid level
+        let rust_log = Some("statiker=trace".to_string());
+        assert_eq!(base_directive(rust_log, "verbose"), ("statiker=trace".into(), None));
+    }
+
+    #[test]
+    fn test_fmt_layer_json() {
+        let line = log_line(LogFormat::Json);
+        let json: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["fields"]["message"], "listening");
+        assert_eq!(json["fields"]["port"], 8080);
+    }
+
+    #[test]
+    fn test_fmt_layer_text() {
+        let line = log_line(LogFormat::Text);
+        assert!(serde_json::from_str::<serde_json::Value>(line.trim_end()).is_err());
+        assert!(line.contains("INFO"), "{line}");
+        assert!(line.contains("listening"), "{line}");
+        // Field names and values may be separated by ANSI color codes
+        assert!(line.contains("port") && line.contains("8080"), "{line}");
+    }
+
+    #[test]
+    fn test_build_directives_no_filters() {
+        let obs = Obs::default();
+        assert_eq!(build_directives("info", &obs), "info");
+    }
+
+    #[test]
+    fn test_build_directives_sorted() {
+        let mut obs = Obs::default();
+        obs.filters.insert("statiker::proxy".into(), "warn".into());
+        obs.filters
+            .insert("statiker::handlers".into(), "debug".into());
+        assert_eq!(
+            build_directives("info", &obs),
+            "info,statiker::handlers=debug,statiker::proxy=warn"
+        );
+    }
+
+    #[test]
+    fn test_build_directives_parse() {
+        let mut obs = Obs::default();
+        obs.filters.insert("statiker::proxy".into(), "warn".into());
+        assert!(EnvFilter::try_new(build_directives("info", &obs)).is_ok());
+    }
+}
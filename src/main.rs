@@ -1,113 +1,223 @@
+mod archive;
 mod cli;
 mod config;
 mod handlers;
+mod init;
+mod logging;
 mod middleware;
 mod proxy;
+mod reload;
 mod router;
 mod server;
+mod ssi;
 mod state;
 mod utils;
+mod validate;
 
 use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
 use axum::middleware::{from_fn, Next};
 use governor::RateLimiter;
-use std::{net::SocketAddr, num::NonZeroU32, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 use tokio::fs;
-use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
 use tracing::{info, warn, Level};
-use tracing_subscriber::{fmt, EnvFilter};
 
-use crate::cli::{print_config, Cli};
+use crate::cli::{print_config, Banner, Cli, Command};
 use clap::Parser;
-use crate::config::Config;
-use crate::middleware::{cache_control_mw, rate_limit_mw, with_security_headers};
+use crate::config::{Config, Obs};
+use crate::middleware::{
+    access_log_mw, basic_auth_mw, cache_control_mw, concurrency_mw, cors_method_headers_mw,
+    forward_auth_mw, ip_filter_mw, is_bcrypt_hash, rate_limit_mw, request_id_mw, retry_after_mw,
+    vary_mw, with_security_headers,
+};
 use crate::router::{build_compression, build_cors, build_router};
-use crate::server::validate_tls;
-use crate::state::AppState;
+use crate::server::{
+    plain_http_router, shutdown_signal, spawn_tls_reload, validate_tls, ClientCertAcceptor,
+    ClientIdentity, InFlight, Shutdown,
+};
+use crate::state::{
+    build_access_log, build_concurrency_limiter, build_file_cache, build_rate_limit_quota,
+    build_read_limit, compile_cache_rules, compile_deny_paths, compile_ip_filter,
+    spawn_limiter_gc, AppState,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing subscriber first, before any logging calls
-    // Use environment variable or default to "info" level
-    // The config file's log level will be respected for subsequent messages,
-    // but early messages (like config loading) will use this initial level
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new("info"));
-    fmt().with_env_filter(filter).compact().init();
-
     // Parse command line arguments
     let cli = Cli::parse();
+    if let Some(command) = &cli.command {
+        logging::init(&Obs::default());
+        return match command {
+            Command::Validate => validate::run(&cli.config).await,
+            Command::Init { force } => init::run(&cli.config, *force).await,
+        };
+    }
 
     // Load config (clap handles env var automatically)
-    let config_path = cli.config;
-
-    // Try read config file; if missing, use defaults
-    let cfg: Config = match fs::read_to_string(&config_path).await {
-        Ok(text) => match serde_yaml::from_str(&text) {
-            Ok(parsed) => {
-                info!("Loaded configuration from {}", &config_path);
-                parsed
-            }
-            Err(e) => {
-                // If parsing fails, fail fast — config file present but invalid.
-                return Err(
-                    anyhow::anyhow!("parsing YAML {}: {e}", &config_path).context("parsing YAML")
-                );
-            }
-        },
-        Err(e) => {
-            warn!(
-                "Could not read config file '{}': {}. Falling back to built-in defaults.",
-                config_path, e
-            );
-            Config::default()
-        }
+    let config_path = cli.config.clone();
+
+    // The config file is read before the tracing subscriber exists, so that
+    // `obs.log_format` and the log levels apply from the very first line.
+    // Nothing is logged until then: the outcome of reading the file is kept
+    // and logged right after initialization, and parse errors are returned.
+    let read = fs::read_to_string(&config_path).await;
+    let mut cfg: Config = match &read {
+        // If parsing fails, fail fast — config file present but invalid.
+        Ok(text) => Config::from_yaml(text).map_err(|e| {
+            anyhow::anyhow!("parsing YAML {}: {e}", &config_path).context("parsing YAML")
+        })?,
+        Err(_) => cli.fallback_config(),
     };
 
+    // Command-line flags take precedence over the config file
+    cli.apply_overrides(&mut cfg);
+
+    let log_handle = logging::init(&cfg.obs);
+    match read {
+        Ok(_) => info!("Loaded configuration from {}", &config_path),
+        Err(_) if cli.path.is_some() => {}
+        Err(e) => warn!(
+            "Could not read config file '{}': {}. Falling back to built-in defaults.",
+            config_path, e
+        ),
+    }
+
+    if let Some(path) = &cli.path {
+        if !path.is_dir() {
+            anyhow::bail!("'{}' is not a directory", path.display());
+        }
+    }
+
     // Print configuration
-    print_config(&cfg);
+    print_config(&cfg, Banner::new(&cli, &cfg));
 
     // Validate TLS configuration if enabled
     validate_tls(&cfg).await?;
 
     // State
     let limiter = if cfg.security.rate_limit.enabled {
-        let rpm = cfg.security.rate_limit.requests_per_min.max(1);
-        let quota = governor::Quota::per_minute(NonZeroU32::new(rpm).unwrap());
-        Some(Arc::new(RateLimiter::keyed(quota)))
+        let quota = build_rate_limit_quota(&cfg.security.rate_limit)?;
+        let limiter = Arc::new(RateLimiter::keyed(quota));
+        spawn_limiter_gc(limiter.clone(), cfg.security.rate_limit.gc_interval);
+        Some(limiter)
     } else {
         None
     };
 
+    let deny_paths = compile_deny_paths(&cfg)?;
+    let read_limit = build_read_limit(&cfg);
+    let concurrency = build_concurrency_limiter(&cfg);
+    let memcache = build_file_cache(&cfg);
+    let ip_filter = compile_ip_filter(&cfg)?;
+    let cache_rules = compile_cache_rules(&cfg)?;
+    let access_log = build_access_log(&cfg)?;
+
+    let cfg = Arc::new(cfg);
+    // Reloadable sections are swapped in on SIGHUP, which only exists on Unix
+    let live = cfg!(unix).then(|| Arc::new(ArcSwap::from(cfg.clone())));
+
     let state = AppState {
         root: cfg.server.root.clone(),
-        cfg: Arc::new(cfg),
+        cfg,
         limiter,
+        deny_paths: Arc::new(deny_paths),
+        read_limit,
+        concurrency,
+        memcache,
+        live,
     };
 
+    #[cfg(unix)]
+    if let Some(live) = &state.live {
+        reload::spawn_sighup_reload(config_path.clone(), live.clone(), log_handle.clone())
+            .context("failed to install SIGHUP handler")?;
+    }
+
     // Router
+    // Same fields as DefaultMakeSpan, plus an empty `route` filled in by the
+    // matched route when `obs.log_route` is enabled
     let trace = TraceLayer::new_for_http()
-        .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+        .make_span_with(|req: &axum::http::Request<axum::body::Body>| {
+            tracing::info_span!(
+                "request",
+                method = %req.method(),
+                uri = %req.uri(),
+                version = ?req.version(),
+                route = tracing::field::Empty,
+                request_id = tracing::field::Empty,
+                client_cert = req
+                    .extensions()
+                    .get::<ClientIdentity>()
+                    .map(|id| tracing::field::display(id.0.clone())),
+            )
+        })
         .on_response(DefaultOnResponse::new().level(Level::INFO));
 
     let mut app = build_router(&state)?;
 
     // Middlewares (capture state with closures)
+    let basic_auth = &state.cfg.security.basic_auth;
+    if basic_auth.enabled {
+        for (user, stored) in &basic_auth.users {
+            if !is_bcrypt_hash(stored) {
+                warn!("basic_auth password for '{user}' is plaintext; prefer a bcrypt hash");
+            }
+        }
+        let ba_state = state.clone();
+        app = app.layer(from_fn(move |req, next: Next| {
+            basic_auth_mw(ba_state.clone(), req, next)
+        }));
+    }
+
+    if state.cfg.security.forward_auth.is_some() {
+        let fa_state = state.clone();
+        app = app.layer(from_fn(move |req, next: Next| {
+            forward_auth_mw(fa_state.clone(), req, next)
+        }));
+    }
+
+    if state.concurrency.is_some() {
+        let pc_state = state.clone();
+        app = app.layer(from_fn(move |req, next: Next| {
+            concurrency_mw(pc_state.clone(), req, next)
+        }));
+    }
+
     let rl_state = state.clone();
     app = app.layer(from_fn(move |req, next: Next| {
         rate_limit_mw(rl_state.clone(), req, next)
     }));
 
+    // Outside the rate limiter so blocked clients don't consume quota
+    if let Some(rules) = ip_filter {
+        let ip_state = state.clone();
+        app = app.layer(from_fn(move |req, next: Next| {
+            ip_filter_mw(ip_state.clone(), rules.clone(), req, next)
+        }));
+    }
+
     if let Some(cors) = build_cors(&state.cfg) {
         app = app.layer(cors);
+
+        let cm_state = state.clone();
+        app = app.layer(from_fn(move |req, next: Next| {
+            cors_method_headers_mw(cm_state.clone(), req, next)
+        }));
     }
     if let Some(comp) = build_compression(&state.cfg) {
         app = app.layer(comp);
     }
 
+    let ra_state = state.clone();
+    app = app.layer(from_fn(move |req, next: Next| {
+        retry_after_mw(ra_state.clone(), req, next)
+    }));
+
     let cc_state = state.clone();
+    let cache_rules = Arc::new(cache_rules);
     app = app.layer(from_fn(move |req, next: Next| {
-        cache_control_mw(cc_state.clone(), req, next)
+        cache_control_mw(cc_state.clone(), cache_rules.clone(), req, next)
     }));
 
     let sh_state = state.clone();
@@ -115,8 +225,31 @@ async fn main() -> Result<()> {
         with_security_headers(sh_state.clone(), req, next)
     }));
 
+    // Outermost before tracing so it sees Vary from compression, CORS and handlers
+    app = app.layer(from_fn(vary_mw));
+
+    // Sees every response in its final form, including ones rejected by other middleware
+    if let Some(sink) = access_log.clone() {
+        let al_state = state.clone();
+        app = app.layer(from_fn(move |req, next: Next| {
+            access_log_mw(al_state.clone(), sink.clone(), req, next)
+        }));
+    }
+
+    // Outside the access log so every response carries the ID, inside tracing so
+    // it lands in the request span
+    if state.cfg.obs.request_id {
+        app = app.layer(from_fn(request_id_mw));
+    }
+
     app = app.layer(trace);
 
+    // Outermost, so shutdown can report every request still being handled
+    let in_flight = InFlight::default();
+    let tracked = in_flight.clone();
+    app = app.layer(from_fn(move |req, next: Next| tracked.clone().track(req, next)));
+    let shutdown = Shutdown::new(shutdown_signal(), state.cfg.server.shutdown_timeout, in_flight);
+
     // Bind and serve (TLS or plain)
     let addr: SocketAddr = format!("{}:{}", state.cfg.server.host, state.cfg.server.port)
         .parse()
@@ -124,22 +257,59 @@ async fn main() -> Result<()> {
 
     if state.cfg.tls.enabled {
         let tls = crate::server::load_tls_config(&state.cfg).await?;
+        spawn_tls_reload(state.cfg.clone(), tls.clone());
+
+        if let Some((port, http_app)) = plain_http_router(&state) {
+            let http_addr = SocketAddr::new(addr.ip(), port);
+            let listener = tokio::net::TcpListener::bind(http_addr)
+                .await
+                .context("failed to bind plain HTTP listener")?;
+            match &state.cfg.tls.http_root {
+                Some(root) => info!("listening http://{http_addr} (serving {})", root.display()),
+                None => info!("listening http://{http_addr} (redirecting to https)"),
+            }
+            let signal = shutdown.signal();
+            tokio::spawn(async move {
+                let serve = axum::serve(
+                    listener,
+                    http_app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(signal);
+                if let Err(e) = serve.await {
+                    warn!("plain HTTP listener stopped: {e}");
+                }
+            });
+        }
 
         info!("listening https://{addr}");
 
-        axum_server::bind_rustls(addr, tls)
-            .serve(app.into_make_service())
-            .await
-            .context("failed to start TLS server")?;
+        let handle = axum_server::Handle::new();
+        let (signal, timeout) = (shutdown.signal(), state.cfg.server.shutdown_timeout);
+        let tls_handle = handle.clone();
+        tokio::spawn(async move {
+            signal.await;
+            tls_handle.graceful_shutdown(Some(timeout));
+        });
+        // Tags requests with the client certificate's identity (`tls.client_auth`)
+        let serve = axum_server::bind(addr)
+            .acceptor(ClientCertAcceptor::new(tls))
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        shutdown.drain(serve).await.context("failed to start TLS server")?;
     } else {
         info!("listening http://{addr}");
         let listener = tokio::net::TcpListener::bind(addr)
             .await
             .context("failed to bind TCP listener")?;
-        axum::serve(listener, app)
-            .await
-            .context("failed to start HTTP server")?;
+        // Peer addresses feed rate limiting and `X-Forwarded-For`
+        let service = app.into_make_service_with_connect_info::<SocketAddr>();
+        let serve = axum::serve(listener, service).with_graceful_shutdown(shutdown.signal());
+        shutdown.drain(serve).await.context("failed to start HTTP server")?;
     }
 
+    // Lines are written in the background; don't lose the last ones on exit
+    if let Some(sink) = access_log {
+        sink.flush();
+    }
     Ok(())
 }
@@ -1,44 +1,328 @@
-use crate::state::AppState;
-use crate::utils::is_asset_path;
+use crate::config::{AccessLogFormat, Config, RateLimitFallback};
+use crate::proxy::strip_hop_by_hop;
+use crate::state::{shared_http_client, AccessLogSink, AppState, CacheRules, IpRules};
+use crate::utils::{
+    cors_request_method, forwarded_client, is_asset_path, normalize_path, path_under, peer_addr,
+    trusted_client, Vary,
+};
 use axum::{
-    http::{HeaderName, HeaderValue, StatusCode},
+    body::{Body, HttpBody},
+    http::{HeaderName, HeaderValue, StatusCode, Uri},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use http::{header::CACHE_CONTROL, Request};
-use std::{net::IpAddr, str::FromStr};
+use http::{
+    header::{
+        ACCESS_CONTROL_ALLOW_HEADERS, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, HOST,
+        REFERER, RETRY_AFTER, USER_AGENT, WWW_AUTHENTICATE,
+    },
+    Method, Request,
+};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use governor::clock::{Clock, DefaultClock};
+use http_body_util::BodyExt;
+use std::{
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+/// Extract the client IP used as the rate limiting key
+///
+/// With `server.trusted_proxies`, `X-Forwarded-For` only counts for requests
+/// from a trusted peer (see [`trusted_client`]). Otherwise the entry selected
+/// by `security.forwarded_hops` (see [`forwarded_client`]) is used, then the
+/// socket address. With neither configured the header is ignored, since any
+/// client could send it.
+pub fn rate_limit_key<B>(req: &Request<B>, cfg: &Config) -> Option<IpAddr> {
+    let xff = req.headers().get("x-forwarded-for").and_then(|v| v.to_str().ok());
+    let peer = peer_addr(req).map(|a| a.ip());
+    let trusted = &cfg.server.trusted_proxies;
+    if !trusted.is_empty() {
+        return peer.map(|peer| trusted_client(peer, xff, trusted));
+    }
+    match cfg.security.forwarded_hops {
+        0 => peer,
+        hops => xff
+            .and_then(|s| forwarded_client(s, hops))
+            .and_then(|s| s.parse::<IpAddr>().ok())
+            .or(peer),
+    }
+}
 
 /// Rate limiting middleware
-/// 
-/// Security: Uses a fallback IP (0.0.0.0) when client IP cannot be extracted
-/// to prevent bypassing rate limits by omitting identification headers.
+///
+/// Clients whose IP cannot be extracted are handled per `rate_limit.fallback`:
+/// - `shared` (default): tracked under a fallback IP (0.0.0.0), so omitting
+///   identification headers cannot bypass the limit, but all unknown clients
+///   share one bucket and a single abuser can exhaust it for everyone.
+/// - `allow`: not rate limited at all; only safe when every request is
+///   guaranteed to carry a client address.
+/// - `deny`: rejected with 403 Forbidden.
 pub async fn rate_limit_mw(state: AppState, req: Request<axum::body::Body>, next: Next) -> Response {
     if let Some(limiter) = &state.limiter {
-        // Try to extract IP from headers or socket address
-        let ip = req
-            .headers()
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| s.split(',').next())
-            .and_then(|s| s.trim().parse::<IpAddr>().ok())
-            .or_else(|| req.extensions().get::<std::net::SocketAddr>().map(|a| a.ip()))
-            // Fallback to 0.0.0.0 for unknown clients to prevent rate limit bypass
-            .unwrap_or_else(|| IpAddr::from([0, 0, 0, 0]));
-        
-        // Apply rate limiting check - all requests are checked, including unknown IPs
-        if limiter.check_key(&ip).is_err() {
-            return (StatusCode::TOO_MANY_REQUESTS, "rate limit").into_response();
+        let ip = match rate_limit_key(&req, &state.cfg) {
+            Some(ip) => ip,
+            None => match state.cfg.security.rate_limit.fallback {
+                RateLimitFallback::Allow => return next.run(req).await,
+                RateLimitFallback::Deny => return StatusCode::FORBIDDEN.into_response(),
+                RateLimitFallback::Shared => IpAddr::from([0, 0, 0, 0]),
+            },
+        };
+
+        if let Err(not_until) = limiter.check_key(&ip) {
+            let wait = not_until.wait_time_from(DefaultClock::default().now());
+            // Retry-After takes whole seconds; round up so clients don't retry too early
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, HeaderValue::from(secs))],
+                "rate limit",
+            )
+                .into_response();
+        }
+    }
+    next.run(req).await
+}
+
+/// Per-IP concurrent request limiting middleware
+///
+/// Clients are identified like in [`rate_limit_mw`], including the
+/// `rate_limit.fallback` policy for clients without an identifiable IP.
+/// The slot is held until the response body has been sent or dropped, so
+/// streamed files, archives and proxied downloads count while they stream.
+pub async fn concurrency_mw(state: AppState, req: Request<Body>, next: Next) -> Response {
+    let Some(limiter) = &state.concurrency else {
+        return next.run(req).await;
+    };
+    let ip = match rate_limit_key(&req, &state.cfg) {
+        Some(ip) => ip,
+        None => match state.cfg.security.rate_limit.fallback {
+            RateLimitFallback::Allow => return next.run(req).await,
+            RateLimitFallback::Deny => return StatusCode::FORBIDDEN.into_response(),
+            RateLimitFallback::Shared => IpAddr::from([0, 0, 0, 0]),
+        },
+    };
+    let Some(permit) = limiter.try_acquire(ip) else {
+        return (StatusCode::TOO_MANY_REQUESTS, "too many concurrent requests").into_response();
+    };
+    // The body owns the permit, releasing it when the body is dropped
+    next.run(req).await.map(|body| {
+        Body::new(body.map_frame(move |frame| {
+            let _ = &permit;
+            frame
+        }))
+    })
+}
+
+/// IP allowlist / denylist middleware (`security.ip_filter`)
+///
+/// Clients are identified like in [`rate_limit_mw`]. Blocked clients get
+/// 403 Forbidden. Clients without an identifiable IP are refused when an
+/// allowlist is configured and let through otherwise.
+pub async fn ip_filter_mw(
+    state: AppState,
+    rules: Arc<IpRules>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let allowed = match rate_limit_key(&req, &state.cfg) {
+        Some(ip) => rules.is_allowed(ip),
+        None => !rules.has_allowlist(),
+    };
+    if !allowed {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(req).await
+}
+
+/// HTTP Basic authentication middleware (`security.basic_auth`)
+///
+/// Requests under the protected `paths` need an `Authorization: Basic` header
+/// matching one of the configured users; otherwise `401` with a
+/// `WWW-Authenticate` challenge is returned.
+pub async fn basic_auth_mw(state: AppState, req: Request<Body>, next: Next) -> Response {
+    let auth = &state.cfg.security.basic_auth;
+    // Match the decoded path the handlers serve, so `/%70rivate` is `/private`
+    let path = normalize_path(req.uri().path());
+    let protected = auth.paths.is_empty() || auth.paths.iter().any(|p| path_under(&path, p));
+    if !auth.enabled || !protected {
+        return next.run(req).await;
+    }
+    let credentials = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_basic_credentials);
+    if let Some((user, password)) = credentials {
+        if check_basic_credentials(&auth.users, user, password).await {
+            return next.run(req).await;
+        }
+    }
+    let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", auth.realm.replace('"', ""));
+    let mut res = StatusCode::UNAUTHORIZED.into_response();
+    if let Ok(hv) = HeaderValue::from_str(&challenge) {
+        res.headers_mut().insert(WWW_AUTHENTICATE, hv);
+    }
+    res
+}
+
+/// Decode an `Authorization: Basic` value into (user, password)
+pub fn parse_basic_credentials(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = BASE64_STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Whether a configured password is a bcrypt hash rather than plaintext
+pub fn is_bcrypt_hash(stored: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| stored.starts_with(prefix))
+}
+
+/// bcrypt hash (cost 12) verified for unknown users, never matching a real password
+const DUMMY_BCRYPT_HASH: &str = "$2b$12$h5cHTZy14ot.fTmEdZGh6ut4RwenZtMLDDQCFw7kD3pBRSf3tgbni";
+
+/// Check credentials against `users` without leaking timing information
+///
+/// Usernames and plaintext passwords are compared in constant time; bcrypt
+/// hashes are verified on a blocking thread. Unknown users are verified
+/// against [`DUMMY_BCRYPT_HASH`], so they take as long as known ones.
+async fn check_basic_credentials(
+    users: &std::collections::HashMap<String, String>,
+    user: String,
+    password: String,
+) -> bool {
+    // Visit every entry so the lookup time doesn't depend on the username
+    let stored = users.iter().fold(None, |found, (name, stored)| {
+        match bool::from(name.as_bytes().ct_eq(user.as_bytes())) {
+            true => Some(stored.clone()),
+            false => found,
+        }
+    });
+    let Some(stored) = stored else {
+        tokio::task::spawn_blocking(move || bcrypt::verify(password, DUMMY_BCRYPT_HASH))
+            .await
+            .ok();
+        return false;
+    };
+    if is_bcrypt_hash(&stored) {
+        tokio::task::spawn_blocking(move || bcrypt::verify(password, &stored).unwrap_or(false))
+            .await
+            .unwrap_or(false)
+    } else {
+        stored.as_bytes().ct_eq(password.as_bytes()).into()
+    }
+}
+
+/// Time allowed for the forward-auth service to answer
+const FORWARD_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Forward authentication middleware (`security.forward_auth`)
+///
+/// Sends a GET subrequest carrying the original request headers to the auth
+/// service, plus `X-Forwarded-Method`, `X-Forwarded-Host` and `X-Forwarded-Uri`.
+/// A 2xx answer lets the request through with the configured `copy_headers`
+/// set from the auth response; any other answer is relayed to the client.
+/// Only requests under `paths` (all by default) are checked, and the health
+/// endpoint never is.
+pub async fn forward_auth_mw(state: AppState, mut req: Request<Body>, next: Next) -> Response {
+    let Some(auth) = &state.cfg.security.forward_auth else {
+        return next.run(req).await;
+    };
+    // Compared raw, as the router matches it: a decoded `/health%7a` would
+    // reach the static handler, not the health route
+    let health = &state.cfg.obs.health;
+    if health.enabled && req.uri().path() == health.path {
+        return next.run(req).await;
+    }
+    let path = normalize_path(req.uri().path());
+    if !auth.paths.is_empty() && !auth.paths.iter().any(|p| path_under(&path, p)) {
+        return next.run(req).await;
+    }
+    let Ok(uri) = Uri::from_str(&auth.url) else {
+        warn!("invalid forward_auth url '{}'", auth.url);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let mut headers = req.headers().clone();
+    strip_hop_by_hop(&mut headers);
+    headers.remove(HOST);
+    headers.remove(CONTENT_LENGTH);
+    if let Some(host) = req.headers().get(HOST) {
+        headers.insert("x-forwarded-host", host.clone());
+    }
+    if let Ok(method) = HeaderValue::from_str(req.method().as_str()) {
+        headers.insert("x-forwarded-method", method);
+    }
+    let forwarded_uri = req.uri().path_and_query().map_or("/", |pq| pq.as_str());
+    if let Ok(hv) = HeaderValue::from_str(forwarded_uri) {
+        headers.insert("x-forwarded-uri", hv);
+    }
+    let mut subrequest = Request::get(uri).body(Body::empty()).unwrap();
+    *subrequest.headers_mut() = headers;
+
+    let pending = shared_http_client(&state.cfg.proxy_client).request(subrequest);
+    let auth_res = match tokio::time::timeout(FORWARD_AUTH_TIMEOUT, pending).await {
+        Ok(Ok(res)) => res,
+        Ok(Err(e)) => {
+            warn!("forward_auth request failed: {e}");
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+        Err(_) => {
+            warn!("forward_auth request timed out");
+            return StatusCode::GATEWAY_TIMEOUT.into_response();
+        }
+    };
+
+    if !auth_res.status().is_success() {
+        // Relay the denial (e.g. 401 with WWW-Authenticate, or a 302 to a login page)
+        let (mut parts, body) = auth_res.into_parts();
+        strip_hop_by_hop(&mut parts.headers);
+        return Response::from_parts(parts, Body::new(body));
+    }
+
+    for name in &auth.copy_headers {
+        let Ok(name) = HeaderName::from_str(name) else {
+            continue;
+        };
+        // Drop client-sent values so they can't be spoofed past the auth service
+        req.headers_mut().remove(&name);
+        for value in auth_res.headers().get_all(&name) {
+            req.headers_mut().append(name.clone(), value.clone());
         }
     }
     next.run(req).await
 }
 
 /// Cache control middleware
-pub async fn cache_control_mw(state: AppState, req: Request<axum::body::Body>, next: Next) -> Response {
+///
+/// The first `assets.rules` pattern matching the path sets `Cache-Control`;
+/// otherwise assets get the global `assets.cache` policy, if enabled.
+pub async fn cache_control_mw(
+    state: AppState,
+    rules: Arc<CacheRules>,
+    req: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
     let path = req.uri().path().to_owned();
     let mut res = next.run(req).await;
-    let cache = &state.cfg.assets.cache;
-    if cache.enabled && is_asset_path(&path) {
+    if let Some((_, value)) = rules.iter().find(|(re, _)| re.is_match(&path)) {
+        res.headers_mut().insert(CACHE_CONTROL, value.clone());
+        return res;
+    }
+    let cfg = state.current_cfg();
+    let cache = &cfg.assets.cache;
+    if cache.enabled && is_asset_path(&path, &cfg.assets.extensions) {
         let secs = cache.max_age.as_secs();
         if let Ok(hv) = HeaderValue::from_str(&format!("public, max-age={secs}, immutable")) {
             res.headers_mut().insert(CACHE_CONTROL, hv);
@@ -47,10 +331,63 @@ pub async fn cache_control_mw(state: AppState, req: Request<axum::body::Body>, n
     res
 }
 
+/// Add `server.default_retry_after` to 503 responses without `Retry-After`
+///
+/// Covers every 503 source (handlers, proxied upstreams, ...) so clients and
+/// load balancers back off uniformly.
+pub async fn retry_after_mw(state: AppState, req: Request<axum::body::Body>, next: Next) -> Response {
+    let mut res = next.run(req).await;
+    let retry_after = state.cfg.server.default_retry_after;
+    if res.status() == StatusCode::SERVICE_UNAVAILABLE
+        && !retry_after.is_zero()
+        && !res.headers().contains_key(RETRY_AFTER)
+    {
+        // Retry-After takes whole seconds; round sub-second values up
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        res.headers_mut().insert(RETRY_AFTER, HeaderValue::from(secs));
+    }
+    res
+}
+
+/// Per-method CORS preflight headers middleware
+///
+/// Wraps the CORS layer: for preflight requests whose requested method has
+/// method-specific `allowed_headers`, the `Access-Control-Allow-Headers` value
+/// produced by the global policy is replaced with that list.
+pub async fn cors_method_headers_mw(state: AppState, req: Request<axum::body::Body>, next: Next) -> Response {
+    let allowed = if req.method() == Method::OPTIONS {
+        cors_request_method(req.method(), req.headers())
+            .and_then(|m| {
+                state
+                    .cfg
+                    .security
+                    .cors
+                    .methods
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(m.as_str()))
+            })
+            .map(|(_, policy)| policy.allowed_headers.join(", "))
+            .filter(|list| !list.is_empty())
+    } else {
+        None
+    };
+
+    let mut res = next.run(req).await;
+    if let Some(list) = allowed {
+        // Only touch responses the CORS layer actually accepted
+        if res.headers().contains_key(ACCESS_CONTROL_ALLOW_HEADERS) {
+            if let Ok(hv) = HeaderValue::from_str(&list) {
+                res.headers_mut().insert(ACCESS_CONTROL_ALLOW_HEADERS, hv);
+            }
+        }
+    }
+    res
+}
+
 /// Security headers middleware
 pub async fn with_security_headers(state: AppState, req: Request<axum::body::Body>, next: Next) -> Response {
     let mut res = next.run(req).await;
-    for (k, v) in &state.cfg.security.headers {
+    for (k, v) in &state.current_cfg().security.headers {
         if let (Ok(name), Ok(val)) = (HeaderName::from_str(k), HeaderValue::from_str(v)) {
             res.headers_mut().insert(name, val);
         }
@@ -58,10 +395,184 @@ pub async fn with_security_headers(state: AppState, req: Request<axum::body::Bod
     res
 }
 
+/// One request as recorded in the access log
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub client: Option<IpAddr>,
+    pub user: Option<String>,
+    pub time: SystemTime,
+    /// `GET /path?query HTTP/1.1`
+    pub request_line: String,
+    pub status: StatusCode,
+    pub bytes: Option<u64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AccessLogEntry {
+    /// Render in Common or Combined Log Format; missing fields become `-`
+    ///
+    /// `127.0.0.1 - alice [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`,
+    /// followed by `"referer" "user-agent"` in the combined format.
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let dash = |v: Option<String>| v.unwrap_or_else(|| "-".into());
+        let mut line = format!(
+            "{} - {} [{}] \"{}\" {} {}",
+            dash(self.client.map(|ip| ip.to_string())),
+            dash(self.user.as_deref().map(escape_log_field)),
+            clf_time(self.time),
+            escape_log_field(&self.request_line),
+            self.status.as_u16(),
+            // Like Apache's `%b`, an empty body is logged as `-`
+            dash(self.bytes.filter(|&b| b > 0).map(|b| b.to_string())),
+        );
+        if format == AccessLogFormat::Combined {
+            line.push_str(&format!(
+                " \"{}\" \"{}\"",
+                dash(self.referer.as_deref().map(escape_log_field)),
+                dash(self.user_agent.as_deref().map(escape_log_field)),
+            ));
+        }
+        line
+    }
+}
+
+/// Escape quotes, backslashes and control characters so a field can't break the line
+fn escape_log_field(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `10/Oct/2000:13:55:36 +0000` (always UTC)
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    // 2000-10-10T13:55:36Z
+    let rfc3339 = humantime::format_rfc3339_seconds(time).to_string();
+    let month: usize = rfc3339[5..7].parse().unwrap_or(1);
+    format!(
+        "{}/{}/{}:{} +0000",
+        &rfc3339[8..10],
+        MONTHS[month - 1],
+        &rfc3339[..4],
+        &rfc3339[11..19]
+    )
+}
+
+/// Access log middleware (`obs.access_log`)
+///
+/// Clients are identified like in [`rate_limit_mw`]; the user is taken from
+/// Basic credentials, if any. Bytes sent come from the response's
+/// `Content-Length` or the body's known size, so streamed or compressed
+/// responses log `-`.
+pub async fn access_log_mw(
+    state: AppState,
+    sink: Arc<AccessLogSink>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let headers = req.headers();
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_owned);
+    let mut entry = AccessLogEntry {
+        client: rate_limit_key(&req, &state.cfg),
+        user: header(AUTHORIZATION)
+            .and_then(|v| parse_basic_credentials(&v))
+            .map(|(user, _)| user),
+        time: SystemTime::now(),
+        request_line: format!(
+            "{} {} {:?}",
+            req.method(),
+            req.uri().path_and_query().map_or("/", |pq| pq.as_str()),
+            req.version()
+        ),
+        status: StatusCode::OK,
+        bytes: None,
+        referer: header(REFERER),
+        user_agent: header(USER_AGENT),
+    };
+    let is_head = req.method() == Method::HEAD;
+
+    let res = next.run(req).await;
+    entry.status = res.status();
+    if !is_head {
+        entry.bytes = res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .or_else(|| res.body().size_hint().exact());
+    }
+    sink.write_line(&entry.format(sink.format));
+    res
+}
+
+/// `X-Request-Id` header read and set by [`request_id_mw`]
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Request ID assigned by [`request_id_mw`], stored in request extensions
+#[derive(Debug, Clone)]
+pub struct RequestId(pub HeaderValue);
+
+/// Request ID middleware (`obs.request_id`)
+///
+/// Keeps a non-empty incoming `X-Request-Id` (up to 200 bytes) or generates a
+/// random UUID, records it in the request's log span and echoes it on the
+/// response. Proxy routes forward it upstream.
+pub async fn request_id_mw(mut req: Request<Body>, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .filter(|v| !v.is_empty() && v.len() <= 200)
+        .cloned()
+        .unwrap_or_else(|| {
+            HeaderValue::from_str(&new_request_id()).expect("uuid is a valid header value")
+        });
+    if let Ok(s) = id.to_str() {
+        tracing::Span::current().record("request_id", s);
+    }
+    req.extensions_mut().insert(RequestId(id.clone()));
+    let mut res = next.run(req).await;
+    res.headers_mut().insert(X_REQUEST_ID.clone(), id);
+    res
+}
+
+/// Random (version 4) UUID in its hyphenated form
+fn new_request_id() -> String {
+    let mut b: [u8; 16] = rand::random();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|x| format!("{x:02x}")).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Collapse the `Vary` headers added by handlers and inner layers into one
+pub async fn vary_mw(req: Request<axum::body::Body>, next: Next) -> Response {
+    let mut res = next.run(req).await;
+    Vary::from_headers(res.headers()).write(res.headers_mut());
+    res
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use crate::state::{test_state, IpLimiterInner};
+    use axum::{body::Body, extract::ConnectInfo, routing::get, Router};
+    use governor::RateLimiter;
     use std::net::IpAddr;
+    use std::sync::Arc;
+    use tower::ServiceExt;
 
     #[test]
     fn test_fallback_ip_constant() {
@@ -89,21 +600,21 @@ mod tests {
             .and_then(|v| v.to_str().ok())
             .and_then(|s| s.split(',').next())
             .and_then(|s| s.trim().parse::<IpAddr>().ok())
-            .or_else(|| req1.extensions().get::<SocketAddr>().map(|a| a.ip()))
+            .or_else(|| peer_addr(&req1).map(|a| a.ip()))
             .unwrap_or_else(|| IpAddr::from([0, 0, 0, 0]));
         assert_eq!(ip1, IpAddr::from([192, 168, 1, 1]));
 
         // Test 2: Extract from socket address
         let mut req2 = Request::builder().body(Body::empty()).unwrap();
         let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-        req2.extensions_mut().insert(addr);
+        req2.extensions_mut().insert(ConnectInfo(addr));
         let ip2 = req2
             .headers()
             .get("x-forwarded-for")
             .and_then(|v| v.to_str().ok())
             .and_then(|s| s.split(',').next())
             .and_then(|s| s.trim().parse::<IpAddr>().ok())
-            .or_else(|| req2.extensions().get::<SocketAddr>().map(|a| a.ip()))
+            .or_else(|| peer_addr(&req2).map(|a| a.ip()))
             .unwrap_or_else(|| IpAddr::from([0, 0, 0, 0]));
         assert_eq!(ip2, IpAddr::from([127, 0, 0, 1]));
 
@@ -115,9 +626,828 @@ mod tests {
             .and_then(|v| v.to_str().ok())
             .and_then(|s| s.split(',').next())
             .and_then(|s| s.trim().parse::<IpAddr>().ok())
-            .or_else(|| req3.extensions().get::<SocketAddr>().map(|a| a.ip()))
+            .or_else(|| peer_addr(&req3).map(|a| a.ip()))
             .unwrap_or_else(|| IpAddr::from([0, 0, 0, 0]));
         assert_eq!(ip3, IpAddr::from([0, 0, 0, 0]), "Unknown IPs should use fallback 0.0.0.0");
     }
-}
 
+    fn limited_app(fallback: RateLimitFallback) -> Router {
+        let mut cfg = Config::default();
+        cfg.security.rate_limit.enabled = true;
+        cfg.security.rate_limit.fallback = fallback;
+        let limiter: Arc<IpLimiterInner> = Arc::new(RateLimiter::keyed(
+            governor::Quota::per_minute(std::num::NonZeroU32::new(1).unwrap()),
+        ));
+        let state = AppState { limiter: Some(limiter), ..test_state(cfg) };
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                rate_limit_mw(state.clone(), req, next)
+            }))
+    }
+
+    /// Send a request to `addr` from a socket bound to `from` and return its status line
+    async fn status_from(addr: std::net::SocketAddr, from: [u8; 4]) -> String {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind((from, 0).into()).unwrap();
+        let mut stream = socket.connect(addr).await.unwrap();
+        let req = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.unwrap();
+        line.trim_end().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_client_connection() {
+        let app = limited_app(RateLimitFallback::Shared);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+
+        assert_eq!(status_from(addr, [127, 0, 0, 1]).await, "HTTP/1.1 200 OK");
+        assert_eq!(status_from(addr, [127, 0, 0, 1]).await, "HTTP/1.1 429 Too Many Requests");
+        // Another loopback address is a different client with its own bucket
+        assert_eq!(status_from(addr, [127, 0, 0, 2]).await, "HTTP/1.1 200 OK");
+        assert_eq!(status_from(addr, [127, 0, 0, 2]).await, "HTTP/1.1 429 Too Many Requests");
+    }
+
+    async fn status_of(app: &Router) -> StatusCode {
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        app.clone().oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_fallback_shared() {
+        let app = limited_app(RateLimitFallback::Shared);
+        assert_eq!(status_of(&app).await, StatusCode::OK);
+        assert_eq!(status_of(&app).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_retry_after() {
+        let app = limited_app(RateLimitFallback::Shared);
+        assert_eq!(status_of(&app).await, StatusCode::OK);
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        // One request per minute: the next cell is just under 60s away
+        let secs: u64 = res.headers()[RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&secs), "{secs}");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_allow() {
+        let app = limited_app(RateLimitFallback::Allow);
+        assert_eq!(status_of(&app).await, StatusCode::OK);
+        assert_eq!(status_of(&app).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_deny() {
+        let app = limited_app(RateLimitFallback::Deny);
+        assert_eq!(status_of(&app).await, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_rate_limit_key() {
+        let req = Request::builder()
+            .header("x-forwarded-for", "192.168.1.1, 10.0.0.1")
+            .body(())
+            .unwrap();
+        let mut cfg = Config::default();
+        cfg.security.forwarded_hops = 1;
+        assert_eq!(rate_limit_key(&req, &cfg), Some(IpAddr::from([10, 0, 0, 1])));
+        cfg.security.forwarded_hops = 2;
+        assert_eq!(rate_limit_key(&req, &cfg), Some(IpAddr::from([192, 168, 1, 1])));
+        let req = Request::builder().body(()).unwrap();
+        assert_eq!(rate_limit_key(&req, &cfg), None);
+    }
+
+    #[test]
+    fn test_rate_limit_key_ignores_untrusted_xff() {
+        // Neither `trusted_proxies` nor `forwarded_hops`: any client could
+        // send the header, so only the socket address counts
+        let cfg = Config::default();
+        let mut req = Request::builder().header("x-forwarded-for", "1.2.3.4").body(()).unwrap();
+        assert_eq!(rate_limit_key(&req, &cfg), None);
+        req.extensions_mut().insert(ConnectInfo(std::net::SocketAddr::from(([203, 0, 113, 9], 1))));
+        assert_eq!(rate_limit_key(&req, &cfg), Some(IpAddr::from([203, 0, 113, 9])));
+    }
+
+    #[test]
+    fn test_rate_limit_key_trusted_proxies() {
+        let cfg = Config {
+            server: crate::config::Server {
+                trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let from = |peer: [u8; 4], xff: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(xff) = xff {
+                req = req.header("x-forwarded-for", xff);
+            }
+            let mut req = req.body(()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(std::net::SocketAddr::from((peer, 4000))));
+            rate_limit_key(&req, &cfg)
+        };
+        // A spoofed header from an untrusted peer is ignored
+        assert_eq!(from([203, 0, 113, 9], Some("1.2.3.4")), Some(IpAddr::from([203, 0, 113, 9])));
+        // A trusted peer's chain yields the rightmost untrusted entry
+        let chain = Some("1.2.3.4, 198.51.100.7, 10.0.0.2");
+        assert_eq!(from([10, 0, 0, 1], chain), Some(IpAddr::from([198, 51, 100, 7])));
+        assert_eq!(from([10, 0, 0, 1], None), Some(IpAddr::from([10, 0, 0, 1])));
+        // Without a socket address nothing can be trusted
+        let req = Request::builder().header("x-forwarded-for", "1.2.3.4").body(()).unwrap();
+        assert_eq!(rate_limit_key(&req, &cfg), None);
+    }
+
+    fn cors_app(cfg: Config) -> Router {
+        let state = test_state(cfg);
+        let cors = crate::router::build_cors(&state.cfg).unwrap();
+        Router::new()
+            .route("/", get(|| async { "ok" }).post(|| async { "ok" }))
+            .layer(cors)
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                cors_method_headers_mw(state.clone(), req, next)
+            }))
+    }
+
+    fn preflight(method: &str, origin: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header("origin", origin)
+            .header("access-control-request-method", method)
+            .header("access-control-request-headers", "content-type")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cors_per_method_preflight() {
+        let mut cfg = Config::default();
+        cfg.security.cors.enabled = true;
+        cfg.security.cors.methods.insert(
+            "POST".to_string(),
+            crate::config::CorsMethod {
+                allowed_origins: vec!["https://app.example.com".to_string()],
+                allowed_headers: vec!["content-type".to_string(), "authorization".to_string()],
+            },
+        );
+        let app = cors_app(cfg);
+
+        // POST preflight uses the method-specific policy
+        let res = app
+            .clone()
+            .oneshot(preflight("POST", "https://app.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            res.headers().get("access-control-allow-origin").unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(
+            res.headers().get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+            "content-type, authorization"
+        );
+
+        let res = app
+            .clone()
+            .oneshot(preflight("POST", "https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(res.headers().get("access-control-allow-origin").is_none());
+
+        // GET preflight falls back to the global policy (any origin, any header)
+        let res = app
+            .oneshot(preflight("GET", "https://other.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            res.headers().get("access-control-allow-origin").unwrap(),
+            "https://other.example.com"
+        );
+        assert_eq!(res.headers().get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "*");
+    }
+
+    #[tokio::test]
+    async fn test_vary_mw_combines_layers() {
+        let mut cfg = Config::default();
+        cfg.compression.enable = true;
+        let comp = crate::router::build_compression(&cfg).unwrap();
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { ([("vary", "user-agent")], "ok".repeat(100)) }),
+            )
+            .layer(comp)
+            .layer(axum::middleware::from_fn(vary_mw));
+        let req = Request::builder()
+            .uri("/")
+            .header("accept-encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let vary: Vec<_> = res.headers().get_all("vary").iter().collect();
+        assert_eq!(vary, vec!["user-agent, accept-encoding"]);
+    }
+
+    #[tokio::test]
+    async fn test_cache_control_observes_reloaded_config() {
+        let mut cfg = Config::default();
+        cfg.assets.cache.enabled = true;
+        cfg.assets.cache.max_age = std::time::Duration::from_secs(3600);
+        let live = Arc::new(arc_swap::ArcSwap::from_pointee(cfg.clone()));
+        let state = AppState { live: Some(live.clone()), ..test_state(cfg) };
+        let app = Router::new()
+            .route("/app.css", get(|| async { "body {}" }))
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                cache_control_mw(state.clone(), Arc::default(), req, next)
+            }));
+        let cache_control = || {
+            let app = app.clone();
+            async move {
+                let req = Request::builder().uri("/app.css").body(Body::empty()).unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                res.headers().get(CACHE_CONTROL).cloned()
+            }
+        };
+        assert_eq!(
+            cache_control().await.unwrap(),
+            "public, max-age=3600, immutable"
+        );
+
+        let mut reloaded = (**live.load()).clone();
+        reloaded.assets.cache.max_age = std::time::Duration::from_secs(60);
+        live.store(Arc::new(reloaded.clone()));
+        assert_eq!(cache_control().await.unwrap(), "public, max-age=60, immutable");
+
+        reloaded.assets.cache.enabled = false;
+        live.store(Arc::new(reloaded));
+        assert!(cache_control().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_control_custom_extensions() {
+        let mut cfg = Config::default();
+        cfg.assets.cache.enabled = true;
+        cfg.assets.extensions = vec!["wasm".into(), "json".into()];
+        let state = test_state(cfg);
+        let app = Router::new()
+            .fallback(|| async { "data" })
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                cache_control_mw(state.clone(), Arc::default(), req, next)
+            }));
+        for (uri, cached) in [("/app.wasm", true), ("/data.JSON", true), ("/intro.mp4", false)] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.headers().contains_key(CACHE_CONTROL), cached, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_control_rules() {
+        let mut cfg = Config::default();
+        cfg.assets.cache.enabled = true;
+        cfg.assets.cache.max_age = std::time::Duration::from_secs(3600);
+        cfg.assets.rules = vec![
+            crate::config::CacheRule {
+                pattern: "/static/**".into(),
+                cache_control: "public, max-age=31536000, immutable".into(),
+            },
+            crate::config::CacheRule {
+                pattern: "/*.html".into(),
+                cache_control: "no-cache".into(),
+            },
+            crate::config::CacheRule {
+                pattern: "/static/legacy/*".into(),
+                cache_control: "no-store".into(),
+            },
+        ];
+        let rules = Arc::new(crate::state::compile_cache_rules(&cfg).unwrap());
+        let state = test_state(cfg);
+        let app = Router::new()
+            .fallback(|| async { "data" })
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                cache_control_mw(state.clone(), rules.clone(), req, next)
+            }));
+        for (uri, expected) in [
+            ("/static/js/app.3f2a.js", Some("public, max-age=31536000, immutable")),
+            // First match wins
+            ("/static/legacy/old.css", Some("public, max-age=31536000, immutable")),
+            ("/index.html", Some("no-cache")),
+            // Non-matches fall back to the global asset policy
+            ("/img/logo.png", Some("public, max-age=3600, immutable")),
+            ("/docs/page.html", None),
+        ] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            let value = res.headers().get(CACHE_CONTROL).map(|v| v.to_str().unwrap());
+            assert_eq!(value, expected, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_on_503() {
+        let mut cfg = Config::default();
+        cfg.server.default_retry_after = std::time::Duration::from_millis(1500);
+        let state = test_state(cfg);
+        let app = Router::new()
+            .route("/down", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+            .route(
+                "/custom",
+                get(|| async { (StatusCode::SERVICE_UNAVAILABLE, [(RETRY_AFTER, "120")]) }),
+            )
+            .route("/ok", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                retry_after_mw(state.clone(), req, next)
+            }));
+        let retry_after = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                res.headers().get(RETRY_AFTER).cloned()
+            }
+        };
+        assert_eq!(retry_after("/down").await.unwrap(), "2");
+        assert_eq!(retry_after("/custom").await.unwrap(), "120");
+        assert!(retry_after("/ok").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_forward_auth() {
+        // Auth service: accepts "Bearer good", redirects everyone else to a login page
+        let auth = Router::new().route(
+            "/auth",
+            get(|headers: http::HeaderMap| async move {
+                let uri = headers.get("x-forwarded-uri").cloned();
+                if headers.get("authorization").map(|v| v == "Bearer good") == Some(true) {
+                    ([("x-user", "alice")], StatusCode::OK).into_response()
+                } else {
+                    let location = format!("/login?rd={}", uri.unwrap().to_str().unwrap());
+                    (StatusCode::FOUND, [("location", location)]).into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, auth).await.unwrap() });
+
+        let mut cfg = Config::default();
+        cfg.security.forward_auth = Some(crate::config::ForwardAuth {
+            url: format!("http://{addr}/auth"),
+            copy_headers: vec!["x-user".into()],
+            paths: Vec::new(),
+        });
+        let state = test_state(cfg);
+        let app = Router::new()
+            .route(
+                "/private",
+                get(|headers: http::HeaderMap| async move {
+                    headers.get("x-user").unwrap().to_str().unwrap().to_string()
+                }),
+            )
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                forward_auth_mw(state.clone(), req, next)
+            }));
+
+        let req = Request::builder()
+            .uri("/private?x=1")
+            .header("authorization", "Bearer good")
+            .header("x-user", "mallory")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"alice");
+
+        let req = Request::builder().uri("/private?x=1").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers().get("location").unwrap(), "/login?rd=/private?x=1");
+    }
+
+    #[tokio::test]
+    async fn test_forward_auth_paths_and_health() {
+        // Unreachable auth service: any request that consults it fails with 502
+        let mut cfg = Config::default();
+        cfg.obs.health.enabled = true;
+        cfg.security.forward_auth = Some(crate::config::ForwardAuth {
+            url: "http://127.0.0.1:9/auth".into(),
+            copy_headers: Vec::new(),
+            paths: vec!["/private".into()],
+        });
+        let state = test_state(cfg);
+        let app = Router::new()
+            .fallback(get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                forward_auth_mw(state.clone(), req, next)
+            }));
+        let status = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        for uri in ["/private", "/private/x", "/%70rivate/x"] {
+            assert_eq!(status(uri).await, StatusCode::BAD_GATEWAY, "{uri}");
+        }
+        for uri in ["/", "/privateer", "/healthz"] {
+            assert_eq!(status(uri).await, StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_basic_credentials_unknown_user() {
+        // The dummy must be a well-formed hash, or unknown users would skip the work
+        assert!(is_bcrypt_hash(DUMMY_BCRYPT_HASH));
+        assert!(!bcrypt::verify("statiker", DUMMY_BCRYPT_HASH).unwrap());
+        let users = [("bob".to_string(), bcrypt::hash("builder", 4).unwrap())].into();
+        assert!(!check_basic_credentials(&users, "eve".into(), "builder".into()).await);
+        assert!(check_basic_credentials(&users, "bob".into(), "builder".into()).await);
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        let mut cfg = Config::default();
+        cfg.security.basic_auth.enabled = true;
+        cfg.security.basic_auth.realm = "Private".into();
+        cfg.security.basic_auth.paths = vec!["/private".into()];
+        let users = &mut cfg.security.basic_auth.users;
+        users.insert("alice".into(), "wonderland".into());
+        users.insert("bob".into(), bcrypt::hash("builder", 4).unwrap());
+        let state = test_state(cfg);
+        let app = Router::new()
+            .route("/private/page", get(|| async { "secret" }))
+            .route("/public", get(|| async { "hello" }))
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                basic_auth_mw(state.clone(), req, next)
+            }));
+        let request = |uri: &str, credentials: Option<&str>| {
+            let mut req = Request::builder().uri(uri);
+            if let Some(credentials) = credentials {
+                let value = format!("Basic {}", BASE64_STANDARD.encode(credentials));
+                req = req.header(AUTHORIZATION, value);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        // Missing header
+        let res = app.clone().oneshot(request("/private/page", None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Basic realm=\"Private\", charset=\"UTF-8\""
+        );
+
+        // Wrong password, unknown user
+        for credentials in ["alice:wrong", "bob:wrong", "carol:wonderland"] {
+            let res = app
+                .clone()
+                .oneshot(request("/private/page", Some(credentials)))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{credentials}");
+        }
+
+        // Correct plaintext and bcrypt credentials
+        for credentials in ["alice:wonderland", "bob:builder"] {
+            let res = app
+                .clone()
+                .oneshot(request("/private/page", Some(credentials)))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "{credentials}");
+        }
+
+        // Paths outside `paths` are not protected
+        let res = app.oneshot(request("/public", None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_encoded_path() {
+        let root = std::env::temp_dir()
+            .join(format!("statiker-basic-auth-encoded-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("private")).unwrap();
+        std::fs::write(root.join("private/secret.txt"), "secret").unwrap();
+        std::fs::write(root.join("privateer.txt"), "ahoy").unwrap();
+        let mut cfg = Config::default();
+        cfg.security.basic_auth.enabled = true;
+        cfg.security.basic_auth.paths = vec!["/private".into()];
+        cfg.security.basic_auth.users.insert("alice".into(), "wonderland".into());
+        let state = AppState { root, ..test_state(cfg) };
+        let st = state.clone();
+        let app = crate::router::mount_static_route(Router::new(), &state, "/").layer(
+            axum::middleware::from_fn(move |req, next: Next| basic_auth_mw(st.clone(), req, next)),
+        );
+        let status = |uri: &str| {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+
+        assert_eq!(status("/private/secret.txt").await, StatusCode::UNAUTHORIZED);
+        // Encoded and unnormalized spellings of the same file are protected too
+        assert_eq!(status("/%70rivate/secret.txt").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/private%2Fsecret.txt").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("//private/./secret.txt").await, StatusCode::UNAUTHORIZED);
+        // Only whole segments match
+        assert_eq!(status("/privateer.txt").await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_parse_basic_credentials() {
+        assert_eq!(parse_basic_credentials("Basic not*base64"), None);
+        assert_eq!(
+            parse_basic_credentials("basic YWxpY2U6b3BlbjpzZXNhbWU="),
+            Some(("alice".into(), "open:sesame".into()))
+        );
+        assert_eq!(parse_basic_credentials("Bearer YWxpY2U6eA=="), None);
+        assert_eq!(parse_basic_credentials("Basic bm9jb2xvbg=="), None);
+    }
+
+    /// `Write` into a buffer the test keeps a handle to
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn access_log_line(format: AccessLogFormat, req: Request<Body>) -> String {
+        let buf = SharedBuf::default();
+        let sink = Arc::new(AccessLogSink::new(format, Box::new(buf.clone())));
+        let mw_sink = sink.clone();
+        let state = test_state(Config::default());
+        let app = Router::new()
+            .route("/hello", get(|| async { "hello world" }))
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                access_log_mw(state.clone(), mw_sink.clone(), req, next)
+            }));
+        app.oneshot(req).await.unwrap();
+        sink.flush();
+        let out = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        out.strip_suffix('\n').unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_access_log_common() {
+        let mut req = Request::builder()
+            .uri("/hello?x=1")
+            .header(AUTHORIZATION, format!("Basic {}", BASE64_STANDARD.encode("alice:pw")))
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(ConnectInfo(std::net::SocketAddr::from(([192, 0, 2, 7], 1))));
+        let line = access_log_line(AccessLogFormat::Common, req).await;
+        // host ident user [time] "request" status bytes
+        let clf = regex::Regex::new(concat!(
+            r"^192\.0\.2\.7 - alice ",
+            r"\[\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}:\d{2} \+0000\] ",
+            r#""GET /hello\?x=1 HTTP/1\.1" 200 11$"#,
+        ))
+        .unwrap();
+        assert!(clf.is_match(&line), "{line}");
+    }
+
+    #[tokio::test]
+    async fn test_access_log_combined() {
+        let req = Request::builder()
+            .uri("/missing")
+            .header(REFERER, "https://example.com/")
+            .header(USER_AGENT, "curl/8.0 \"quoted\"")
+            .body(Body::empty())
+            .unwrap();
+        let line = access_log_line(AccessLogFormat::Combined, req).await;
+        assert!(line.starts_with("- - - ["), "{line}");
+        let tail = concat!(
+            r#"] "GET /missing HTTP/1.1" 404 - "#,
+            r#""https://example.com/" "curl/8.0 \"quoted\"""#,
+        );
+        assert!(line.ends_with(tail), "{line}");
+    }
+
+    async fn request_id_response(req: Request<Body>) -> (Response, Option<HeaderValue>) {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|axum::Extension(id): axum::Extension<RequestId>| async move {
+                    id.0.to_str().unwrap().to_string()
+                }),
+            )
+            .layer(axum::middleware::from_fn(request_id_mw));
+        let res = app.oneshot(req).await.unwrap();
+        let echoed = res.headers().get(&X_REQUEST_ID).cloned();
+        (res, echoed)
+    }
+
+    #[tokio::test]
+    async fn test_request_id_generated() {
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let (res, echoed) = request_id_response(req).await;
+        let id = echoed.unwrap().to_str().unwrap().to_string();
+        let uuid = regex::Regex::new(concat!(
+            r"^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-",
+            r"[89ab][0-9a-f]{3}-[0-9a-f]{12}$",
+        ))
+        .unwrap();
+        assert!(uuid.is_match(&id), "{id}");
+        // The handler saw the same ID in the request extensions
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, id.as_bytes());
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let (_, other) = request_id_response(req).await;
+        assert_ne!(other.unwrap(), id.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_request_id_preserved() {
+        let req = Request::builder()
+            .uri("/")
+            .header("x-request-id", "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let (res, echoed) = request_id_response(req).await;
+        assert_eq!(echoed.unwrap(), "abc-123");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"abc-123");
+
+        // Empty IDs are replaced
+        let req = Request::builder()
+            .uri("/")
+            .header("x-request-id", "")
+            .body(Body::empty())
+            .unwrap();
+        let (_, echoed) = request_id_response(req).await;
+        assert_eq!(echoed.unwrap().len(), 36);
+    }
+
+    #[test]
+    fn test_clf_time() {
+        let time = std::time::UNIX_EPOCH + Duration::from_secs(971_187_336);
+        assert_eq!(clf_time(time), "10/Oct/2000:14:15:36 +0000");
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter_mw() {
+        let mut cfg = Config::default();
+        cfg.security.ip_filter.allow = vec!["10.0.0.0/8".into(), "2001:db8::/32".into()];
+        cfg.security.ip_filter.deny = vec!["10.0.0.66".into()];
+        let rules = crate::state::compile_ip_filter(&cfg).unwrap().unwrap();
+        let state = test_state(cfg);
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                ip_filter_mw(state.clone(), rules.clone(), req, next)
+            }));
+        let from = |ip: Option<&str>| {
+            let mut req = Request::builder().uri("/").body(Body::empty()).unwrap();
+            if let Some(ip) = ip {
+                let peer = std::net::SocketAddr::new(ip.parse().unwrap(), 4000);
+                req.extensions_mut().insert(ConnectInfo(peer));
+            }
+            req
+        };
+
+        for ip in ["10.1.2.3", "2001:db8::1"] {
+            let res = app.clone().oneshot(from(Some(ip))).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "{ip}");
+        }
+        // Denied inside an allowed range, outside the allowlist, unidentifiable
+        for ip in [Some("10.0.0.66"), Some("192.0.2.1"), Some("2001:db9::1"), None] {
+            let res = app.clone().oneshot(from(ip)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{ip:?}");
+        }
+    }
+
+    #[test]
+    fn test_ip_rules_without_allowlist() {
+        let mut cfg = Config::default();
+        cfg.security.ip_filter.deny = vec!["192.0.2.0/24".into(), "2001:db8::/32".into()];
+        let rules = crate::state::compile_ip_filter(&cfg).unwrap().unwrap();
+        assert!(!rules.is_allowed("192.0.2.7".parse().unwrap()));
+        assert!(!rules.is_allowed("::ffff:192.0.2.7".parse().unwrap()));
+        assert!(!rules.is_allowed("2001:db8::7".parse().unwrap()));
+        assert!(rules.is_allowed("198.51.100.7".parse().unwrap()));
+        assert!(rules.is_allowed("::1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_mw_rejects_excess_requests() {
+        let mut cfg = Config::default();
+        cfg.security.per_ip_concurrency = Some(1);
+        let state = AppState {
+            concurrency: crate::state::build_concurrency_limiter(&cfg),
+            ..test_state(cfg)
+        };
+        let gate = Arc::new(tokio::sync::Notify::new());
+        let slow_gate = gate.clone();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(move || {
+                    let gate = slow_gate.clone();
+                    async move {
+                        gate.notified().await;
+                        "done"
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                concurrency_mw(state.clone(), req, next)
+            }));
+        let from = |ip: &'static str| {
+            let mut req = Request::builder().uri("/slow").body(Body::empty()).unwrap();
+            let peer = std::net::SocketAddr::new(ip.parse().unwrap(), 4000);
+            req.extensions_mut().insert(ConnectInfo(peer));
+            req
+        };
+
+        let first = tokio::spawn(app.clone().oneshot(from("192.0.2.1")));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let res = app.clone().oneshot(from("192.0.2.1")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // A different client is unaffected
+        let second = tokio::spawn(app.clone().oneshot(from("192.0.2.2")));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        gate.notify_waiters();
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+        assert_eq!(second.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        // The slot is released once the first request completes
+        let third = tokio::spawn(app.oneshot(from("192.0.2.1")));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        gate.notify_waiters();
+        assert_eq!(third.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_mw_holds_slot_while_streaming() {
+        let mut cfg = Config::default();
+        cfg.security.per_ip_concurrency = Some(1);
+        let state = AppState {
+            concurrency: crate::state::build_concurrency_limiter(&cfg),
+            ..test_state(cfg)
+        };
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<axum::body::Bytes, std::io::Error>>(1);
+        let rx = Arc::new(std::sync::Mutex::new(Some(rx)));
+        let app = Router::new()
+            .route(
+                "/stream",
+                get(move || {
+                    let rx = rx.lock().unwrap().take();
+                    async move {
+                        match rx {
+                            Some(mut rx) => Body::from_stream(futures_util::stream::poll_fn(
+                                move |cx| rx.poll_recv(cx),
+                            )),
+                            None => Body::from("done"),
+                        }
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn(move |req, next: Next| {
+                concurrency_mw(state.clone(), req, next)
+            }));
+        let request = || {
+            let mut req = Request::builder().uri("/stream").body(Body::empty()).unwrap();
+            let peer = std::net::SocketAddr::from(([192, 0, 2, 1], 4000));
+            req.extensions_mut().insert(ConnectInfo(peer));
+            req
+        };
+
+        // The head is out, but the body is still streaming
+        let streaming = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(streaming.status(), StatusCode::OK);
+        let res = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Finishing the body releases the slot
+        tx.send(Ok("chunk".into())).await.unwrap();
+        drop(tx);
+        let body = axum::body::to_bytes(streaming.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"chunk");
+        let res = app.oneshot(request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
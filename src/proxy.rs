@@ -1,48 +1,398 @@
-use crate::config::Proxy;
-use crate::state::HTTP_CLIENT;
+use crate::config::{Config, IpRange, LbStrategy, Proxy, ProxyClient};
+use crate::middleware::{RequestId, X_REQUEST_ID};
+use crate::router::with_route_tag;
+use crate::server::ClientIdentity;
+use crate::state::{build_http_client, shared_http_client, HttpClient};
+use crate::utils::{forwarded_client, peer_addr, trusted_client};
 use axum::{
-    body::Body,
+    body::{Body, HttpBody},
     extract::Path,
-    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
     response::{IntoResponse, Response},
     routing::any,
 };
 use bytes::Bytes;
 use futures_util::TryStreamExt;
 use http::Request;
-use http_body_util::BodyStream;
-use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use http_body_util::{BodyStream, LengthLimitError, Limited};
+use http::header::{
+    CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION, SET_COOKIE, UPGRADE,
+};
+use hyper_util::rt::TokioIo;
+use rand::Rng;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
 
 /// Proxy state for a route
 #[derive(Clone)]
 pub struct ProxyState {
-    pub target: String,
+    /// Upstream base URLs, without trailing slash
+    pub targets: Vec<String>,
+    pub strategy: LbStrategy,
+    /// Round-robin position
+    pub next: Arc<AtomicUsize>,
+    /// Passive health of each target, by index
+    pub health: Arc<Vec<UpstreamHealth>>,
+    /// Consecutive failures before ejection (0 disables ejection)
+    pub max_failures: u32,
+    pub eject_duration: Duration,
+    /// Mount path (without `*`) prepended to `tail` unless `strip_prefix`
+    pub prefix: String,
+    pub strip_prefix: bool,
+    /// Compiled `rewrite` rule: pattern and replacement
+    pub rewrite: Option<(Regex, String)>,
+    /// Map upstream `Location` headers to this route (`rewrite_redirects`)
+    pub rewrite_redirects: bool,
+    /// Request bodies beyond this many bytes are refused with 413
+    pub max_body_size: Option<u64>,
     pub timeout: Duration,
     pub add_headers: Vec<(HeaderName, String)>,
+    /// Upstream status -> (local file, response status)
+    pub error_overrides: HashMap<StatusCode, (PathBuf, StatusCode)>,
+    /// Upstream client; routes with custom connection settings get their own pool
+    pub client: HttpClient,
+    /// HTTP/1.1-only client for WebSocket handshakes, which can't upgrade over h2
+    pub upgrade_client: HttpClient,
+    /// Trusted reverse proxies in front of statiker, for `{client_ip}`
+    pub forwarded_hops: usize,
+    /// Peers whose `X-Forwarded-For` is honored for `{client_ip}` (`server.trusted_proxies`)
+    pub trusted_proxies: Vec<IpRange>,
+    /// Mark upstream cookies `Secure` (`force_secure_cookies`, TLS only)
+    pub secure_cookies: bool,
+    /// Tunnel WebSocket upgrades to the upstream
+    pub websocket: bool,
+    /// Scheme for `X-Forwarded-Proto`, `None` when `forward_headers` is off
+    pub forwarded_proto: Option<&'static str>,
+    /// Extra attempts on connection errors and 502/503/504
+    pub retries: u32,
+    pub retry_backoff: Duration,
+    pub retry_non_idempotent: bool,
 }
 
 impl ProxyState {
-    pub fn new(p: Proxy) -> Self {
+    pub fn new(p: Proxy, client: &ProxyClient) -> Self {
         let add_headers = p
             .add_headers
+            .into_entries()
             .into_iter()
             .filter_map(|(k, v)| HeaderName::from_str(&k).ok().map(|n| (n, v)))
             .collect::<Vec<_>>();
+        let error_overrides = p
+            .error_overrides
+            .into_iter()
+            .filter_map(|(code, o)| {
+                let upstream = StatusCode::from_u16(code).ok()?;
+                let status = match o.status {
+                    Some(s) => StatusCode::from_u16(s).ok()?,
+                    None => upstream,
+                };
+                Some((upstream, (o.file, status)))
+            })
+            .collect();
+        let targets: Vec<String> = std::iter::once(&p.url)
+            .chain(&p.urls)
+            .filter(|url| !url.is_empty())
+            .map(|url| url.trim_end_matches('/').to_string())
+            .collect();
+        let mut client = client.clone();
+        if let Some(connect_timeout) = p.connect_timeout {
+            client.connect_timeout = connect_timeout;
+        }
+        let mut http1 = client.clone();
+        http1.http2 = false;
+        let build = |settings: &ProxyClient| match p.tcp_keepalive {
+            Some(ka) => build_http_client(settings, Some(ka)),
+            None => shared_http_client(settings),
+        };
         Self {
-            target: p.url.trim_end_matches('/').to_string(),
+            client: build(&client),
+            upgrade_client: build(&http1),
+            health: Arc::new(targets.iter().map(|_| UpstreamHealth::default()).collect()),
+            targets,
+            strategy: p.strategy,
+            next: Arc::default(),
+            max_failures: p.max_failures,
+            eject_duration: match p.eject_duration {
+                Duration::ZERO => Duration::from_secs(30),
+                d => d,
+            },
+            prefix: String::from("/"),
+            strip_prefix: p.strip_prefix,
+            // Patterns are validated when the router is built
+            rewrite: p
+                .rewrite
+                .and_then(|r| Regex::new(&r.from).ok().map(|re| (re, r.to))),
+            rewrite_redirects: p.rewrite_redirects,
+            max_body_size: p.max_body_size.map(|size| size.0),
             timeout: if p.timeout == Duration::ZERO {
                 Duration::from_secs(5)
             } else {
                 p.timeout
             },
             add_headers,
+            error_overrides,
+            forwarded_hops: 0,
+            trusted_proxies: Vec::new(),
+            secure_cookies: p.force_secure_cookies,
+            websocket: p.websocket,
+            forwarded_proto: p.forward_headers.then_some("http"),
+            retries: p.retries,
+            retry_backoff: p.retry_backoff,
+            retry_non_idempotent: p.retry_non_idempotent,
+        }
+    }
+
+    /// Pick an upstream per `strategy`, returning its index
+    ///
+    /// Ejected upstreams are skipped; if all are ejected, the upstream the
+    /// strategy chose is used anyway.
+    pub fn pick_target(&self) -> usize {
+        let start = match (self.targets.len(), self.strategy) {
+            (0 | 1, _) => return 0,
+            (n, LbStrategy::RoundRobin) => self.next.fetch_add(1, Ordering::Relaxed) % n,
+            (n, LbStrategy::Random) => rand::thread_rng().gen_range(0..n),
+        };
+        let n = self.targets.len();
+        (0..n)
+            .map(|i| (start + i) % n)
+            .find(|&i| !self.health[i].is_ejected())
+            .unwrap_or(start)
+    }
+
+    /// Upstream URI for `path` (path and query) on the next picked target,
+    /// with the target's index
+    pub fn upstream_uri(&self, path: &str) -> Option<(usize, Uri)> {
+        let index = self.pick_target();
+        let target = self.targets.get(index)?;
+        Some((index, Uri::from_str(&format!("{target}{path}")).ok()?))
+    }
+
+    /// Upstream request path (without query) for the captured `tail`
+    ///
+    /// `tail` is what the `*tail` capture matched after the mount path. With
+    /// `strip_prefix` it is forwarded as `/{tail}`, otherwise the mount path is
+    /// kept in front of it. The `rewrite` rule is applied last.
+    pub fn upstream_path(&self, tail: &str) -> String {
+        let path = match self.strip_prefix {
+            true => format!("/{tail}"),
+            false => format!("{}{tail}", self.prefix),
+        };
+        match &self.rewrite {
+            Some((re, to)) => re.replace(&path, to.as_str()).into_owned(),
+            None => path,
+        }
+    }
+
+    /// Public path for an upstream `Location` that points at one of the targets
+    ///
+    /// The location must have a target's origin and lie under its base path;
+    /// the mount path is put back in front unless the upstream already sees
+    /// it (`strip_prefix: false`). The `rewrite` rule can't be reversed, so it
+    /// is not undone. Relative locations and other origins give `None`.
+    pub fn public_location(&self, location: &str) -> Option<String> {
+        let (location, fragment) = match location.split_once('#') {
+            Some((loc, frag)) => (loc, Some(frag)),
+            None => (location, None),
+        };
+        let loc = Uri::from_str(location).ok()?;
+        loc.scheme()?;
+        let path = loc.path_and_query().map_or("/", |pq| pq.as_str());
+        let rest = self.targets.iter().find_map(|target| {
+            let target = Uri::from_str(target).ok()?;
+            if !same_origin(&target, &loc) {
+                return None;
+            }
+            let rest = path.strip_prefix(target.path().trim_end_matches('/'))?;
+            match rest.chars().next() {
+                None | Some('?') => Some(format!("/{rest}")),
+                Some('/') => Some(rest.to_string()),
+                Some(_) => None,
+            }
+        })?;
+        let mut public = match self.strip_prefix {
+            true => format!("{}{rest}", self.prefix.trim_end_matches('/')),
+            false => rest,
+        };
+        if let Some(fragment) = fragment {
+            public.push('#');
+            public.push_str(fragment);
+        }
+        Some(public)
+    }
+
+    /// Record the outcome of a request to target `index`
+    pub fn record(&self, index: usize, ok: bool) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let Some(health) = self.health.get(index) else {
+            return;
+        };
+        if ok {
+            health.failures.store(0, Ordering::Relaxed);
+        } else if health.failures.fetch_add(1, Ordering::Relaxed) + 1 >= self.max_failures {
+            health.failures.store(0, Ordering::Relaxed);
+            *health.ejected_until.lock().unwrap() = Some(Instant::now() + self.eject_duration);
+            warn!(
+                "ejecting upstream {} for {:?}",
+                self.targets[index], self.eject_duration
+            );
+        }
+    }
+}
+
+/// Passive health state of one upstream
+#[derive(Debug, Default)]
+pub struct UpstreamHealth {
+    /// Consecutive failed requests
+    pub failures: AtomicU32,
+    /// Skipped by selection until this instant
+    pub ejected_until: Mutex<Option<Instant>>,
+}
+
+impl UpstreamHealth {
+    pub fn is_ejected(&self) -> bool {
+        self.ejected_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| Instant::now() < until)
+    }
+}
+
+/// Why an upstream attempt produced no response
+#[derive(Debug)]
+pub enum UpstreamError {
+    /// No usable upstream URL for the request
+    InvalidUri,
+    /// The connection couldn't be established, e.g. refused or `connect_timeout`
+    Connect(hyper_util::client::legacy::Error),
+    /// The connection failed after it was established
+    Transport(hyper_util::client::legacy::Error),
+    /// No response head within `timeout`
+    Timeout(Duration),
+    /// The request body exceeded `max_body_size` while being streamed
+    BodyTooLarge,
+}
+
+impl UpstreamError {
+    /// Status returned to the client
+    ///
+    /// A timeout is 504 and a failed connection 502. An invalid upstream URL
+    /// is a configuration problem rather than an upstream one, so it is 500.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UpstreamError::InvalidUri => StatusCode::INTERNAL_SERVER_ERROR,
+            UpstreamError::Connect(_) | UpstreamError::Transport(_) => StatusCode::BAD_GATEWAY,
+            UpstreamError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            UpstreamError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::InvalidUri => write!(f, "invalid upstream URI"),
+            UpstreamError::Connect(e) => write!(f, "connect failed: {}", error_chain(e)),
+            UpstreamError::Transport(e) => write!(f, "request failed: {}", error_chain(e)),
+            UpstreamError::Timeout(t) => write!(f, "no response within {t:?}"),
+            UpstreamError::BodyTooLarge => write!(f, "request body exceeds max_body_size"),
+        }
+    }
+}
+
+/// An error and its sources, joined with `: `
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut out = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        out.push_str(": ");
+        out.push_str(&e.to_string());
+        source = e.source();
+    }
+    out
+}
+
+/// Send one request to upstream `index` with `client`, bounded by `timeout`
+///
+/// The connect timeout lives in the client's connector, so a slow connect
+/// surfaces as [`UpstreamError::Connect`] unless `timeout` runs out first.
+async fn send_upstream(
+    pstate: &ProxyState,
+    client: &HttpClient,
+    index: usize,
+    req: Request<Body>,
+) -> Result<http::Response<hyper::body::Incoming>, UpstreamError> {
+    let res = match tokio::time::timeout(pstate.timeout, client.request(req)).await {
+        Ok(Ok(res)) => Ok(res),
+        Ok(Err(e)) if e.is_connect() => Err(UpstreamError::Connect(e)),
+        Ok(Err(e)) if exceeded_body_limit(&e) => Err(UpstreamError::BodyTooLarge),
+        Ok(Err(e)) => Err(UpstreamError::Transport(e)),
+        Err(_) => Err(UpstreamError::Timeout(pstate.timeout)),
+    };
+    match &res {
+        // The client's fault, not the upstream's
+        Err(e @ UpstreamError::BodyTooLarge) => {
+            debug!("upstream {}: {e}", pstate.targets[index]);
+            return res;
         }
+        Err(e) => warn!("upstream {}: {e}", pstate.targets[index]),
+        Ok(_) => {}
     }
+    pstate.record(index, !is_upstream_failure(res.as_ref()));
+    res
+}
+
+/// Whether a client error was caused by the `max_body_size` limit
+fn exceeded_body_limit(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Whether an upstream attempt failed: no response, or 502/503/504
+fn is_upstream_failure<B, E>(res: Result<&http::Response<B>, E>) -> bool {
+    res.map_or(true, |r| {
+        matches!(
+            r.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        )
+    })
 }
 
 /// Create a proxy route handler
-pub fn make_proxy_route(base: &str, p: Proxy) -> (String, axum::routing::MethodRouter) {
-    let ps = Arc::new(ProxyState::new(p));
+pub fn make_proxy_route(
+    base: &str,
+    p: Proxy,
+    cfg: &Config,
+) -> (String, axum::routing::MethodRouter) {
+    let mut ps = ProxyState::new(p, &cfg.proxy_client);
+    ps.prefix = base.trim_end_matches('*').to_string();
+    ps.forwarded_hops = cfg.security.forwarded_hops;
+    ps.trusted_proxies = cfg.server.trusted_proxies.clone();
+    // Over plain HTTP a Secure cookie would never be sent back
+    ps.secure_cookies &= cfg.tls.enabled;
+    if cfg.tls.enabled && ps.forwarded_proto.is_some() {
+        ps.forwarded_proto = Some("https");
+    }
+    let ps = Arc::new(ps);
     let route_path = format!("{}*tail", base.trim_end_matches('*'));
     let handler = {
         let ps = ps.clone();
@@ -51,42 +401,101 @@ pub fn make_proxy_route(base: &str, p: Proxy) -> (String, axum::routing::MethodR
             async move { proxy_forward(ps, tail, req).await }
         })
     };
-    (route_path, handler)
+    (route_path, with_route_tag(handler, cfg, "proxy", base))
 }
 
 /// Forward a request to the upstream proxy
 pub async fn proxy_forward(pstate: Arc<ProxyState>, tail: String, mut req: Request<Body>) -> Response {
-    let mut upstream = format!("{}/{}", pstate.target, tail);
+    let mut path = pstate.upstream_path(&tail);
     if let Some(q) = req.uri().query() {
-        upstream.push('?');
-        upstream.push_str(q);
+        path.push('?');
+        path.push_str(q);
     }
-    let Ok(uri) = Uri::from_str(&upstream) else {
-        return StatusCode::BAD_GATEWAY.into_response();
-    };
-
-    *req.uri_mut() = uri;
 
-    // Add configured headers (supports {client_ip})
-    let client_ip = client_ip(&req).unwrap_or_else(|| "unknown".into());
+    // Add configured headers (supports {client_ip}, {request_id} and {client_cn}). The first
+    // occurrence of a name replaces any client-sent value; repeated names are appended.
+    let client_ip = client_ip(&req, pstate.forwarded_hops, &pstate.trusted_proxies)
+        .unwrap_or_else(|| "unknown".into());
+    if let Some(proto) = pstate.forwarded_proto {
+        add_forwarded_headers(&mut req, proto);
+    }
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    if let Some(id) = &request_id {
+        req.headers_mut().insert(X_REQUEST_ID.clone(), id.clone());
+    }
+    let request_id = request_id.as_ref().and_then(|id| id.to_str().ok()).unwrap_or_default();
+    let client_cn = req
+        .extensions()
+        .get::<ClientIdentity>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default();
+    let mut seen: Vec<&HeaderName> = Vec::new();
     for (k, v) in &pstate.add_headers {
-        let vv = v.replace("{client_ip}", &client_ip);
+        let vv = v
+            .replace("{client_ip}", &client_ip)
+            .replace("{request_id}", request_id)
+            .replace("{client_cn}", &client_cn);
         if let Ok(hv) = HeaderValue::from_str(&vv) {
-            req.headers_mut().insert(k.clone(), hv);
+            if seen.contains(&k) {
+                req.headers_mut().append(k.clone(), hv);
+            } else {
+                req.headers_mut().insert(k.clone(), hv);
+                seen.push(k);
+            }
         }
     }
 
+    // WebSocket handshakes keep their upgrade headers; the connection is
+    // tunneled once the upstream switches protocols
+    if pstate.websocket && is_websocket_upgrade(req.headers()) {
+        return websocket_forward(&pstate, req, &path).await;
+    }
+
     // Remove hop-by-hop headers
     strip_hop_by_hop(req.headers_mut());
 
-    match tokio::time::timeout(pstate.timeout, HTTP_CLIENT.request(req)).await {
-        Ok(Ok(upstream_res)) => {
+    // The body is streamed through as it arrives. A declared length over the
+    // limit is refused upfront; other bodies are cut off once they exceed it.
+    if let Some(limit) = pstate.max_body_size {
+        let declared = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+        if declared.is_some_and(|len| len > limit) {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        }
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+        req = req.map(|body| Body::new(Limited::new(body, limit)));
+    }
+
+    // The timeout only covers waiting for the upstream's response head. Bodies are
+    // streamed frame by frame without a deadline, so long-lived streams such as
+    // Server-Sent Events (`text/event-stream`) stay open while the upstream is quiet.
+    match send_with_retries(&pstate, req, &path).await {
+        Ok(upstream_res) => {
+            if let Some((file, status)) = pstate.error_overrides.get(&upstream_res.status()) {
+                match error_override_response(file, *status).await {
+                    Ok(res) => return res,
+                    Err(e) => warn!("error override {:?} unavailable: {e}", file),
+                }
+            }
+
             // Copy status/headers; stream body through using BodyStream
             let mut builder = Response::builder()
                 .status(upstream_res.status())
                 .version(upstream_res.version());
             let mut headers = upstream_res.headers().clone();
             strip_hop_by_hop(&mut headers);
+            if pstate.secure_cookies {
+                force_secure_cookies(&mut headers);
+            }
+            if pstate.rewrite_redirects {
+                let public = headers.get(LOCATION).and_then(|v| v.to_str().ok());
+                let public = public.and_then(|loc| pstate.public_location(loc));
+                if let Some(hv) = public.and_then(|p| HeaderValue::from_str(&p).ok()) {
+                    headers.insert(LOCATION, hv);
+                }
+            }
             *builder.headers_mut().unwrap() = headers;
 
             let incoming = upstream_res.into_body(); // hyper::body::Incoming
@@ -95,10 +504,176 @@ pub async fn proxy_forward(pstate: Arc<ProxyState>, tail: String, mut req: Reque
             let body = Body::from_stream(stream); // axum::body::Body
             builder.body(body).unwrap()
         }
-        _ => StatusCode::BAD_GATEWAY.into_response(),
+        Err(e) => e.status().into_response(),
     }
 }
 
+/// Send a request for `path` upstream, retrying per the route's `retries` setting
+///
+/// Every attempt picks an upstream anew, so retries move on to the next one.
+/// Only bodiless requests are retried, since the body can't be replayed:
+/// GET and HEAD, plus other methods with `retry_non_idempotent`. Returns
+/// the last attempt's error when it got no response.
+async fn send_with_retries(
+    pstate: &ProxyState,
+    req: Request<Body>,
+    path: &str,
+) -> Result<http::Response<hyper::body::Incoming>, UpstreamError> {
+    let send = |mut req: Request<Body>| async move {
+        let Some((index, uri)) = pstate.upstream_uri(path) else {
+            warn!("no valid upstream URI for {path}");
+            return Err(UpstreamError::InvalidUri);
+        };
+        *req.uri_mut() = uri;
+        send_upstream(pstate, &pstate.client, index, req).await
+    };
+    let idempotent = matches!(*req.method(), Method::GET | Method::HEAD);
+    let retriable = (idempotent || pstate.retry_non_idempotent)
+        && req.body().size_hint().exact() == Some(0);
+    if pstate.retries == 0 || !retriable {
+        return send(req).await;
+    }
+
+    let (parts, _) = req.into_parts();
+    let mut attempt = 0;
+    loop {
+        let mut retry = Request::builder()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .version(parts.version)
+            .body(Body::empty())
+            .unwrap();
+        *retry.headers_mut() = parts.headers.clone();
+        let res = send(retry).await;
+        // An invalid upstream URL won't get any better on a retry
+        let invalid = matches!(res, Err(UpstreamError::InvalidUri));
+        if invalid || !is_upstream_failure(res.as_ref()) || attempt == pstate.retries {
+            return res;
+        }
+        attempt += 1;
+        debug!("retrying {} {path} (attempt {attempt})", parts.method);
+        tokio::time::sleep(pstate.retry_backoff).await;
+    }
+}
+
+/// Set the standard `X-Forwarded-*` headers on an outgoing request
+///
+/// The peer address is appended to any existing `X-Forwarded-For` chain;
+/// `X-Forwarded-Host` carries the original `Host` header.
+pub fn add_forwarded_headers<B>(req: &mut Request<B>, proto: &'static str) {
+    if let Some(peer) = peer_addr(req).map(|a| a.ip()) {
+        let chain = match req.headers().get(X_FORWARDED_FOR).map(|v| v.to_str()) {
+            Some(Ok(existing)) if !existing.trim().is_empty() => format!("{existing}, {peer}"),
+            _ => peer.to_string(),
+        };
+        if let Ok(hv) = HeaderValue::from_str(&chain) {
+            req.headers_mut().insert(X_FORWARDED_FOR, hv);
+        }
+    }
+    req.headers_mut()
+        .insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+    if let Some(host) = req.headers().get(HOST).cloned() {
+        req.headers_mut().insert(X_FORWARDED_HOST, host);
+    }
+}
+
+/// Whether a request asks to upgrade the connection to WebSocket
+pub fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers.get_all(CONNECTION).iter().any(|v| {
+        v.to_str()
+            .is_ok_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("upgrade")))
+    });
+    let websocket = headers
+        .get(UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("websocket"));
+    connection_upgrade && websocket
+}
+
+/// Forward a WebSocket handshake and tunnel the upgraded connections
+///
+/// After the upstream answers `101 Switching Protocols`, bytes are copied in
+/// both directions until either side closes. Any other upstream response is
+/// relayed as-is.
+async fn websocket_forward(pstate: &ProxyState, mut req: Request<Body>, path: &str) -> Response {
+    let Some((index, uri)) = pstate.upstream_uri(path) else {
+        warn!("no valid upstream URI for {path}");
+        return UpstreamError::InvalidUri.status().into_response();
+    };
+    *req.uri_mut() = uri;
+    let client_upgrade = hyper::upgrade::on(&mut req);
+    strip_hop_by_hop(req.headers_mut());
+    req.headers_mut()
+        .insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    req.headers_mut()
+        .insert(UPGRADE, HeaderValue::from_static("websocket"));
+    // The handshake carries no body
+    let (parts, _) = req.into_parts();
+    let req = Request::from_parts(parts, Body::empty());
+
+    let mut upstream_res = match send_upstream(pstate, &pstate.upgrade_client, index, req).await {
+        Ok(res) => res,
+        Err(e) => return e.status().into_response(),
+    };
+    if upstream_res.status() != StatusCode::SWITCHING_PROTOCOLS {
+        let mut builder = Response::builder().status(upstream_res.status());
+        let mut headers = upstream_res.headers().clone();
+        strip_hop_by_hop(&mut headers);
+        *builder.headers_mut().unwrap() = headers;
+        return builder.body(Body::new(upstream_res.into_body())).unwrap();
+    }
+
+    let upstream_upgrade = hyper::upgrade::on(&mut upstream_res);
+    tokio::spawn(async move {
+        let (client, upstream) = match tokio::try_join!(client_upgrade, upstream_upgrade) {
+            Ok(io) => io,
+            Err(e) => {
+                warn!("websocket upgrade failed: {e}");
+                return;
+            }
+        };
+        let mut client = TokioIo::new(client);
+        let mut upstream = TokioIo::new(upstream);
+        if let Err(e) = tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+            debug!("websocket tunnel closed: {e}");
+        }
+    });
+
+    // Relay the handshake response, including Upgrade/Connection
+    let mut builder = Response::builder().status(StatusCode::SWITCHING_PROTOCOLS);
+    *builder.headers_mut().unwrap() = upstream_res.headers().clone();
+    builder.body(Body::empty()).unwrap()
+}
+
+/// Build a response from a local error override file
+async fn error_override_response(file: &PathBuf, status: StatusCode) -> std::io::Result<Response> {
+    let bytes = tokio::fs::read(file).await?;
+    let mime = mime_guess::from_path(file).first_or_octet_stream();
+    let mut builder = Response::builder().status(status);
+    if let Ok(hv) = HeaderValue::from_str(mime.as_ref()) {
+        builder = builder.header(CONTENT_TYPE, hv);
+    }
+    Ok(builder.body(Body::from(bytes)).unwrap())
+}
+
+/// Whether two absolute URIs share scheme, host and (effective) port
+fn same_origin(a: &Uri, b: &Uri) -> bool {
+    let port = |u: &Uri| {
+        u.port_u16().or(match u.scheme_str() {
+            Some(s) if s.eq_ignore_ascii_case("https") => Some(443),
+            Some(s) if s.eq_ignore_ascii_case("http") => Some(80),
+            _ => None,
+        })
+    };
+    let (Some(sa), Some(sb)) = (a.scheme_str(), b.scheme_str()) else {
+        return false;
+    };
+    let (Some(ha), Some(hb)) = (a.host(), b.host()) else {
+        return false;
+    };
+    sa.eq_ignore_ascii_case(sb) && ha.eq_ignore_ascii_case(hb) && port(a) == port(b)
+}
+
 /// Remove hop-by-hop headers from a header map
 pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
     for h in [
@@ -116,14 +691,46 @@ pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
     }
 }
 
-/// Extract client IP from request
-pub fn client_ip<B>(req: &Request<B>) -> Option<String> {
-    if let Some(v) = req.headers().get("x-forwarded-for") {
-        return v.to_str().ok().map(|s| s.to_string());
+/// Add the `Secure` attribute to every `Set-Cookie` header lacking it
+pub fn force_secure_cookies(headers: &mut HeaderMap) {
+    let cookies: Vec<HeaderValue> = headers.get_all(SET_COOKIE).iter().cloned().collect();
+    if cookies.is_empty() {
+        return;
     }
-    req.extensions()
-        .get::<SocketAddr>()
-        .map(|a| a.ip().to_string())
+    headers.remove(SET_COOKIE);
+    for cookie in cookies {
+        let secure = cookie.to_str().map_or(true, |s| {
+            s.split(';')
+                .skip(1)
+                .any(|attr| attr.trim().eq_ignore_ascii_case("secure"))
+        });
+        let value = if secure {
+            cookie
+        } else {
+            let mut bytes = cookie.as_bytes().to_vec();
+            bytes.extend_from_slice(b"; Secure");
+            HeaderValue::from_bytes(&bytes).unwrap_or(cookie)
+        };
+        headers.append(SET_COOKIE, value);
+    }
+}
+
+/// Extract client IP from request, behind `hops` trusted reverse proxies
+///
+/// With `trusted` ranges, only a trusted peer's `X-Forwarded-For` is consulted
+/// (see [`trusted_client`]). Otherwise, with `hops > 0` the entry selected by
+/// [`forwarded_client`] is used. With neither, the header is client-controlled
+/// and ignored in favor of the socket address.
+pub fn client_ip<B>(req: &Request<B>, hops: usize, trusted: &[IpRange]) -> Option<String> {
+    if !trusted.is_empty() {
+        let peer = peer_addr(req)?.ip();
+        let xff = req.headers().get("x-forwarded-for").and_then(|v| v.to_str().ok());
+        return Some(trusted_client(peer, xff, trusted).to_string());
+    }
+    if let (1.., Some(v)) = (hops, req.headers().get("x-forwarded-for")) {
+        return forwarded_client(v.to_str().ok()?, hops).map(str::to_string);
+    }
+    peer_addr(req).map(|a| a.ip().to_string())
 }
 
 #[cfg(test)]
@@ -132,6 +739,7 @@ mod tests {
     use crate::config::Proxy;
     use http::Request;
     use std::collections::HashMap;
+    use axum::extract::ConnectInfo;
     use std::net::SocketAddr;
 
     #[test]
@@ -139,10 +747,11 @@ mod tests {
         let proxy = Proxy {
             url: "https://example.com/".to_string(),
             timeout: Duration::from_secs(10),
-            add_headers: HashMap::new(),
+            add_headers: HashMap::new().into(),
+            ..Proxy::default()
         };
-        let state = ProxyState::new(proxy);
-        assert_eq!(state.target, "https://example.com");
+        let state = ProxyState::new(proxy, &ProxyClient::default());
+        assert_eq!(state.targets, ["https://example.com"]);
         assert_eq!(state.timeout, Duration::from_secs(10));
     }
 
@@ -151,9 +760,10 @@ mod tests {
         let proxy = Proxy {
             url: "https://example.com".to_string(),
             timeout: Duration::ZERO,
-            add_headers: HashMap::new(),
+            add_headers: HashMap::new().into(),
+            ..Proxy::default()
         };
-        let state = ProxyState::new(proxy);
+        let state = ProxyState::new(proxy, &ProxyClient::default());
         assert_eq!(state.timeout, Duration::from_secs(5));
     }
 
@@ -162,10 +772,11 @@ mod tests {
         let proxy = Proxy {
             url: "https://example.com/".to_string(),
             timeout: Duration::from_secs(5),
-            add_headers: HashMap::new(),
+            add_headers: HashMap::new().into(),
+            ..Proxy::default()
         };
-        let state = ProxyState::new(proxy);
-        assert_eq!(state.target, "https://example.com");
+        let state = ProxyState::new(proxy, &ProxyClient::default());
+        assert_eq!(state.targets, ["https://example.com"]);
     }
 
     #[test]
@@ -175,12 +786,50 @@ mod tests {
         let proxy = Proxy {
             url: "https://example.com".to_string(),
             timeout: Duration::from_secs(5),
-            add_headers: headers,
+            add_headers: headers.into(),
+            ..Proxy::default()
         };
-        let state = ProxyState::new(proxy);
+        let state = ProxyState::new(proxy, &ProxyClient::default());
         assert_eq!(state.add_headers.len(), 1);
     }
 
+    #[test]
+    fn test_proxy_state_add_headers_list_keeps_order_and_duplicates() {
+        let proxy: Proxy = serde_yaml::from_str(
+            r#"
+url: https://example.com
+add_headers:
+  - [X-Tag, b]
+  - [X-Other, c]
+  - [X-Tag, a]
+"#,
+        )
+        .unwrap();
+        let state = ProxyState::new(proxy, &ProxyClient::default());
+        let names: Vec<_> = state
+            .add_headers
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        assert_eq!(names, vec!["x-tag=b", "x-other=c", "x-tag=a"]);
+    }
+
+    #[test]
+    fn test_proxy_state_add_headers_map_compat() {
+        let proxy: Proxy = serde_yaml::from_str(
+            r#"
+url: https://example.com
+add_headers:
+  X-B: "2"
+  X-A: "1"
+"#,
+        )
+        .unwrap();
+        let state = ProxyState::new(proxy, &ProxyClient::default());
+        let names: Vec<_> = state.add_headers.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(names, vec!["x-a", "x-b"]);
+    }
+
     #[test]
     fn test_strip_hop_by_hop() {
         let mut headers = HeaderMap::new();
@@ -196,41 +845,973 @@ mod tests {
     }
 
     #[test]
-    fn test_client_ip_from_x_forwarded_for() {
-        let req = Request::builder()
+    fn test_client_ip_ignores_untrusted_x_forwarded_for() {
+        // Without `trusted_proxies` or hops any client could send the header
+        let mut req = Request::builder()
             .header("x-forwarded-for", "192.168.1.1")
             .body(())
             .unwrap();
-        let ip = client_ip(&req);
-        assert_eq!(ip, Some("192.168.1.1".to_string()));
+        assert_eq!(client_ip(&req, 0, &[]), None);
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 9], 4000))));
+        assert_eq!(client_ip(&req, 0, &[]), Some("203.0.113.9".to_string()));
     }
 
     #[test]
-    fn test_client_ip_from_x_forwarded_for_multiple() {
-        // Note: client_ip returns the full header value, not just the first IP
-        // The actual parsing happens in rate_limit_mw middleware
+    fn test_client_ip_with_hops() {
         let req = Request::builder()
             .header("x-forwarded-for", "192.168.1.1, 10.0.0.1")
             .body(())
             .unwrap();
-        let ip = client_ip(&req);
-        assert_eq!(ip, Some("192.168.1.1, 10.0.0.1".to_string()));
+        // Behind one trusted proxy, only its appended entry is trusted
+        assert_eq!(client_ip(&req, 1, &[]), Some("10.0.0.1".to_string()));
+        assert_eq!(client_ip(&req, 2, &[]), Some("192.168.1.1".to_string()));
+    }
+
+    #[test]
+    fn test_client_ip_trusted_proxies() {
+        let trusted = ["10.0.0.0/8".parse().unwrap(), "2001:db8::/32".parse().unwrap()];
+        let from = |peer: SocketAddr, xff: &str| {
+            let mut req = Request::builder().header("x-forwarded-for", xff).body(()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(peer));
+            client_ip(&req, 0, &trusted)
+        };
+        let untrusted = SocketAddr::from(([203, 0, 113, 9], 4000));
+        assert_eq!(from(untrusted, "1.2.3.4"), Some("203.0.113.9".to_string()));
+        let proxy = SocketAddr::from(([10, 0, 0, 1], 4000));
+        assert_eq!(from(proxy, "1.2.3.4, 198.51.100.7"), Some("198.51.100.7".to_string()));
+        assert_eq!(from(proxy, "1.2.3.4, 10.0.0.3"), Some("1.2.3.4".to_string()));
+        // Malformed entries end the walk at the last trusted hop
+        assert_eq!(from(proxy, "1.2.3.4, junk, 10.0.0.3"), Some("10.0.0.3".to_string()));
+        // IPv4-mapped peers of dual-stack sockets match IPv4 ranges
+        let mapped = SocketAddr::from(("::ffff:10.0.0.1".parse::<std::net::IpAddr>().unwrap(), 1));
+        assert_eq!(from(mapped, "198.51.100.7"), Some("198.51.100.7".to_string()));
+        let v6 = SocketAddr::from(("2001:db8::1".parse::<std::net::IpAddr>().unwrap(), 1));
+        assert_eq!(from(v6, "2001:db8::2, 198.51.100.7"), Some("198.51.100.7".to_string()));
     }
 
     #[test]
     fn test_client_ip_from_extensions() {
         let mut req = Request::builder().body(()).unwrap();
         let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-        req.extensions_mut().insert(addr);
-        let ip = client_ip(&req);
+        req.extensions_mut().insert(ConnectInfo(addr));
+        let ip = client_ip(&req, 0, &[]);
         assert_eq!(ip, Some("127.0.0.1".to_string()));
     }
 
     #[test]
     fn test_client_ip_none() {
         let req = Request::builder().body(()).unwrap();
-        let ip = client_ip(&req);
+        let ip = client_ip(&req, 0, &[]);
         assert_eq!(ip, None);
     }
-}
 
+    /// Start an upstream server on an ephemeral port and return its base URL
+    async fn spawn_upstream(app: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_proxied_identity_response_is_compressed() {
+        use tower::ServiceExt;
+
+        let html = "<p>hello</p>".repeat(200);
+        let upstream = {
+            let html = html.clone();
+            axum::Router::new().route(
+                "/page",
+                axum::routing::get(move || async move { axum::response::Html(html) }),
+            )
+        };
+        let url = spawn_upstream(upstream).await;
+
+        let mut cfg = crate::config::Config::default();
+        cfg.compression.enable = true;
+        let (route_path, handler) = make_proxy_route(
+            "/api/",
+            Proxy {
+                url,
+                ..Proxy::default()
+            },
+            &crate::config::Config::default(),
+        );
+        let app = axum::Router::new()
+            .route(&route_path, handler)
+            .layer(crate::router::build_compression(&cfg).unwrap());
+
+        let req = Request::builder()
+            .uri("/api/page")
+            .header("accept-encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("content-encoding").unwrap(), "gzip");
+        assert!(!res.headers().contains_key("content-length"));
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(body.len() < html.len());
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_headers_on_outbound_request() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new().route(
+            "/echo",
+            axum::routing::get(|headers: HeaderMap| async move {
+                ["x-forwarded-for", "x-forwarded-proto", "x-forwarded-host"]
+                    .map(|h| headers.get(h).map_or("-", |v| v.to_str().unwrap()).to_string())
+                    .join("|")
+            }),
+        );
+        let url = spawn_upstream(upstream).await;
+        let proxy = Proxy {
+            url,
+            ..Proxy::default()
+        };
+        let request = |xff: Option<&str>| {
+            let mut req = Request::builder()
+                .uri("/api/echo")
+                .header("host", "example.com");
+            if let Some(xff) = xff {
+                req = req.header("x-forwarded-for", xff);
+            }
+            let mut req = req.body(Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 4000))));
+            req
+        };
+        let body = |res: Response| async move {
+            let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let mut cfg = crate::config::Config::default();
+        cfg.tls.enabled = true;
+        let (route_path, handler) = make_proxy_route("/api/", proxy.clone(), &cfg);
+        let app = axum::Router::new().route(&route_path, handler);
+
+        let res = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(body(res).await, "203.0.113.7|https|example.com");
+        // An existing chain is extended, not replaced
+        let res = app.oneshot(request(Some("198.51.100.1"))).await.unwrap();
+        assert_eq!(
+            body(res).await,
+            "198.51.100.1, 203.0.113.7|https|example.com"
+        );
+
+        // Plain HTTP listener
+        let (route_path, handler) =
+            make_proxy_route("/api/", proxy.clone(), &crate::config::Config::default());
+        let app = axum::Router::new().route(&route_path, handler);
+        let res = app.oneshot(request(None)).await.unwrap();
+        assert_eq!(body(res).await, "203.0.113.7|http|example.com");
+
+        // Disabled
+        let proxy = Proxy {
+            forward_headers: false,
+            ..proxy
+        };
+        let (route_path, handler) = make_proxy_route("/api/", proxy, &cfg);
+        let app = axum::Router::new().route(&route_path, handler);
+        let res = app.oneshot(request(None)).await.unwrap();
+        assert_eq!(body(res).await, "-|-|-");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_forwarded_upstream() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new().route(
+            "/echo",
+            axum::routing::get(|headers: HeaderMap| async move {
+                ["x-request-id", "x-trace"]
+                    .map(|h| headers.get(h).map_or("-", |v| v.to_str().unwrap()).to_string())
+                    .join("|")
+            }),
+        );
+        let url = spawn_upstream(upstream).await;
+        let mut add_headers = HashMap::new();
+        add_headers.insert("X-Trace".to_string(), "req-{request_id}".to_string());
+        let proxy = Proxy {
+            url,
+            add_headers: add_headers.into(),
+            ..Proxy::default()
+        };
+        let (route_path, handler) =
+            make_proxy_route("/api/", proxy, &crate::config::Config::default());
+        let app = axum::Router::new()
+            .route(&route_path, handler)
+            .layer(axum::middleware::from_fn(crate::middleware::request_id_mw));
+
+        let req = Request::builder()
+            .uri("/api/echo")
+            .header("x-request-id", "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"abc-123|req-abc-123");
+
+        // A generated ID reaches the upstream and the client alike
+        let req = Request::builder().uri("/api/echo").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let id = res.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(String::from_utf8(bytes.to_vec()).unwrap(), format!("{id}|req-{id}"));
+    }
+
+    /// Upstream answering `503` to the first `failures` requests, then `200`
+    async fn spawn_flaky_upstream(failures: usize) -> (String, Arc<AtomicUsize>) {
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let upstream = axum::Router::new().route(
+            "/flaky",
+            any(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match n < failures {
+                        true => StatusCode::SERVICE_UNAVAILABLE,
+                        false => StatusCode::OK,
+                    }
+                }
+            }),
+        );
+        (spawn_upstream(upstream).await, calls)
+    }
+
+    #[tokio::test]
+    async fn test_proxy_prefix_stripping_and_rewrite() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new().fallback(|uri: Uri| async move { uri.to_string() });
+        let url = spawn_upstream(upstream).await;
+        let forwarded = |proxy: Proxy, uri: &'static str| {
+            let (route_path, handler) =
+                make_proxy_route("/api/*", proxy, &crate::config::Config::default());
+            let app = axum::Router::new().route(&route_path, handler);
+            async move {
+                let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+        let proxy = Proxy {
+            url,
+            ..Proxy::default()
+        };
+
+        // Mount path stripped by default
+        assert_eq!(
+            forwarded(proxy.clone(), "/api/users?page=2").await,
+            "/users?page=2"
+        );
+
+        let keep = Proxy {
+            strip_prefix: false,
+            ..proxy.clone()
+        };
+        assert_eq!(
+            forwarded(keep.clone(), "/api/users?page=2").await,
+            "/api/users?page=2"
+        );
+
+        let rewrite = Some(crate::config::Rewrite {
+            from: "^/v1/(.*)$".into(),
+            to: "/v2/$1".into(),
+        });
+        let rewritten = Proxy {
+            rewrite: rewrite.clone(),
+            ..proxy
+        };
+        assert_eq!(forwarded(rewritten.clone(), "/api/v1/items?x=1").await, "/v2/items?x=1");
+        assert_eq!(forwarded(rewritten, "/api/other").await, "/other");
+
+        // The rewrite sees the full path when the prefix is kept
+        let rewritten = Proxy { rewrite, ..keep };
+        assert_eq!(forwarded(rewritten, "/api/v1/items").await, "/api/v1/items");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_load_balancing() {
+        use tower::ServiceExt;
+
+        let named = |name: &'static str| {
+            axum::Router::new().route("/who", axum::routing::get(move || async move { name }))
+        };
+        let a = spawn_upstream(named("a")).await;
+        let b = spawn_upstream(named("b")).await;
+        let proxy: Proxy =
+            serde_yaml::from_str(&format!("url: {a}\nurls: [\"{b}/\"]")).unwrap();
+        assert_eq!(proxy.strategy, LbStrategy::RoundRobin);
+
+        let hits = |proxy: Proxy, n: usize| async move {
+            let (route_path, handler) =
+                make_proxy_route("/api/", proxy, &crate::config::Config::default());
+            let app = axum::Router::new().route(&route_path, handler);
+            let mut names = Vec::new();
+            for _ in 0..n {
+                let req = Request::builder().uri("/api/who").body(Body::empty()).unwrap();
+                let res = app.clone().oneshot(req).await.unwrap();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                names.push(String::from_utf8(body.to_vec()).unwrap());
+            }
+            names
+        };
+
+        assert_eq!(hits(proxy.clone(), 4).await, ["a", "b", "a", "b"]);
+
+        let random = Proxy {
+            strategy: LbStrategy::Random,
+            ..proxy
+        };
+        let names = hits(random, 40).await;
+        assert!(names.iter().any(|n| n == "a"));
+        assert!(names.iter().any(|n| n == "b"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_ejects_failing_upstream() {
+        use tower::ServiceExt;
+
+        let (bad, _) = spawn_flaky_upstream(usize::MAX).await;
+        let (good, _) = spawn_flaky_upstream(0).await;
+        let proxy = Proxy {
+            urls: vec![bad, good],
+            max_failures: 2,
+            eject_duration: Duration::from_millis(300),
+            ..Proxy::default()
+        };
+        let (route_path, handler) =
+            make_proxy_route("/api/", proxy, &crate::config::Config::default());
+        let app = axum::Router::new().route(&route_path, handler);
+        let statuses = |n: usize| {
+            let app = app.clone();
+            async move {
+                let mut statuses = Vec::new();
+                for _ in 0..n {
+                    let req = Request::builder().uri("/api/flaky").body(Body::empty()).unwrap();
+                    statuses.push(app.clone().oneshot(req).await.unwrap().status().as_u16());
+                }
+                statuses
+            }
+        };
+
+        // Alternates until the second consecutive failure ejects the bad upstream
+        assert_eq!(statuses(4).await, [503, 200, 503, 200]);
+        assert_eq!(statuses(4).await, [200, 200, 200, 200]);
+
+        // Re-admitted after the cooldown
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert!(statuses(2).await.contains(&503));
+    }
+
+    #[test]
+    fn test_pick_target_when_all_ejected() {
+        let proxy = Proxy {
+            urls: vec!["http://a".into(), "http://b".into()],
+            max_failures: 1,
+            ..Proxy::default()
+        };
+        let state = ProxyState::new(proxy, &ProxyClient::default());
+        state.record(0, false);
+        state.record(1, false);
+        assert!(state.health.iter().all(UpstreamHealth::is_ejected));
+        // Still picks an upstream instead of failing outright
+        assert_eq!(state.pick_target(), 0);
+        assert_eq!(state.pick_target(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retries_flaky_upstream() {
+        use tower::ServiceExt;
+
+        let request = |method: &str| {
+            Request::builder()
+                .method(method)
+                .uri("/api/flaky")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let app = |url: String, proxy: Proxy| {
+            let proxy = Proxy { url, ..proxy };
+            let (route_path, handler) =
+                make_proxy_route("/api/", proxy, &crate::config::Config::default());
+            axum::Router::new().route(&route_path, handler)
+        };
+        let retrying = Proxy {
+            retries: 2,
+            retry_backoff: Duration::from_millis(10),
+            ..Proxy::default()
+        };
+
+        // Two failures are absorbed by two retries
+        let (url, calls) = spawn_flaky_upstream(2).await;
+        let res = app(url, retrying.clone()).oneshot(request("GET")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Retries exhausted: the last upstream answer is relayed
+        let (url, calls) = spawn_flaky_upstream(5).await;
+        let res = app(url, retrying.clone()).oneshot(request("GET")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // No retries by default
+        let (url, calls) = spawn_flaky_upstream(1).await;
+        let res = app(url, Proxy::default()).oneshot(request("GET")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Non-idempotent methods only with retry_non_idempotent
+        let (url, calls) = spawn_flaky_upstream(1).await;
+        let res = app(url, retrying.clone()).oneshot(request("POST")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let (url, calls) = spawn_flaky_upstream(1).await;
+        let proxy = Proxy {
+            retry_non_idempotent: true,
+            ..retrying
+        };
+        let res = app(url, proxy).oneshot(request("POST")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_timeouts() {
+        use tower::ServiceExt;
+
+        let slow = axum::Router::new().route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                "late"
+            }),
+        );
+        let slow = spawn_upstream(slow).await;
+        let (stalled, _listener, _backlog) = crate::state::tests::stalled_listener().await;
+        let call = |url: String| async move {
+            let proxy = Proxy {
+                url,
+                timeout: Duration::from_millis(300),
+                connect_timeout: Some(Duration::from_millis(100)),
+                ..Proxy::default()
+            };
+            let (route_path, handler) =
+                make_proxy_route("/api/", proxy, &crate::config::Config::default());
+            let app = axum::Router::new().route(&route_path, handler);
+            let started = std::time::Instant::now();
+            let req = Request::builder().uri("/api/slow").body(Body::empty()).unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            (res.status(), started.elapsed())
+        };
+
+        // Connected, but no response within `timeout`
+        let (status, elapsed) = call(slow).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(elapsed >= Duration::from_millis(300));
+
+        // Never connected: `connect_timeout` fails first, with 502
+        let (status, elapsed) = call(format!("http://{stalled}")).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(elapsed < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_upstream_error_status() {
+        let timeout = UpstreamError::Timeout(Duration::from_secs(5));
+        assert_eq!(timeout.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(timeout.to_string(), "no response within 5s");
+        assert_eq!(UpstreamError::InvalidUri.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_error_statuses() {
+        use tower::ServiceExt;
+
+        // Reserve a port, then close it so connections are refused
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let call = |url: &str| {
+            let proxy = Proxy {
+                url: url.to_string(),
+                ..Proxy::default()
+            };
+            let (route_path, handler) =
+                make_proxy_route("/api/", proxy, &crate::config::Config::default());
+            let app = axum::Router::new().route(&route_path, handler);
+            let req = Request::builder().uri("/api/x").body(Body::empty()).unwrap();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+        assert_eq!(call(&unreachable).await, StatusCode::BAD_GATEWAY);
+        assert_eq!(call("http://bad host").await, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(call("").await, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retries_connection_errors() {
+        use tower::ServiceExt;
+
+        // Reserve a port, then close it so connections are refused
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let proxy = Proxy {
+            url: format!("http://{addr}"),
+            retries: 2,
+            retry_backoff: Duration::from_millis(50),
+            ..Proxy::default()
+        };
+        let (route_path, handler) =
+            make_proxy_route("/api/", proxy, &crate::config::Config::default());
+        let app = axum::Router::new().route(&route_path, handler);
+        let started = std::time::Instant::now();
+        let req = Request::builder().uri("/api/x").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_is_websocket_upgrade() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONNECTION, HeaderValue::from_static("keep-alive, Upgrade"));
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        assert!(is_websocket_upgrade(&headers));
+        headers.insert(UPGRADE, HeaderValue::from_static("h2c"));
+        assert!(!is_websocket_upgrade(&headers));
+        headers.remove(CONNECTION);
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        assert!(!is_websocket_upgrade(&headers));
+    }
+
+    /// Read an HTTP/1 message head (up to the blank line) from a stream
+    async fn read_head(stream: &mut tokio::net::TcpStream) -> String {
+        use tokio::io::AsyncReadExt;
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_websocket_frames_round_trip() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Upstream: accept the handshake, then echo every byte back
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let head = read_head(&mut stream).await.to_ascii_lowercase();
+            assert!(head.starts_with("get /ws/chat http/1.1"));
+            assert!(head.contains("upgrade: websocket"));
+            stream
+                .write_all(
+                    b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                      Connection: Upgrade\r\nSec-WebSocket-Accept: test\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let (mut reader, mut writer) = stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let (route_path, handler) = make_proxy_route(
+            "/api/",
+            Proxy {
+                url: format!("http://{upstream_addr}"),
+                ..Proxy::default()
+            },
+            &crate::config::Config::default(),
+        );
+        let url = spawn_upstream(axum::Router::new().route(&route_path, handler)).await;
+
+        let mut client = tokio::net::TcpStream::connect(url.trim_start_matches("http://"))
+            .await
+            .unwrap();
+        client
+            .write_all(
+                b"GET /api/ws/chat HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+                  Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let head = read_head(&mut client).await;
+        assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+        assert!(head.to_ascii_lowercase().contains("sec-websocket-accept: test"));
+
+        // Masked text frame "hello", echoed back unchanged through the tunnel
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        for _ in 0..2 {
+            client.write_all(&frame).await.unwrap();
+            let mut echoed = [0u8; 11];
+            client.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(echoed, frame);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_with_tcp_keepalive() {
+        use tower::ServiceExt;
+
+        let proxy: Proxy = serde_yaml::from_str("url: http://unused\ntcp_keepalive: 30s").unwrap();
+        assert_eq!(proxy.tcp_keepalive, Some(Duration::from_secs(30)));
+
+        let upstream = axum::Router::new().route("/ping", axum::routing::get(|| async { "pong" }));
+        let url = spawn_upstream(upstream).await;
+        let (route_path, handler) = make_proxy_route(
+            "/api/",
+            Proxy {
+                url,
+                ..proxy
+            },
+            &crate::config::Config::default(),
+        );
+        let app = axum::Router::new().route(&route_path, handler);
+        let req = Request::builder().uri("/api/ping").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_proxied_sse_streams_past_timeout() {
+        use http_body_util::BodyExt;
+        use tower::ServiceExt;
+
+        // Emits an event every 150ms, longer in total than the proxy timeout
+        let upstream = axum::Router::new().route(
+            "/events",
+            axum::routing::get(|| async {
+                let events = futures_util::stream::unfold(0, |n| async move {
+                    if n == 3 {
+                        return None;
+                    }
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                    let frame = Bytes::from(format!("data: {n}\n\n"));
+                    Some((Ok::<_, std::io::Error>(frame), n + 1))
+                });
+                Response::builder()
+                    .header("content-type", "text/event-stream")
+                    .body(Body::from_stream(events))
+                    .unwrap()
+            }),
+        );
+        let url = spawn_upstream(upstream).await;
+
+        let mut cfg = crate::config::Config::default();
+        cfg.compression.enable = true;
+        let (route_path, handler) = make_proxy_route(
+            "/api/",
+            Proxy {
+                url,
+                timeout: Duration::from_millis(300),
+                ..Proxy::default()
+            },
+            &crate::config::Config::default(),
+        );
+        let app = axum::Router::new()
+            .route(&route_path, handler)
+            .layer(crate::router::build_compression(&cfg).unwrap());
+
+        let req = Request::builder()
+            .uri("/api/events")
+            .header("accept-encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let started = std::time::Instant::now();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key("content-encoding"));
+
+        // Events arrive one by one rather than buffered until the end
+        let mut body = res.into_body();
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(&first[..], b"data: 0\n\n");
+        assert!(started.elapsed() < Duration::from_millis(400));
+
+        let rest = body.collect().await.unwrap().to_bytes();
+        assert_eq!(&rest[..], b"data: 1\n\ndata: 2\n\n");
+        assert!(started.elapsed() > Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_proxied_response_respects_disabled_encoding() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new().route(
+            "/page",
+            axum::routing::get(|| async { axum::response::Html("<p>hello</p>".repeat(200)) }),
+        );
+        let url = spawn_upstream(upstream).await;
+
+        let mut cfg = crate::config::Config::default();
+        cfg.compression.enable = true;
+        cfg.compression.br = false;
+        let (route_path, handler) = make_proxy_route(
+            "/api/",
+            Proxy {
+                url,
+                ..Proxy::default()
+            },
+            &crate::config::Config::default(),
+        );
+        let app = axum::Router::new()
+            .route(&route_path, handler)
+            .layer(crate::router::build_compression(&cfg).unwrap());
+
+        let req = Request::builder()
+            .uri("/api/page")
+            .header("accept-encoding", "br")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(!res.headers().contains_key("content-encoding"));
+    }
+
+    #[tokio::test]
+    async fn test_error_override_replaces_upstream_body() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new()
+            .route(
+                "/missing",
+                axum::routing::get(|| async { (StatusCode::NOT_FOUND, "upstream 404") }),
+            )
+            .route(
+                "/down",
+                axum::routing::get(|| async { (StatusCode::SERVICE_UNAVAILABLE, "upstream 503") }),
+            )
+            .route(
+                "/teapot",
+                axum::routing::get(|| async { (StatusCode::IM_A_TEAPOT, "upstream 418") }),
+            );
+        let url = spawn_upstream(upstream).await;
+
+        let dir = std::env::temp_dir()
+            .join(format!("statiker-err-override-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("404.html"), "local 404").unwrap();
+        std::fs::write(dir.join("503.html"), "local 503").unwrap();
+
+        let mut error_overrides = HashMap::new();
+        error_overrides.insert(
+            404,
+            crate::config::ErrorOverride {
+                file: dir.join("404.html"),
+                status: None,
+            },
+        );
+        error_overrides.insert(
+            503,
+            crate::config::ErrorOverride {
+                file: dir.join("503.html"),
+                status: Some(502),
+            },
+        );
+        let (route_path, handler) = make_proxy_route(
+            "/api/",
+            Proxy {
+                url,
+                error_overrides,
+                ..Proxy::default()
+            },
+            &crate::config::Config::default(),
+        );
+        let app = axum::Router::new().route(&route_path, handler);
+
+        let call = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8_lossy(&body).to_string())
+            }
+        };
+
+        assert_eq!(call("/api/missing").await, (StatusCode::NOT_FOUND, "local 404".into()));
+        assert_eq!(call("/api/down").await, (StatusCode::BAD_GATEWAY, "local 503".into()));
+        assert_eq!(call("/api/teapot").await, (StatusCode::IM_A_TEAPOT, "upstream 418".into()));
+    }
+
+    #[test]
+    fn test_force_secure_cookies() {
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, HeaderValue::from_static("a=1; Path=/"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("b=2; HttpOnly; secure"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("secure=3"));
+        force_secure_cookies(&mut headers);
+        let cookies: Vec<_> = headers.get_all(SET_COOKIE).iter().collect();
+        assert_eq!(
+            cookies,
+            vec!["a=1; Path=/; Secure", "b=2; HttpOnly; secure", "secure=3; Secure"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_secure_cookies_only_over_tls() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new().route(
+            "/login",
+            axum::routing::get(|| async { ([("set-cookie", "session=abc; HttpOnly")], "ok") }),
+        );
+        let url = spawn_upstream(upstream).await;
+
+        let cookie_for = |tls: bool| {
+            let url = url.clone();
+            async move {
+                let mut cfg = crate::config::Config::default();
+                cfg.tls.enabled = tls;
+                let proxy = Proxy {
+                    url,
+                    force_secure_cookies: true,
+                    ..Proxy::default()
+                };
+                let (route_path, handler) = make_proxy_route("/api/", proxy, &cfg);
+                let app = axum::Router::new().route(&route_path, handler);
+                let req = Request::builder().uri("/api/login").body(Body::empty()).unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                res.headers().get(SET_COOKIE).unwrap().clone()
+            }
+        };
+
+        assert_eq!(cookie_for(true).await, "session=abc; HttpOnly; Secure");
+        assert_eq!(cookie_for(false).await, "session=abc; HttpOnly");
+    }
+
+    #[test]
+    fn test_public_location() {
+        let proxy = Proxy {
+            url: "http://backend:3000/app/".to_string(),
+            urls: vec!["https://other.internal".to_string()],
+            ..Proxy::default()
+        };
+        let mut state = ProxyState::new(proxy, &ProxyClient::default());
+        state.prefix = "/api/".to_string();
+
+        let public = |loc: &str| state.public_location(loc);
+        assert_eq!(
+            public("http://backend:3000/app/login?next=%2F").as_deref(),
+            Some("/api/login?next=%2F")
+        );
+        assert_eq!(public("http://BACKEND:3000/app").as_deref(), Some("/api/"));
+        assert_eq!(public("https://other.internal:443/x#top").as_deref(), Some("/api/x#top"));
+        // Other origins, paths outside the base path and relative locations stay
+        assert_eq!(public("http://backend:3001/app/login"), None);
+        assert_eq!(public("https://backend:3000/app/login"), None);
+        assert_eq!(public("http://backend:3000/application"), None);
+        assert_eq!(public("https://example.com/app/login"), None);
+        assert_eq!(public("/app/login"), None);
+
+        // Without strip_prefix the upstream path already carries the mount path
+        state.strip_prefix = false;
+        let location = state.public_location("http://backend:3000/app/api/login");
+        assert_eq!(location.as_deref(), Some("/api/login"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rewrites_self_redirects() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new()
+            .route(
+                "/old",
+                axum::routing::get(|headers: HeaderMap| async move {
+                    let host = headers[HOST].to_str().unwrap().to_string();
+                    (StatusCode::FOUND, [(LOCATION, format!("http://{host}/new?x=1"))])
+                }),
+            )
+            .route(
+                "/away",
+                axum::routing::get(|| async {
+                    (StatusCode::FOUND, [(LOCATION, "https://example.com/new")])
+                }),
+            );
+        let url = spawn_upstream(upstream).await;
+
+        let location = |path: &'static str, rewrite_redirects: bool| {
+            let url = url.clone();
+            async move {
+                let proxy = Proxy {
+                    url,
+                    rewrite_redirects,
+                    ..Proxy::default()
+                };
+                let (route_path, handler) =
+                    make_proxy_route("/api/", proxy, &crate::config::Config::default());
+                let app = axum::Router::new().route(&route_path, handler);
+                let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                assert_eq!(res.status(), StatusCode::FOUND);
+                res.headers()[LOCATION].to_str().unwrap().to_string()
+            }
+        };
+
+        assert_eq!(location("/api/old", true).await, "/api/new?x=1");
+        assert_eq!(location("/api/away", true).await, "https://example.com/new");
+        assert!(location("/api/old", false).await.starts_with("http://127.0.0.1:"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_max_body_size() {
+        use tower::ServiceExt;
+
+        let upstream = axum::Router::new().route(
+            "/upload",
+            axum::routing::post(|body: Bytes| async move { body.len().to_string() }),
+        );
+        let url = spawn_upstream(upstream).await;
+        let proxy = Proxy {
+            url,
+            max_body_size: Some(crate::config::ByteSize(1024)),
+            ..Proxy::default()
+        };
+        let (route_path, handler) =
+            make_proxy_route("/api/", proxy, &crate::config::Config::default());
+        let app = axum::Router::new().route(&route_path, handler);
+        let post = |body: Body, length: Option<usize>| {
+            let mut req = Request::post("/api/upload");
+            if let Some(length) = length {
+                req = req.header(CONTENT_LENGTH, length);
+            }
+            app.clone().oneshot(req.body(body).unwrap())
+        };
+        // Body without a declared length, streamed in chunks
+        let chunked = |chunks: usize| {
+            let chunks = (0..chunks).map(|_| Ok::<_, std::io::Error>(Bytes::from(vec![b'x'; 256])));
+            Body::from_stream(futures_util::stream::iter(chunks))
+        };
+
+        let res = post(Body::from(vec![b'x'; 1000]), Some(1000)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"1000");
+        let res = post(chunked(4), None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"1024");
+
+        let res = post(Body::from(vec![b'x'; 2048]), Some(2048)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let res = post(chunked(8), None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::config::Config;
+use crate::logging::{self, FilterHandle};
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Re-read the config file and swap in its reloadable sections
+///
+/// Errors (unreadable file, invalid YAML) leave the current config untouched.
+pub async fn reload(
+    path: &str,
+    live: &ArcSwap<Config>,
+    log_handle: &FilterHandle,
+) -> anyhow::Result<()> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| anyhow::anyhow!("reading {path}: {e}"))?;
+    let new = Config::from_yaml(&text).map_err(|e| anyhow::anyhow!("parsing YAML {path}: {e}"))?;
+    let (merged, ignored) = live.load().reload_from(&new);
+    if ignored {
+        warn!(
+            "config reload: only security.headers, assets.cache and obs levels are applied; \
+             restart to pick up the other changes"
+        );
+    }
+    logging::apply_config(log_handle, &merged.obs);
+    live.store(Arc::new(merged));
+    Ok(())
+}
+
+/// Reload the config from `path` whenever the process receives SIGHUP
+#[cfg(unix)]
+pub fn spawn_sighup_reload(
+    path: String,
+    live: Arc<ArcSwap<Config>>,
+    log_handle: FilterHandle,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match reload(&path, &live, &log_handle).await {
+                Ok(()) => info!("Reloaded configuration from {path}"),
+                Err(e) => warn!("config reload failed, keeping current config: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_from_applies_reloadable_sections() {
+        let current = Config::default();
+        let mut new = Config::default();
+        new.security
+            .headers
+            .insert("X-Frame-Options".into(), "DENY".into());
+        new.assets.cache.max_age = std::time::Duration::from_secs(60);
+        new.obs.level = "debug".into();
+
+        let (merged, ignored) = current.reload_from(&new);
+        assert!(!ignored);
+        assert_eq!(merged.security.headers["X-Frame-Options"], "DENY");
+        assert_eq!(merged.assets.cache.max_age.as_secs(), 60);
+        assert_eq!(merged.obs.level, "debug");
+    }
+
+    #[test]
+    fn test_reload_from_keeps_other_sections() {
+        let current = Config::default();
+        let mut new = Config::default();
+        new.server.port = current.server.port + 1;
+        new.spa.enabled = !current.spa.enabled;
+
+        let (merged, ignored) = current.reload_from(&new);
+        assert!(ignored);
+        assert_eq!(merged.server.port, current.server.port);
+        assert_eq!(merged.spa.enabled, current.spa.enabled);
+    }
+
+    #[tokio::test]
+    async fn test_reload_from_file() {
+        let path = std::env::temp_dir().join("statiker_test_reload.yaml");
+        let path_str = path.to_str().unwrap();
+        let live = ArcSwap::from_pointee(Config::default());
+        let (_layer, handle) =
+            tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new("info"));
+
+        std::fs::write(&path, "security:\n  headers:\n    X-Test: reloaded\n").unwrap();
+        reload(path_str, &live, &handle).await.unwrap();
+        assert_eq!(live.load().security.headers["X-Test"], "reloaded");
+
+        // Invalid YAML keeps the current config
+        std::fs::write(&path, "security: [").unwrap();
+        assert!(reload(path_str, &live, &handle).await.is_err());
+        assert_eq!(live.load().security.headers["X-Test"], "reloaded");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,36 +1,41 @@
-use crate::config::{Config, Route};
-use crate::handlers::serve_static;
+use crate::config::{CompressionLevel, CompressionPreset, Config, Route};
+use crate::handlers::{serve_static, spa_fallback, Download};
 use crate::proxy::make_proxy_route;
 use crate::state::AppState;
+use crate::utils::cors_request_method;
 use anyhow::Result;
 use axum::{
     body::Body,
     extract::Path,
-    http::{HeaderValue, Method},
-    routing::any,
-    Router,
+    http::{HeaderValue, Method, StatusCode},
+    middleware::{from_fn, Next},
+    response::IntoResponse,
+    routing::{any, get, MethodRouter},
+    Extension, Router,
 };
 use http::Request;
+use std::collections::HashMap;
 use std::path::{Path as StdPath, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 use tower_http::{
     cors::{Any, CorsLayer},
-    services::ServeFile,
+    limit::RequestBodyLimitLayer,
 };
-use tracing::{info, warn};
+use tracing::{info, warn, Span};
 
 /// Safely resolve a path within the root directory, preventing path traversal
 /// 
 /// Returns an error if the path contains `..` components or would escape the root.
 /// This function validates path components without requiring the file to exist.
-fn resolve_path_within_root(root: &PathBuf, rel_path: &str) -> Result<PathBuf> {
+pub fn resolve_path_within_root(root: &StdPath, rel_path: &str) -> Result<PathBuf> {
     // Security: disallow path traversal attempts like ".."
     if rel_path.split('/').any(|p| p == "..") {
         return Err(anyhow::anyhow!("Path traversal detected in fallback path"));
     }
 
     // Build path safely, only allowing normal components
-    let mut resolved = root.clone();
+    let mut resolved = root.to_path_buf();
     if !rel_path.is_empty() {
         for component in StdPath::new(rel_path).components() {
             match component {
@@ -70,9 +75,54 @@ fn resolve_path_within_root(root: &PathBuf, rel_path: &str) -> Result<PathBuf> {
     Ok(resolved)
 }
 
+/// Route that handled a request, attached when `obs.log_route` is enabled
+///
+/// Stored in both the request and response extensions and recorded as the
+/// `route` field of the request's tracing span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedRoute {
+    /// "static" or "proxy"
+    pub kind: &'static str,
+    /// Route path as configured
+    pub path: String,
+}
+
+impl std::fmt::Display for MatchedRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind, self.path)
+    }
+}
+
+/// Tag requests handled by `handler` with their route, if `obs.log_route` is set
+pub fn with_route_tag(
+    handler: MethodRouter,
+    cfg: &Config,
+    kind: &'static str,
+    path: &str,
+) -> MethodRouter {
+    if !cfg.obs.log_route {
+        return handler;
+    }
+    let route = MatchedRoute {
+        kind,
+        path: path.to_string(),
+    };
+    handler.layer(from_fn(move |mut req: Request<Body>, next: Next| {
+        let route = route.clone();
+        async move {
+            Span::current().record("route", tracing::field::display(&route));
+            req.extensions_mut().insert(route.clone());
+            let mut res = next.run(req).await;
+            res.extensions_mut().insert(route);
+            res
+        }
+    }))
+}
+
 /// Mount a static file route
 pub fn mount_static_route(router: Router, state: &AppState, path: &str) -> Router {
     let st = state.clone();
+    let tag = |handler| with_route_tag(handler, &state.cfg, "static", path);
 
     if path == "/" {
         // For root path, register both "/" and "/*tail"
@@ -83,7 +133,7 @@ pub fn mount_static_route(router: Router, state: &AppState, path: &str) -> Route
                 async move { serve_static(st, String::new(), req).await }
             })
         };
-        let mut router = router.route("/", handler_root);
+        let mut router = router.route("/", tag(handler_root));
 
         let handler_tail = {
             let st = st.clone();
@@ -92,7 +142,7 @@ pub fn mount_static_route(router: Router, state: &AppState, path: &str) -> Route
                 async move { serve_static(st, tail, req).await }
             })
         };
-        router = router.route("/*tail", handler_tail);
+        router = router.route("/*tail", tag(handler_tail));
         router
     } else {
         // For non-root paths, use nested routing
@@ -104,7 +154,7 @@ pub fn mount_static_route(router: Router, state: &AppState, path: &str) -> Route
                 async move { serve_static(st, tail, req).await }
             })
         };
-        router.route(&format!("{}/*tail", base), handler)
+        router.route(&format!("{}/*tail", base), tag(handler))
     }
 }
 
@@ -113,18 +163,68 @@ pub fn build_router(state: &AppState) -> Result<Router> {
     let mut router = Router::new();
     let mut has_routes = false;
 
-    for Route { path, serve, proxy } in &state.cfg.routing {
+    // Registered before any static catch-all so it can't be shadowed
+    let health = &state.cfg.obs.health;
+    if health.enabled {
+        if !health.path.starts_with('/') {
+            return Err(anyhow::anyhow!(
+                "obs.health.path must start with '/': {}",
+                health.path
+            ));
+        }
+        info!("Health check endpoint: {}", health.path);
+        router = router.route(&health.path, get(|| async { "ok" }));
+    }
+
+    for Route {
+        path,
+        serve,
+        root,
+        auto_index,
+        download,
+        proxy,
+    } in &state.cfg.routing
+    {
         // Routes should be mutually exclusive: either serve static files OR proxy, not both
         if serve.as_deref() == Some("static") {
             if proxy.is_some() {
                 warn!("Route '{}' has both 'serve: static' and 'proxy' configured. 'proxy' will be ignored. Routes should be mutually exclusive.", path);
             }
             // Create handlers for static files and directories
-            info!("Mounting static route: {}", path);
-            router = mount_static_route(router, state, path);
+            let mut route_state = state.clone();
+            match root {
+                // Everything under the route resolves against its own root
+                Some(root) => {
+                    info!("Mounting static route: {} from {}", path, root.display());
+                    route_state.root = root.clone();
+                }
+                None => info!("Mounting static route: {}", path),
+            }
+            // The route's `auto_index` takes precedence over `server.auto_index`
+            if let Some(auto_index) = *auto_index {
+                let mut cfg = (*state.cfg).clone();
+                cfg.server.auto_index = auto_index;
+                route_state.cfg = Arc::new(cfg);
+            }
+            router = if *download {
+                // Marks the route's requests for `Content-Disposition: attachment`
+                let route = mount_static_route(Router::new(), &route_state, path);
+                router.merge(route.layer(Extension(Download)))
+            } else {
+                mount_static_route(router, &route_state, path)
+            };
             has_routes = true;
         } else if let Some(p) = proxy.clone() {
-            let (route_path, handler) = make_proxy_route(path, p);
+            // Would otherwise be mounted and fail every request
+            if p.url.is_empty() && p.urls.is_empty() {
+                return Err(anyhow::anyhow!("proxy route '{path}' has no upstream url"));
+            }
+            if let Some(rewrite) = &p.rewrite {
+                regex::Regex::new(&rewrite.from).map_err(|e| {
+                    anyhow::anyhow!("invalid proxy rewrite pattern '{}': {e}", rewrite.from)
+                })?;
+            }
+            let (route_path, handler) = make_proxy_route(path, p, &state.cfg);
             router = router.route(&route_path, handler);
             has_routes = true;
         }
@@ -136,18 +236,35 @@ pub fn build_router(state: &AppState) -> Result<Router> {
         router = mount_static_route(router, state, "/");
     }
 
+    // Precedence is fixed: health and proxy routes, then files under static
+    // routes, then the SPA fallback. Static routes hand navigational requests
+    // for missing files to `spa_fallback` themselves; the router fallback
+    // covers paths outside every route.
     if state.cfg.spa.enabled {
         // Security: Safely resolve SPA fallback path within root directory
         let fallback = state.cfg.spa.fallback.trim_start_matches('/');
-        let index_file = match resolve_path_within_root(&state.root, fallback) {
-            Ok(path) => path,
-            Err(_) => {
-                // If path traversal detected, use default index.html
-                warn!("SPA fallback path '{}' contains path traversal, using default index.html", fallback);
-                state.root.join("index.html")
+        if resolve_path_within_root(&state.root, fallback).is_err() {
+            // If path traversal detected, use default index.html
+            warn!("SPA fallback path '{}' contains path traversal, using default index.html", fallback);
+        }
+        let st = state.clone();
+        router = router.fallback(move |req: Request<Body>| {
+            let st = st.clone();
+            async move {
+                match spa_fallback(&st, req.uri().path(), req.method(), req.headers()).await {
+                    Some(res) => res,
+                    None => StatusCode::NOT_FOUND.into_response(),
+                }
             }
-        };
-        router = router.fallback_service(ServeFile::new(index_file));
+        });
+    }
+
+    // Checks `Content-Length` upfront and cuts off longer streamed bodies, so
+    // oversized requests get 413 before or while a handler reads them
+    let max_body_size = state.cfg.server.max_body_size.0;
+    if max_body_size > 0 {
+        let limit = usize::try_from(max_body_size).unwrap_or(usize::MAX);
+        router = router.layer(RequestBodyLimitLayer::new(limit));
     }
 
     Ok(router)
@@ -156,12 +273,28 @@ pub fn build_router(state: &AppState) -> Result<Router> {
 /// Build compression layer
 pub fn build_compression(cfg: &Config) -> Option<tower_http::compression::CompressionLayer> {
     if cfg.compression.enable && (cfg.compression.gzip || cfg.compression.br) {
-        Some(tower_http::compression::CompressionLayer::new())
+        Some(
+            tower_http::compression::CompressionLayer::new()
+                .gzip(cfg.compression.gzip)
+                .br(cfg.compression.br)
+                .quality(compression_level(cfg.compression.level)),
+        )
     } else {
         None
     }
 }
 
+/// Map `compression.level` to tower-http's compression level
+pub fn compression_level(level: CompressionLevel) -> tower_http::CompressionLevel {
+    use tower_http::CompressionLevel as Level;
+    match level {
+        CompressionLevel::Preset(CompressionPreset::Fastest) => Level::Fastest,
+        CompressionLevel::Preset(CompressionPreset::Default) => Level::Default,
+        CompressionLevel::Preset(CompressionPreset::Best) => Level::Best,
+        CompressionLevel::Quality(q) => Level::Precise(q.into()),
+    }
+}
+
 /// Build CORS layer
 pub fn build_cors(cfg: &Config) -> Option<CorsLayer> {
     if !cfg.security.cors.enabled {
@@ -169,17 +302,36 @@ pub fn build_cors(cfg: &Config) -> Option<CorsLayer> {
     }
 
     // Origins
-    let origins = if cfg.security.cors.allowed_origins.is_empty() {
+    let parse_origins = |list: &[String]| {
+        list.iter()
+            .filter_map(|s| HeaderValue::from_str(s).ok())
+            .collect::<Vec<_>>()
+    };
+    let method_origins: HashMap<Method, Vec<HeaderValue>> = cfg
+        .security
+        .cors
+        .methods
+        .iter()
+        .filter(|(_, m)| !m.allowed_origins.is_empty())
+        .filter_map(|(k, m)| {
+            Method::from_str(&k.to_ascii_uppercase())
+                .ok()
+                .map(|method| (method, parse_origins(&m.allowed_origins)))
+        })
+        .collect();
+    let origins = if !method_origins.is_empty() {
+        // Vary the allowed origins by (requested) method, falling back to the global list
+        let global = parse_origins(&cfg.security.cors.allowed_origins);
+        tower_http::cors::AllowOrigin::predicate(move |origin, parts| {
+            let list = cors_request_method(&parts.method, &parts.headers)
+                .and_then(|m| method_origins.get(&m))
+                .unwrap_or(&global);
+            list.is_empty() || list.contains(origin)
+        })
+    } else if cfg.security.cors.allowed_origins.is_empty() {
         tower_http::cors::AllowOrigin::any()
     } else {
-        let list = cfg
-            .security
-            .cors
-            .allowed_origins
-            .iter()
-            .filter_map(|s| HeaderValue::from_str(s).ok())
-            .collect::<Vec<_>>();
-        tower_http::cors::AllowOrigin::list(list)
+        tower_http::cors::AllowOrigin::list(parse_origins(&cfg.security.cors.allowed_origins))
     };
 
     // Methods
@@ -200,20 +352,117 @@ pub fn build_cors(cfg: &Config) -> Option<CorsLayer> {
             .collect()
     };
 
-    Some(
-        CorsLayer::new()
-            .allow_origin(origins)
-            .allow_methods(methods)
-            .allow_headers(Any)
-            .expose_headers(Any),
-    )
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(Any)
+        .expose_headers(Any);
+    // Lets browsers skip repeated preflights for the same request shape
+    if !cfg.security.cors.max_age.is_zero() {
+        layer = layer.max_age(cfg.security.cors.max_age);
+    }
+    Some(layer)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::Config;
+    use crate::state::test_state;
     use std::path::PathBuf;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_log_route_tags_responses() {
+        let mut cfg = Config::default();
+        cfg.obs.log_route = true;
+        cfg.routing = vec![Route {
+            path: "/files".into(),
+            serve: Some("static".into()),
+            root: None,
+            auto_index: None,
+            download: false,
+            proxy: None,
+        }];
+        let state = test_state(cfg);
+        let app = build_router(&state).unwrap();
+        let req = Request::get("/files/Cargo.toml").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            res.extensions().get::<MatchedRoute>(),
+            Some(&MatchedRoute {
+                kind: "static",
+                path: "/files".into()
+            })
+        );
+
+        // Untagged when disabled
+        let state = AppState {
+            cfg: Arc::new(Config::default()),
+            ..state
+        };
+        let app = build_router(&state).unwrap();
+        let req = Request::get("/Cargo.toml").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(res.extensions().get::<MatchedRoute>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint() {
+        let mut cfg = Config::default();
+        cfg.obs.health.enabled = true;
+        let state = test_state(cfg);
+        let app = build_router(&state).unwrap();
+        let req = Request::get("/healthz").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"ok");
+    }
+
+    #[test]
+    fn test_invalid_proxy_rewrite_is_rejected() {
+        let cfg = Config {
+            routing: vec![Route {
+                path: "/api/*".into(),
+                serve: None,
+                root: None,
+                auto_index: None,
+                download: false,
+                proxy: Some(crate::config::Proxy {
+                    url: "http://localhost:3000".into(),
+                    rewrite: Some(crate::config::Rewrite {
+                        from: "([".into(),
+                        to: "/".into(),
+                    }),
+                    ..Default::default()
+                }),
+            }],
+            ..Config::default()
+        };
+        let state = test_state(cfg);
+        let err = build_router(&state).unwrap_err();
+        assert!(err.to_string().contains("invalid proxy rewrite pattern"));
+    }
+
+    #[test]
+    fn test_proxy_without_upstream_is_rejected() {
+        let cfg = Config {
+            routing: vec![Route {
+                path: "/api/*".into(),
+                serve: None,
+                root: None,
+                auto_index: None,
+                download: false,
+                proxy: Some(crate::config::Proxy::default()),
+            }],
+            ..Config::default()
+        };
+        let state = test_state(cfg);
+        let err = build_router(&state).unwrap_err();
+        assert_eq!(err.to_string(), "proxy route '/api/*' has no upstream url");
+    }
 
     #[test]
     fn test_resolve_path_within_root_valid() {
@@ -294,6 +543,67 @@ mod tests {
         assert!(build_compression(&cfg).is_none());
     }
 
+    #[test]
+    fn test_compression_level() {
+        let parse = |yaml: &str| serde_yaml::from_str::<CompressionLevel>(yaml).unwrap();
+        assert_eq!(
+            compression_level(parse("fastest")),
+            tower_http::CompressionLevel::Fastest
+        );
+        assert_eq!(compression_level(parse("best")), tower_http::CompressionLevel::Best);
+        assert_eq!(
+            compression_level(CompressionLevel::default()),
+            tower_http::CompressionLevel::Default
+        );
+        assert_eq!(compression_level(parse("5")), tower_http::CompressionLevel::Precise(5));
+        assert!(serde_yaml::from_str::<CompressionLevel>("maximum").is_err());
+        assert!(serde_yaml::from_str::<CompressionLevel>("-1").is_err());
+    }
+
+    /// Compressed response for `accept_encoding` through `build_compression(cfg)`
+    async fn compress(cfg: &Config, accept_encoding: &str) -> (Option<String>, usize) {
+        use tower::ServiceExt;
+
+        let text: String = (0..5000).map(|i| format!("line {i}: {}\n", i * i % 977)).collect();
+        let app = Router::new()
+            .route("/", get(move || async move { text }))
+            .layer(build_compression(cfg).unwrap());
+        let req = Request::builder()
+            .uri("/")
+            .header("accept-encoding", accept_encoding)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let encoding = res
+            .headers()
+            .get("content-encoding")
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (encoding, body.len())
+    }
+
+    #[tokio::test]
+    async fn test_build_compression_quality_and_encodings() {
+        let mut cfg = Config::default();
+        cfg.compression.enable = true;
+
+        cfg.compression.level = CompressionLevel::Preset(CompressionPreset::Fastest);
+        let (encoding, fastest) = compress(&cfg, "gzip").await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        cfg.compression.level = CompressionLevel::Preset(CompressionPreset::Best);
+        let (_, best) = compress(&cfg, "gzip").await;
+        assert!(best < fastest, "best {best} >= fastest {fastest}");
+
+        // Disabled algorithms are never negotiated
+        assert_eq!(compress(&cfg, "br, gzip").await.0.as_deref(), Some("br"));
+        cfg.compression.br = false;
+        assert_eq!(compress(&cfg, "br, gzip").await.0.as_deref(), Some("gzip"));
+        assert_eq!(compress(&cfg, "br").await.0, None);
+        cfg.compression.br = true;
+        cfg.compression.gzip = false;
+        assert_eq!(compress(&cfg, "gzip").await.0, None);
+    }
+
     #[test]
     fn test_build_cors_disabled() {
         let cfg = Config::default();
@@ -323,5 +633,333 @@ mod tests {
         cfg.security.cors.allowed_methods.push("POST".to_string());
         assert!(build_cors(&cfg).is_some());
     }
-}
 
+    #[test]
+    fn test_build_cors_enabled_with_method_overrides() {
+        let mut cfg = Config::default();
+        cfg.security.cors.enabled = true;
+        cfg.security.cors.methods.insert(
+            "post".to_string(),
+            crate::config::CorsMethod {
+                allowed_origins: vec!["https://app.example.com".to_string()],
+                allowed_headers: vec!["content-type".to_string()],
+            },
+        );
+        assert!(build_cors(&cfg).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_build_cors_max_age() {
+        use std::time::Duration;
+
+        let preflight = |max_age: Duration| {
+            let mut cfg = Config::default();
+            cfg.security.cors.enabled = true;
+            cfg.security.cors.max_age = max_age;
+            let app = Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(build_cors(&cfg).unwrap());
+            let req = Request::options("/")
+                .header("origin", "https://app.example.com")
+                .header("access-control-request-method", "POST")
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                res.headers().get("access-control-max-age").cloned()
+            }
+        };
+        let default = crate::config::Cors::default().max_age;
+        assert_eq!(preflight(default).await.unwrap(), "3600");
+        assert_eq!(preflight(Duration::from_secs(600)).await.unwrap(), "600");
+        assert!(preflight(Duration::ZERO).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        use crate::config::{ByteSize, Proxy};
+        use axum::http::StatusCode;
+
+        let upstream = Router::new().route(
+            "/echo",
+            axum::routing::post(|body: bytes::Bytes| async move { body.len().to_string() }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        let app = |limit: u64| {
+            let mut cfg = Config::default();
+            cfg.server.max_body_size = ByteSize(limit);
+            cfg.routing = vec![Route {
+                path: "/api/*".into(),
+                serve: None,
+                root: None,
+                auto_index: None,
+                download: false,
+                proxy: Some(Proxy {
+                    url: url.clone(),
+                    ..Proxy::default()
+                }),
+            }];
+            let state = test_state(cfg);
+            build_router(&state).unwrap()
+        };
+        let post = |limit: u64, body: Body, length: Option<usize>| {
+            let mut req = Request::post("/api/echo");
+            if let Some(length) = length {
+                req = req.header(axum::http::header::CONTENT_LENGTH, length);
+            }
+            app(limit).oneshot(req.body(body).unwrap())
+        };
+        let streamed = || {
+            let chunk = || Ok::<_, std::io::Error>(bytes::Bytes::from(vec![b'x'; 16]));
+            let chunks = (0..4).map(move |_| chunk());
+            Body::from_stream(futures_util::stream::iter(chunks))
+        };
+
+        let res = post(64, Body::from(vec![b'x'; 64]), Some(64)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = post(32, Body::from(vec![b'x'; 64]), Some(64)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let res = post(32, streamed(), None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // 0 disables the limit
+        let res = post(0, streamed(), None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_spa_fallback_precedence() {
+        use crate::config::Proxy;
+
+        let upstream = Router::new()
+            .route("/users", get(|| async { "[]" }))
+            .fallback(|| async { (StatusCode::NOT_FOUND, "no such endpoint") });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        let root = std::env::temp_dir().join(format!("statiker-spa-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("index.html"), "app").unwrap();
+        std::fs::write(root.join("style.css"), "body {}").unwrap();
+
+        let app = |static_path: &str| {
+            let mut cfg = Config::default();
+            cfg.spa.enabled = true;
+            cfg.routing = vec![
+                Route {
+                    path: "/api/*".into(),
+                    serve: None,
+                    root: None,
+                    auto_index: None,
+                    download: false,
+                    proxy: Some(Proxy {
+                        url: url.clone(),
+                        ..Proxy::default()
+                    }),
+                },
+                Route {
+                    path: static_path.into(),
+                    serve: Some("static".into()),
+                    root: None,
+                    auto_index: None,
+                    download: false,
+                    proxy: None,
+                },
+            ];
+            let state = AppState { root: root.clone(), ..test_state(cfg) };
+            build_router(&state).unwrap()
+        };
+        let fetch = |app: Router, uri: &str, accept: &str| {
+            let req = Request::get(uri).header("accept", accept).body(Body::empty()).unwrap();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let html = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+        for static_path in ["/", "/assets"] {
+            let prefix = static_path.trim_end_matches('/');
+            // API misses keep the upstream's 404, even for navigations
+            let res = fetch(app(static_path), "/api/missing", html).await;
+            assert_eq!(res, (StatusCode::NOT_FOUND, "no such endpoint".into()));
+            let res = fetch(app(static_path), "/api/users", "application/json").await;
+            assert_eq!(res, (StatusCode::OK, "[]".into()));
+            // Existing files win over the fallback
+            let res = fetch(app(static_path), &format!("{prefix}/style.css"), html).await;
+            assert_eq!(res, (StatusCode::OK, "body {}".into()));
+            // Deep links inside and outside the static route serve the app
+            for uri in [format!("{prefix}/dashboard/settings"), "/settings".into()] {
+                let res = fetch(app(static_path), &uri, html).await;
+                assert_eq!(res, (StatusCode::OK, "app".into()));
+                let res = fetch(app(static_path), &uri, "application/json").await;
+                assert_eq!(res.0, StatusCode::NOT_FOUND);
+            }
+            // Missing assets are genuine 404s
+            for uri in [format!("{prefix}/missing.js"), "/missing.js".into()] {
+                let res = fetch(app(static_path), &uri, "*/*").await;
+                assert_eq!(res.0, StatusCode::NOT_FOUND);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_route_roots() {
+        let temp = |name: &str, file: &str, content: &str| {
+            let dir = std::env::temp_dir()
+                .join(format!("statiker-mount-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(file), content).unwrap();
+            dir
+        };
+        let public = temp("public", "index.html", "home");
+        let docs = temp("docs", "guide.md", "# Guide");
+
+        let cfg = Config {
+            routing: vec![
+                Route {
+                    path: "/docs".into(),
+                    serve: Some("static".into()),
+                    root: Some(docs.clone()),
+                    auto_index: None,
+                    download: false,
+                    proxy: None,
+                },
+                Route {
+                    path: "/".into(),
+                    serve: Some("static".into()),
+                    root: None,
+                    auto_index: None,
+                    download: false,
+                    proxy: None,
+                },
+            ],
+            ..Config::default()
+        };
+        let state = AppState { root: public, ..test_state(cfg) };
+        let app = build_router(&state).unwrap();
+        let fetch = |uri: &str| {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        assert_eq!(fetch("/docs/guide.md").await, (StatusCode::OK, "# Guide".into()));
+        assert_eq!(fetch("/index.html").await, (StatusCode::OK, "home".into()));
+        // Each route only sees its own root
+        assert_eq!(fetch("/docs/index.html").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(fetch("/guide.md").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(fetch("/docs/../index.html").await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_static_route_auto_index() {
+        let root = std::env::temp_dir()
+            .join(format!("statiker-route-index-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/notes.txt"), "notes").unwrap();
+
+        let route = |path: &str, auto_index| Route {
+            path: path.into(),
+            serve: Some("static".into()),
+            root: None,
+            auto_index,
+            download: false,
+            proxy: None,
+        };
+        let app = |server_auto_index, routing| {
+            let mut cfg = Config {
+                routing,
+                ..Config::default()
+            };
+            cfg.server.auto_index = server_auto_index;
+            let state = AppState { root: root.clone(), ..test_state(cfg) };
+            build_router(&state).unwrap()
+        };
+        let fetch = |app: &Router, uri: &str| {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        // Listing enabled for /files only
+        let opt_in = app(false, vec![route("/files", Some(true)), route("/", None)]);
+        let (status, body) = fetch(&opt_in, "/files/sub/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("notes.txt"));
+        assert_eq!(fetch(&opt_in, "/sub/").await.0, StatusCode::NOT_FOUND);
+
+        // Listing enabled globally, disabled for /files
+        let opt_out = app(true, vec![route("/files", Some(false)), route("/", None)]);
+        assert_eq!(fetch(&opt_out, "/files/sub/").await.0, StatusCode::NOT_FOUND);
+        let (status, body) = fetch(&opt_out, "/sub/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("notes.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_download_route() {
+        let root = std::env::temp_dir()
+            .join(format!("statiker-download-route-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("files/sub")).unwrap();
+        std::fs::write(root.join("files/Résumé 2024.pdf"), "pdf").unwrap();
+        std::fs::write(root.join("page.html"), "page").unwrap();
+
+        let cfg = Config {
+            routing: vec![
+                Route {
+                    path: "/files".into(),
+                    serve: Some("static".into()),
+                    root: Some(root.join("files")),
+                    auto_index: Some(true),
+                    download: true,
+                    proxy: None,
+                },
+                Route {
+                    path: "/".into(),
+                    serve: Some("static".into()),
+                    ..Route::default()
+                },
+            ],
+            ..Config::default()
+        };
+        let state = AppState { root, ..test_state(cfg) };
+        let app = build_router(&state).unwrap();
+        let disposition = |uri: &str| {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
+                res.headers().get(http::header::CONTENT_DISPOSITION).cloned()
+            }
+        };
+
+        assert_eq!(
+            disposition("/files/R%C3%A9sum%C3%A9%202024.pdf").await.unwrap(),
+            "attachment; filename=\"R_sum_ 2024.pdf\"; filename*=UTF-8''R%C3%A9sum%C3%A9%202024.pdf"
+        );
+        // Listings and other routes are left inline
+        assert_eq!(disposition("/files/sub/").await, None);
+        assert_eq!(disposition("/page.html").await, None);
+    }
+}
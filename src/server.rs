@@ -1,14 +1,50 @@
-use crate::config::Config;
+use crate::config::{ClientAuthMode, Config};
+use crate::router::mount_static_route;
+use crate::state::AppState;
 use anyhow::{Context, Result};
-use axum_server::tls_rustls::RustlsConfig;
-use tokio::io::AsyncReadExt;
-use tracing::info;
+use axum::{
+    body::Body,
+    http::{
+        header::{HOST, LOCATION},
+        uri::Authority,
+        HeaderMap, StatusCode, Uri,
+    },
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use http::Request;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::future::{Future, IntoFuture};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tower::Service;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
 
 /// Validate TLS configuration and files
 pub async fn validate_tls(cfg: &Config) -> Result<()> {
     if !cfg.tls.enabled {
         return Ok(());
     }
+    if cfg.tls.client_auth.enabled {
+        client_cert_verifier(cfg).await?;
+    }
+    if uses_self_signed(cfg).await {
+        return Ok(());
+    }
 
     // Ensure cert & key paths are provided and files exist
     if cfg.tls.cert_path.as_os_str().is_empty() || cfg.tls.key_path.as_os_str().is_empty() {
@@ -56,20 +92,984 @@ pub async fn validate_tls(cfg: &Config) -> Result<()> {
     Ok(())
 }
 
+/// ALPN protocols offered by the TLS listener, in order of preference
+pub fn alpn_protocols(cfg: &Config) -> Vec<Vec<u8>> {
+    let mut protocols = Vec::new();
+    if cfg.tls.http2 {
+        protocols.push(b"h2".to_vec());
+    }
+    protocols.push(b"http/1.1".to_vec());
+    protocols
+}
+
+/// Whether `tls.self_signed` applies: it is set and the cert/key files are missing
+pub async fn uses_self_signed(cfg: &Config) -> bool {
+    let is_file = |path: PathBuf| async move {
+        !path.as_os_str().is_empty()
+            && tokio::fs::metadata(path).await.is_ok_and(|m| m.is_file())
+    };
+    cfg.tls.self_signed
+        && !(is_file(cfg.tls.cert_path.clone()).await && is_file(cfg.tls.key_path.clone()).await)
+}
+
+/// Names a `tls.self_signed` certificate is issued for
+///
+/// `server.host`, unless it's a wildcard address like `0.0.0.0`, followed by
+/// `localhost` and the loopback addresses.
+pub fn self_signed_names(cfg: &Config) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".into(), "::1".into()];
+    let host = cfg.server.host.trim_start_matches('[').trim_end_matches(']');
+    let wildcard = host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified());
+    if !host.is_empty() && !wildcard && !names.iter().any(|n| n.eq_ignore_ascii_case(host)) {
+        names.insert(0, host.to_string());
+    }
+    names
+}
+
 /// Load TLS configuration
+///
+/// With `tls.self_signed` and no cert/key files, a certificate for
+/// [`self_signed_names`] is generated in memory instead. With
+/// `tls.client_auth`, clients are verified against its CA bundle.
 pub async fn load_tls_config(cfg: &Config) -> Result<RustlsConfig> {
-    RustlsConfig::from_pem_file(
-        cfg.tls.cert_path.clone(),
-        cfg.tls.key_path.clone(),
+    let (certs, key) = if uses_self_signed(cfg).await {
+        let names = self_signed_names(cfg);
+        let pair = rcgen::generate_simple_self_signed(names.clone())
+            .context("generating self-signed TLS certificate")?;
+        warn!(
+            "tls.self_signed: serving a generated self-signed certificate for {}. \
+             Browsers will not trust it; use it for local development only",
+            names.join(", ")
+        );
+        let key = PrivatePkcs8KeyDer::from(pair.key_pair.serialize_der());
+        (vec![pair.cert.der().clone()], key.into())
+    } else {
+        let pem = tokio::fs::read(&cfg.tls.cert_path)
+            .await
+            .context("reading TLS cert file")?;
+        let certs = CertificateDer::pem_slice_iter(&pem)
+            .collect::<Result<Vec<_>, _>>()
+            .context("parsing TLS cert file")?;
+        if certs.is_empty() {
+            anyhow::bail!("no certificate in {}", cfg.tls.cert_path.display());
+        }
+        let pem = tokio::fs::read(&cfg.tls.key_path)
+            .await
+            .context("reading TLS key file")?;
+        let key = PrivateKeyDer::from_pem_slice(&pem).context("parsing TLS key file")?;
+        (certs, key)
+    };
+    let builder = ServerConfig::builder();
+    let builder = match client_cert_verifier(cfg).await? {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut server = builder
+        .with_single_cert(certs, key)
+        .context("loading TLS cert/key")?;
+    server.alpn_protocols = alpn_protocols(cfg);
+    Ok(RustlsConfig::from_config(Arc::new(server)))
+}
+
+/// Client certificate verifier for `tls.client_auth`, if enabled
+///
+/// Client certificates must chain up to a CA from `ca_path`. In `optional`
+/// mode, clients without a certificate are let through as well.
+pub async fn client_cert_verifier(cfg: &Config) -> Result<Option<Arc<dyn ClientCertVerifier>>> {
+    let client_auth = &cfg.tls.client_auth;
+    if !client_auth.enabled {
+        return Ok(None);
+    }
+    let pem = tokio::fs::read(&client_auth.ca_path)
+        .await
+        .with_context(|| format!("reading client CA file {}", client_auth.ca_path.display()))?;
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(&pem) {
+        roots
+            .add(cert.context("parsing client CA file")?)
+            .context("adding client CA certificate")?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("no certificate in {}", client_auth.ca_path.display());
+    }
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = match client_auth.mode {
+        ClientAuthMode::Require => builder,
+        ClientAuthMode::Optional => builder.allow_unauthenticated(),
+    };
+    Ok(Some(builder.build().context("building client certificate verifier")?))
+}
+
+/// Verified client certificate of a TLS connection, identified by its subject CN
+///
+/// Inserted into the extensions of every request on a connection whose client
+/// presented a certificate accepted by `tls.client_auth`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity(pub String);
+
+/// Common name (CN) from the subject of a DER-encoded X.509 certificate
+pub fn subject_common_name(cert: &[u8]) -> Option<String> {
+    const CONTEXT_0: u8 = 0xa0;
+    const CN_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+    let (_, cert, _) = der_next(cert)?;
+    let (_, mut tbs, _) = der_next(cert)?;
+    // The version is optional; then serial number, signature algorithm,
+    // issuer and validity precede the subject
+    let (tag, _, rest) = der_next(tbs)?;
+    if tag == CONTEXT_0 {
+        tbs = rest;
+    }
+    for _ in 0..4 {
+        tbs = der_next(tbs)?.2;
+    }
+    let (_, mut rdns, _) = der_next(tbs)?;
+    while !rdns.is_empty() {
+        let (_, mut attrs, rest) = der_next(rdns)?;
+        rdns = rest;
+        while !attrs.is_empty() {
+            let (_, attr, rest) = der_next(attrs)?;
+            attrs = rest;
+            let (_, oid, value) = der_next(attr)?;
+            if oid == CN_OID {
+                let (_, value, _) = der_next(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Split the first DER element off `input`: its tag, contents and the rest
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let (bytes, tail) = rest.split_at(n);
+        rest = tail;
+        bytes.iter().fold(0, |len, b| len << 8 | usize::from(*b))
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(len);
+    Some((tag, contents, rest))
+}
+
+/// TLS acceptor that tags requests with the connection's [`ClientIdentity`]
+#[derive(Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(config: RustlsConfig) -> Self {
+        Self {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = <RustlsAcceptor as Accept<I, S>>::Stream;
+    type Service = WithClientIdentity<S>;
+    type Future = BoxFuture<'static, std::io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, inner) = accept.await?;
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| subject_common_name(cert))
+                .map(ClientIdentity);
+            Ok((stream, WithClientIdentity { inner, identity }))
+        })
+    }
+}
+
+/// Service inserting a connection's [`ClientIdentity`] into each request
+#[derive(Clone)]
+pub struct WithClientIdentity<S> {
+    inner: S,
+    identity: Option<ClientIdentity>,
+}
+
+impl<S, B> Service<Request<B>> for WithClientIdentity<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if let Some(identity) = &self.identity {
+            req.extensions_mut().insert(identity.clone());
+        }
+        self.inner.call(req)
+    }
+}
+
+/// Reload the TLS certificate and key in place from `tls.cert_path`/`key_path`
+///
+/// The new pair is parsed and checked to belong together before it replaces
+/// the current one, so on error the current certificate stays in use. The
+/// ALPN protocols are set on the new config before the swap.
+pub async fn reload_tls_config(cfg: &Config, rustls: &RustlsConfig) -> Result<()> {
+    let fresh = load_tls_config(cfg)
+        .await
+        .context("reloading TLS cert/key")?;
+    rustls.reload_from_config(fresh.get_inner());
+    Ok(())
+}
+
+/// Modification times of the TLS cert and key files
+async fn tls_mtimes(cfg: &Config) -> (Option<SystemTime>, Option<SystemTime>) {
+    let mtime = |path: PathBuf| async move {
+        tokio::fs::metadata(path).await.and_then(|m| m.modified()).ok()
+    };
+    (
+        mtime(cfg.tls.cert_path.clone()).await,
+        mtime(cfg.tls.key_path.clone()).await,
     )
-    .await
-    .context("loading TLS cert/key")
+}
+
+/// Watch the TLS cert/key for changes (`tls.reload_interval`), e.g. renewals
+///
+/// Polls the files' modification times and reloads the pair when either
+/// changed. A pair that fails to load is logged and retried on the next change.
+pub fn spawn_tls_reload(cfg: Arc<Config>, rustls: RustlsConfig) -> Option<JoinHandle<()>> {
+    let interval = cfg.tls.reload_interval;
+    if interval.is_zero() {
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        let mut last = tls_mtimes(&cfg).await;
+        loop {
+            tokio::time::sleep(interval).await;
+            let current = tls_mtimes(&cfg).await;
+            if current == last {
+                continue;
+            }
+            last = current;
+            match reload_tls_config(&cfg, &rustls).await {
+                Ok(()) => info!("reloaded TLS certificate {}", cfg.tls.cert_path.display()),
+                Err(e) => warn!("keeping the current TLS certificate: {e:#}"),
+            }
+        }
+    }))
+}
+
+/// Build the router for the plain HTTP listener that serves `tls.http_root`
+///
+/// Everything under the directory is served as static files with the same
+/// handler settings as the main listener, only the root differs. Useful for
+/// ACME HTTP-01 challenges or a plain-text landing page.
+pub fn http_root_router(state: &AppState, root: PathBuf) -> Router {
+    let state = AppState {
+        root,
+        ..state.clone()
+    };
+    mount_static_route(Router::new(), &state, "/")
+}
+
+/// Build the plain HTTP listener next to the TLS listener, if one is configured
+///
+/// Returns the port and router. A bare `tls.http_root` is served on
+/// `tls.http_port`. With `tls.redirect_http` the listener runs on `from_port`
+/// and redirects to HTTPS; combined with `tls.http_root`, files under that
+/// directory are served and only misses are redirected.
+pub fn plain_http_router(state: &AppState) -> Option<(u16, Router)> {
+    let tls = &state.cfg.tls;
+    let cfg = state.cfg.clone();
+    match (&tls.redirect_http, &tls.http_root) {
+        (None, None) => None,
+        (None, Some(root)) => Some((tls.http_port, http_root_router(state, root.clone()))),
+        (Some(redirect), None) => {
+            let app = Router::new().fallback(move |req: Request<Body>| {
+                let cfg = cfg.clone();
+                async move { redirect_to_https(&cfg, req.headers(), req.uri()) }
+            });
+            Some((redirect.from_port, app))
+        }
+        (Some(redirect), Some(root)) => {
+            let app = http_root_router(state, root.clone()).layer(from_fn(
+                move |req: Request<Body>, next: Next| {
+                    let cfg = cfg.clone();
+                    async move {
+                        let (headers, uri) = (req.headers().clone(), req.uri().clone());
+                        let res = next.run(req).await;
+                        match res.status() {
+                            StatusCode::NOT_FOUND => redirect_to_https(&cfg, &headers, &uri),
+                            _ => res,
+                        }
+                    }
+                },
+            ));
+            Some((redirect.from_port, app))
+        }
+    }
+}
+
+/// `301` to the same host and path on the TLS listener
+///
+/// The port is taken from `server.port` (omitted when it is 443). Requests
+/// without a usable `Host` header are redirected to `server.host`.
+pub fn redirect_to_https(cfg: &Arc<Config>, headers: &HeaderMap, uri: &Uri) -> Response {
+    let host = headers
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|h| h.parse::<Authority>().ok())
+        .map(|a| a.host().to_string())
+        .unwrap_or_else(|| cfg.server.host.clone());
+    let port = match cfg.server.port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    let location = format!("https://{host}{port}{path}");
+    (StatusCode::MOVED_PERMANENTLY, [(LOCATION, location)]).into_response()
+}
+
+/// Resolves on Ctrl-C (SIGINT) or, on Unix, SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Number of requests currently being handled, for shutdown reporting
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Middleware counting the request while it runs
+    pub async fn track(self, req: Request<Body>, next: Next) -> Response {
+        struct Guard(Arc<AtomicUsize>);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        self.0.fetch_add(1, Ordering::SeqCst);
+        let _guard = Guard(self.0.clone());
+        next.run(req).await
+    }
+}
+
+/// Graceful shutdown (`server.shutdown_timeout`)
+///
+/// Once `trigger` resolves, listeners stop accepting connections and
+/// in-flight requests get up to `timeout` to finish before [`Shutdown::drain`]
+/// gives up on them.
+#[derive(Clone)]
+pub struct Shutdown {
+    trigger: Shared<BoxFuture<'static, ()>>,
+    timeout: Duration,
+    in_flight: InFlight,
+}
+
+impl Shutdown {
+    pub fn new(
+        trigger: impl Future<Output = ()> + Send + 'static,
+        timeout: Duration,
+        in_flight: InFlight,
+    ) -> Self {
+        Self {
+            trigger: trigger.boxed().shared(),
+            timeout,
+            in_flight,
+        }
+    }
+
+    /// Resolves when shutdown was requested; pass to `with_graceful_shutdown`
+    pub fn signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.trigger.clone()
+    }
+
+    /// Run `serve` until it exits, or until shutdown was requested and
+    /// in-flight requests finished or the timeout elapsed
+    pub async fn drain<F>(&self, serve: F) -> std::io::Result<()>
+    where
+        F: IntoFuture<Output = std::io::Result<()>>,
+    {
+        let serve = serve.into_future();
+        tokio::pin!(serve);
+        tokio::select! {
+            res = &mut serve => return res,
+            _ = self.signal() => {}
+        }
+        let pending = self.in_flight.count();
+        info!(
+            "shutting down, draining {pending} in-flight request(s) (timeout {})",
+            humantime::format_duration(self.timeout)
+        );
+        match tokio::time::timeout(self.timeout, serve).await {
+            Ok(res) => {
+                info!("drained {pending} in-flight request(s), shutdown complete");
+                res
+            }
+            Err(_) => {
+                let left = self.in_flight.count();
+                warn!("shutdown timeout elapsed, dropping {left} in-flight request(s)");
+                Ok(())
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
+    use crate::state::test_state;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_http_root_router_serves_alternate_root() {
+        let root =
+            std::env::temp_dir().join(format!("statiker-http-root-{}", std::process::id()));
+        std::fs::create_dir_all(root.join(".well-known/acme-challenge")).unwrap();
+        std::fs::write(root.join(".well-known/acme-challenge/token"), "proof").unwrap();
+
+        let state = AppState {
+            root: PathBuf::from("/nonexistent"),
+            ..test_state(Config::default())
+        };
+        let app = http_root_router(&state, root);
+        let res = app
+            .oneshot(
+                Request::get("/.well-known/acme-challenge/token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"proof");
+    }
+
+    /// Write a fresh self-signed cert/key pair for `localhost` and return the PEMs
+    fn write_self_signed(cert_path: &std::path::Path, key_path: &std::path::Path) -> String {
+        let pair = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        std::fs::write(cert_path, pair.cert.pem()).unwrap();
+        std::fs::write(key_path, pair.key_pair.serialize_pem()).unwrap();
+        pair.cert.pem()
+    }
+
+    #[tokio::test]
+    async fn test_tls_reload() {
+        let dir = std::env::temp_dir().join(format!("statiker-tls-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = Config::default();
+        cfg.tls.cert_path = dir.join("cert.pem");
+        cfg.tls.key_path = dir.join("key.pem");
+        let first_cert = write_self_signed(&cfg.tls.cert_path, &cfg.tls.key_path);
+        let rustls = load_tls_config(&cfg).await.unwrap();
+        let initial = rustls.get_inner();
+
+        // A renewed pair is swapped in
+        write_self_signed(&cfg.tls.cert_path, &cfg.tls.key_path);
+        reload_tls_config(&cfg, &rustls).await.unwrap();
+        let renewed = rustls.get_inner();
+        assert!(!Arc::ptr_eq(&initial, &renewed));
+
+        // A cert that doesn't match the key is rejected and the current one kept
+        std::fs::write(&cfg.tls.cert_path, first_cert).unwrap();
+        assert!(reload_tls_config(&cfg, &rustls).await.is_err());
+        assert!(Arc::ptr_eq(&renewed, &rustls.get_inner()));
+        std::fs::write(&cfg.tls.key_path, "not a key").unwrap();
+        assert!(reload_tls_config(&cfg, &rustls).await.is_err());
+        assert!(Arc::ptr_eq(&renewed, &rustls.get_inner()));
+
+        // The watcher picks up changed files on its own
+        cfg.tls.reload_interval = Duration::from_millis(20);
+        let watcher = spawn_tls_reload(Arc::new(cfg.clone()), rustls.clone()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        write_self_signed(&cfg.tls.cert_path, &cfg.tls.key_path);
+        let later = SystemTime::now() + Duration::from_secs(5);
+        for path in [&cfg.tls.cert_path, &cfg.tls.key_path] {
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(later)
+                .unwrap();
+        }
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while Arc::ptr_eq(&renewed, &rustls.get_inner()) {
+            assert!(std::time::Instant::now() < deadline, "watcher didn't reload");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        watcher.abort();
+
+        cfg.tls.reload_interval = Duration::ZERO;
+        assert!(spawn_tls_reload(Arc::new(cfg), rustls).is_none());
+    }
+
+    /// Serve a handler sleeping for `delay` with graceful shutdown on `trigger`
+    ///
+    /// Returns the address, the in-flight counter and the draining server task.
+    async fn spawn_draining_server(
+        delay: Duration,
+        timeout: Duration,
+        trigger: tokio::sync::oneshot::Receiver<()>,
+    ) -> (std::net::SocketAddr, InFlight, tokio::task::JoinHandle<std::io::Result<()>>) {
+        let in_flight = InFlight::default();
+        let tracked = in_flight.clone();
+        let app = Router::new()
+            .route(
+                "/slow",
+                axum::routing::get(move || async move {
+                    tokio::time::sleep(delay).await;
+                    "done"
+                }),
+            )
+            .layer(from_fn(move |req, next: Next| tracked.clone().track(req, next)));
+        let shutdown = Shutdown::new(
+            async {
+                let _ = trigger.await;
+            },
+            timeout,
+            in_flight.clone(),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let serve = axum::serve(listener, app).with_graceful_shutdown(shutdown.signal());
+            shutdown.drain(serve).await
+        });
+        (addr, in_flight, task)
+    }
+
+    /// Send `GET /slow` and wait until the server is handling it
+    async fn start_slow_request(
+        addr: std::net::SocketAddr,
+        in_flight: &InFlight,
+    ) -> tokio::net::TcpStream {
+        use tokio::io::AsyncWriteExt;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        while in_flight.count() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        stream
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_requests() {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let (addr, in_flight, task) =
+            spawn_draining_server(Duration::from_millis(200), Duration::from_secs(5), rx).await;
+        let mut stream = start_slow_request(addr, &in_flight).await;
+
+        tx.send(()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("done"));
+
+        let res = tokio::time::timeout(Duration::from_secs(2), task).await;
+        assert!(res.unwrap().unwrap().is_ok());
+        assert_eq!(in_flight.count(), 0);
+        // The listener is closed
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout_abandons_slow_requests() {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let (addr, in_flight, task) =
+            spawn_draining_server(Duration::from_secs(30), Duration::from_millis(100), rx).await;
+        let _stream = start_slow_request(addr, &in_flight).await;
+
+        let started = std::time::Instant::now();
+        tx.send(()).unwrap();
+        let res = tokio::time::timeout(Duration::from_secs(2), task).await;
+        assert!(res.unwrap().unwrap().is_ok());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_redirect_to_https() {
+        let mut cfg = Config::default();
+        cfg.server.host = "example.org".into();
+        cfg.server.port = 443;
+        let cfg = Arc::new(cfg);
+        let uri: Uri = "/docs/page?x=1".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, "example.com:80".parse().unwrap());
+        let res = redirect_to_https(&cfg, &headers, &uri);
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            res.headers().get(LOCATION).unwrap(),
+            "https://example.com/docs/page?x=1"
+        );
+
+        // Missing Host falls back to server.host
+        let res = redirect_to_https(&cfg, &HeaderMap::new(), &uri);
+        assert_eq!(
+            res.headers().get(LOCATION).unwrap(),
+            "https://example.org/docs/page?x=1"
+        );
+
+        // Non-default TLS port is kept
+        let mut cfg = (*cfg).clone();
+        cfg.server.port = 8443;
+        let res = redirect_to_https(&Arc::new(cfg), &headers, &"/".parse().unwrap());
+        assert_eq!(res.headers().get(LOCATION).unwrap(), "https://example.com:8443/");
+    }
+
+    #[tokio::test]
+    async fn test_plain_http_router_http_port() {
+        let root = std::env::temp_dir().join(format!("statiker-http-port-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("token"), "proof").unwrap();
+        let mut cfg = Config::default();
+        cfg.tls.http_root = Some(root);
+        cfg.tls.http_port = 8080;
+        let state = AppState { root: PathBuf::from("/nonexistent"), ..test_state(cfg) };
+        let (port, app) = plain_http_router(&state).unwrap();
+        assert_eq!(port, 8080);
+        let req = Request::get("/token").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // `redirect_http.from_port` takes precedence
+        let mut cfg = (*state.cfg).clone();
+        cfg.tls.redirect_http = Some(crate::config::RedirectHttp { from_port: 8081 });
+        let state = AppState { cfg: Arc::new(cfg), ..state };
+        assert_eq!(plain_http_router(&state).unwrap().0, 8081);
+    }
+
+    #[tokio::test]
+    async fn test_plain_http_router_redirects_misses() {
+        let root =
+            std::env::temp_dir().join(format!("statiker-redirect-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("token"), "proof").unwrap();
+        let mut cfg = Config::default();
+        cfg.server.port = 443;
+        cfg.tls.redirect_http = Some(Default::default());
+        cfg.tls.http_root = Some(root);
+        let state = AppState { root: PathBuf::from("/nonexistent"), ..test_state(cfg) };
+        let (port, app) = plain_http_router(&state).unwrap();
+        assert_eq!(port, 80);
+
+        let req = Request::get("/token").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::get("/page")
+            .header(HOST, "example.com")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers().get(LOCATION).unwrap(), "https://example.com/page");
+    }
+
+    #[tokio::test]
+    async fn test_tls_alpn_protocols() {
+        let dir = std::env::temp_dir().join(format!("statiker-tls-alpn-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = Config::default();
+        cfg.tls.cert_path = dir.join("cert.pem");
+        cfg.tls.key_path = dir.join("key.pem");
+        write_self_signed(&cfg.tls.cert_path, &cfg.tls.key_path);
+
+        let rustls = load_tls_config(&cfg).await.unwrap();
+        assert_eq!(
+            rustls.get_inner().alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+
+        cfg.tls.http2 = false;
+        let rustls = load_tls_config(&cfg).await.unwrap();
+        assert_eq!(rustls.get_inner().alpn_protocols, vec![b"http/1.1".to_vec()]);
+        // A reload keeps the configured protocols
+        reload_tls_config(&cfg, &rustls).await.unwrap();
+        assert_eq!(rustls.get_inner().alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_self_signed_names() {
+        let mut cfg = Config::default();
+        assert_eq!(self_signed_names(&cfg), ["localhost", "127.0.0.1", "::1"]);
+        cfg.server.host = "dev.example.test".into();
+        assert_eq!(
+            self_signed_names(&cfg),
+            ["dev.example.test", "localhost", "127.0.0.1", "::1"]
+        );
+        cfg.server.host = "127.0.0.1".into();
+        assert_eq!(self_signed_names(&cfg), ["localhost", "127.0.0.1", "::1"]);
+    }
+
+    /// Accepts any server certificate, for talking to a self-signed listener
+    #[derive(Debug)]
+    struct AcceptAnyCert;
+
+    impl rustls::client::danger::ServerCertVerifier for AcceptAnyCert {
+        fn verify_server_cert(
+            &self,
+            _: &rustls::pki_types::CertificateDer<'_>,
+            _: &[rustls::pki_types::CertificateDer<'_>],
+            _: &rustls::pki_types::ServerName<'_>,
+            _: &[u8],
+            _: rustls::pki_types::UnixTime,
+        ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _: &[u8],
+            _: &rustls::pki_types::CertificateDer<'_>,
+            _: &rustls::DigitallySignedStruct,
+        ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _: &[u8],
+            _: &rustls::pki_types::CertificateDer<'_>,
+            _: &rustls::DigitallySignedStruct,
+        ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+            vec![
+                rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+                rustls::SignatureScheme::ED25519,
+                rustls::SignatureScheme::RSA_PSS_SHA256,
+            ]
+        }
+    }
+
+    type WantsClientCert =
+        rustls::ConfigBuilder<rustls::ClientConfig, rustls::client::WantsClientCert>;
+
+    fn accept_any_cert() -> WantsClientCert {
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+    }
+
+    /// `GET /` from a TLS listener on `port`, returning the body
+    async fn https_get(port: u16, client_tls: rustls::ClientConfig) -> Result<String> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(client_tls)
+            .https_only()
+            .enable_http1()
+            .build();
+        let client = hyper_util::client::legacy::Client::builder(
+            hyper_util::rt::TokioExecutor::new(),
+        )
+        .build::<_, Body>(connector);
+        let req = Request::get(format!("https://localhost:{port}/")).body(Body::empty())?;
+        let res = client.request(req).await?;
+        let body = axum::body::to_bytes(Body::new(res.into_body()), usize::MAX).await?;
+        Ok(String::from_utf8(body.to_vec())?)
+    }
+
+    #[tokio::test]
+    async fn test_tls_self_signed() {
+        let mut cfg = Config::default();
+        cfg.tls.enabled = true;
+        cfg.tls.self_signed = true;
+        cfg.tls.cert_path = PathBuf::from("/nonexistent/cert.pem");
+        cfg.tls.key_path = PathBuf::from("/nonexistent/key.pem");
+        assert!(uses_self_signed(&cfg).await);
+        assert!(validate_tls(&cfg).await.is_ok());
+        let tls = load_tls_config(&cfg).await.unwrap();
+
+        // Serves HTTPS with the generated certificate
+        let app = Router::new().route("/", axum::routing::get(|| async { "secure" }));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(axum_server::from_tcp_rustls(listener, tls).serve(app.into_make_service()));
+        let client_tls = accept_any_cert().with_no_client_auth();
+        assert_eq!(https_get(port, client_tls).await.unwrap(), "secure");
+
+        // Existing cert/key files take precedence
+        let dir = std::env::temp_dir().join(format!("statiker-tls-self-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        cfg.tls.cert_path = dir.join("cert.pem");
+        cfg.tls.key_path = dir.join("key.pem");
+        write_self_signed(&cfg.tls.cert_path, &cfg.tls.key_path);
+        assert!(!uses_self_signed(&cfg).await);
+        cfg.tls.self_signed = false;
+        cfg.tls.cert_path = PathBuf::new();
+        assert!(!uses_self_signed(&cfg).await);
+        assert!(validate_tls(&cfg).await.is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// CA for client certificates, written to `ca_path`
+    fn write_client_ca(ca_path: &std::path::Path) -> (rcgen::Certificate, rcgen::KeyPair) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.distinguished_name.push(rcgen::DnType::CommonName, "Test CA");
+        let ca = params.self_signed(&key).unwrap();
+        std::fs::write(ca_path, ca.pem()).unwrap();
+        (ca, key)
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_verifier() {
+        let dir = std::env::temp_dir().join(format!("statiker-mtls-ca-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = Config::default();
+        assert!(client_cert_verifier(&cfg).await.unwrap().is_none());
+
+        cfg.tls.client_auth.enabled = true;
+        cfg.tls.client_auth.ca_path = dir.join("missing.pem");
+        let err = client_cert_verifier(&cfg).await.err().unwrap();
+        assert!(err.to_string().contains("reading client CA file"), "{err}");
+        cfg.tls.client_auth.ca_path = dir.join("empty.pem");
+        std::fs::write(&cfg.tls.client_auth.ca_path, "").unwrap();
+        assert!(client_cert_verifier(&cfg).await.is_err());
+
+        cfg.tls.client_auth.ca_path = dir.join("ca.pem");
+        write_client_ca(&cfg.tls.client_auth.ca_path);
+        let verifier = client_cert_verifier(&cfg).await.unwrap().unwrap();
+        assert!(verifier.client_auth_mandatory());
+        assert!(verifier.offer_client_auth());
+        cfg.tls.client_auth.mode = ClientAuthMode::Optional;
+        let verifier = client_cert_verifier(&cfg).await.unwrap().unwrap();
+        assert!(!verifier.client_auth_mandatory());
+        assert!(verifier.offer_client_auth());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_subject_common_name() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".into()]).unwrap();
+        params.distinguished_name.push(rcgen::DnType::OrganizationName, "Example");
+        params.distinguished_name.push(rcgen::DnType::CommonName, "svc-a");
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(subject_common_name(cert.der()), Some("svc-a".into()));
+
+        // Without a CN
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".into()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::OrganizationName, "Example");
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(subject_common_name(cert.der()), None);
+        assert_eq!(subject_common_name(b"\x30\x82\xff"), None);
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let dir = std::env::temp_dir().join(format!("statiker-mtls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = Config::default();
+        cfg.tls.enabled = true;
+        cfg.tls.self_signed = true;
+        cfg.tls.client_auth.enabled = true;
+        cfg.tls.client_auth.ca_path = dir.join("ca.pem");
+        let (ca, ca_key) = write_client_ca(&cfg.tls.client_auth.ca_path);
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, "svc-a");
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        // Echo the identity the acceptor attached to the request
+        let identity = |req: Request<Body>| async move {
+            match req.extensions().get::<ClientIdentity>() {
+                Some(id) => id.0.clone(),
+                None => "anonymous".into(),
+            }
+        };
+        let serve = |cfg: Config| async move {
+            let tls = load_tls_config(&cfg).await.unwrap();
+            let app = Router::new().route("/", axum::routing::get(identity));
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = axum_server::from_tcp(listener).acceptor(ClientCertAcceptor::new(tls));
+            tokio::spawn(server.serve(app.into_make_service()));
+            port
+        };
+        let with_cert = || {
+            let key = PrivatePkcs8KeyDer::from(client_key.serialize_der());
+            accept_any_cert()
+                .with_client_auth_cert(vec![client_cert.der().clone()], key.into())
+                .unwrap()
+        };
+
+        let port = serve(cfg.clone()).await;
+        assert_eq!(https_get(port, with_cert()).await.unwrap(), "svc-a");
+        let anonymous = accept_any_cert().with_no_client_auth();
+        assert!(https_get(port, anonymous).await.is_err());
+
+        cfg.tls.client_auth.mode = ClientAuthMode::Optional;
+        let port = serve(cfg).await;
+        assert_eq!(https_get(port, with_cert()).await.unwrap(), "svc-a");
+        let anonymous = accept_any_cert().with_no_client_auth();
+        assert_eq!(https_get(port, anonymous).await.unwrap(), "anonymous");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 
     #[tokio::test]
     async fn test_validate_tls_disabled() {
@@ -0,0 +1,149 @@
+use crate::router::resolve_path_within_root;
+use futures_util::future::{BoxFuture, FutureExt};
+use std::path::{Path, PathBuf};
+
+/// Maximum nesting of `#include` directives, guards against include loops
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Text substituted for directives that cannot be processed
+const SSI_ERROR: &str = "[an error occurred while processing this directive]";
+
+/// Expand `<!--#include file="..." -->` directives in an HTML document
+///
+/// `file` paths are relative to `dir` (the including document's directory)
+/// and may not leave it, not even through symlinks, so includes always stay
+/// within the served root.
+pub fn expand(html: String, dir: PathBuf, depth: usize) -> BoxFuture<'static, String> {
+    async move {
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html.as_str();
+        while let Some(start) = rest.find("<!--#include") {
+            out.push_str(&rest[..start]);
+            let directive = &rest[start..];
+            let Some(end) = directive.find("-->") else {
+                // Unterminated directive: emit verbatim
+                out.push_str(directive);
+                rest = "";
+                break;
+            };
+            let body = &directive[..end];
+            rest = &directive[end + 3..];
+            out.push_str(&include(body, &dir, depth).await);
+        }
+        out.push_str(rest);
+        out
+    }
+    .boxed()
+}
+
+/// Resolve a single directive body (without the closing `-->`)
+async fn include(directive: &str, dir: &Path, depth: usize) -> String {
+    if depth >= MAX_INCLUDE_DEPTH {
+        return SSI_ERROR.to_string();
+    }
+    let Some(file) = parse_file_attr(directive) else {
+        return SSI_ERROR.to_string();
+    };
+    let Ok(path) = resolve_path_within_root(dir, file) else {
+        return SSI_ERROR.to_string();
+    };
+    match tokio::fs::read_to_string(&path).await {
+        Ok(content) => {
+            let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            expand(content, parent, depth + 1).await
+        }
+        Err(_) => SSI_ERROR.to_string(),
+    }
+}
+
+/// Extract the value of `file="..."` from a directive
+fn parse_file_attr(directive: &str) -> Option<&str> {
+    let start = directive.find("file=\"")? + "file=\"".len();
+    let len = directive[start..].find('"')?;
+    Some(&directive[start..start + len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("statiker-ssi-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_file_attr() {
+        assert_eq!(
+            parse_file_attr(r#"<!--#include file="a.html" "#),
+            Some("a.html")
+        );
+        assert_eq!(parse_file_attr(r#"<!--#include virtual="/a.html" "#), None);
+    }
+
+    #[tokio::test]
+    async fn test_expand_nested() {
+        let dir = temp_dir("nested");
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        std::fs::write(
+            dir.join("header.html"),
+            r#"<h1><!--#include file="parts/t.txt" --></h1>"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("parts/t.txt"), "Title").unwrap();
+        let html = r#"<body><!--#include file="header.html" -->text</body>"#.to_string();
+        assert_eq!(
+            expand(html, dir, 0).await,
+            "<body><h1>Title</h1>text</body>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expand_loop_is_bounded() {
+        let dir = temp_dir("loop");
+        std::fs::write(
+            dir.join("self.html"),
+            r#"x<!--#include file="self.html" -->"#,
+        )
+        .unwrap();
+        let out = expand(r#"<!--#include file="self.html" -->"#.into(), dir, 0).await;
+        assert_eq!(
+            out,
+            format!("{}{}", "x".repeat(MAX_INCLUDE_DEPTH), SSI_ERROR)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expand_rejects_traversal_and_missing() {
+        let dir = temp_dir("traversal");
+        let out = expand(
+            r#"a<!--#include file="../etc/passwd" -->b"#.into(),
+            dir.clone(),
+            0,
+        )
+        .await;
+        assert_eq!(out, format!("a{SSI_ERROR}b"));
+        let out = expand(r#"<!--#include file="missing.html" -->"#.into(), dir, 0).await;
+        assert_eq!(out, SSI_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_expand_unterminated() {
+        let out = expand("a<!--#include file=\"x\"".into(), PathBuf::from("."), 0).await;
+        assert_eq!(out, "a<!--#include file=\"x\"");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_expand_rejects_symlink_escape() {
+        let outside = temp_dir("symlink-outside");
+        std::fs::write(outside.join("secret.html"), "secret").unwrap();
+        let dir = temp_dir("symlink");
+        std::os::unix::fs::symlink(outside.join("secret.html"), dir.join("leak.html")).unwrap();
+        let out = expand(r#"<!--#include file="leak.html" -->"#.into(), dir, 0).await;
+        assert_eq!(out, SSI_ERROR);
+    }
+}
@@ -1,14 +1,32 @@
-use crate::config::Config;
+use crate::config::{AccessLogFormat, Config, ProxyClient, RateLimit};
+use arc_swap::ArcSwap;
 use axum::body::Body;
 use governor::{
-    clock::DefaultClock, middleware::NoOpMiddleware, state::keyed::DashMapStateStore, RateLimiter,
+    clock::DefaultClock, middleware::NoOpMiddleware, state::keyed::DashMapStateStore, Quota,
+    RateLimiter,
 };
-use hyper_rustls::HttpsConnectorBuilder;
+use hyper_rustls::{builderstates::WantsSchemes, HttpsConnectorBuilder};
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
-use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use ipnet::IpNet;
+use crate::utils::glob_to_regex;
+use http::{HeaderValue, StatusCode};
 use once_cell::sync::Lazy;
-use std::{net::IpAddr, path::PathBuf, sync::Arc};
+use regex::Regex;
+use bytes::Bytes;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    net::IpAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
 
 /// Application state shared across handlers
 #[derive(Clone)]
@@ -16,34 +34,560 @@ pub struct AppState {
     pub cfg: Arc<Config>,
     pub root: PathBuf,
     pub limiter: Option<Arc<IpLimiterInner>>,
+    /// Compiled `security.deny_paths` patterns
+    pub deny_paths: Arc<Vec<(Regex, StatusCode)>>,
+    /// Bounds concurrent static file reads (`server.max_concurrent_reads`)
+    pub read_limit: Option<Arc<Semaphore>>,
+    /// In-flight requests per client IP (`security.per_ip_concurrency`)
+    pub concurrency: Option<Arc<IpConcurrencyLimiter>>,
+    /// Small files kept in memory (`assets.memcache`)
+    pub memcache: Option<Arc<FileCache>>,
+    /// Config swapped on reload (SIGHUP); `None` when reloading is unavailable
+    pub live: Option<Arc<ArcSwap<Config>>>,
+}
+
+/// State for `cfg` with every optional component off, for tests
+///
+/// Override fields with struct update syntax, e.g.
+/// `AppState { root, ..test_state(cfg) }`, so new fields only touch this.
+#[cfg(test)]
+pub fn test_state(cfg: Config) -> AppState {
+    AppState {
+        root: cfg.server.root.clone(),
+        cfg: Arc::new(cfg),
+        limiter: None,
+        deny_paths: Arc::default(),
+        read_limit: None,
+        concurrency: None,
+        memcache: None,
+        live: None,
+    }
+}
+
+impl AppState {
+    /// The config as of the last reload
+    ///
+    /// Only the reloadable sections (see [`Config::reload_from`]) ever differ
+    /// from `cfg`, so read those through here and everything else from `cfg`.
+    pub fn current_cfg(&self) -> Arc<Config> {
+        match &self.live {
+            Some(live) => live.load_full(),
+            None => self.cfg.clone(),
+        }
+    }
 }
 
 pub type IpLimiterInner = RateLimiter<IpAddr, DashMapStateStore<IpAddr>, DefaultClock, NoOpMiddleware>;
 
-/// Shared hyper client (HTTP/1 + TLS). HTTP/2 optional — skipped here.
-pub static HTTP_CLIENT: Lazy<Client<hyper_rustls::HttpsConnector<HttpConnector>, Body>> =
-    Lazy::new(|| {
-        let https = HttpsConnectorBuilder::new()
-            .with_webpki_roots()
-            .https_or_http()
-            .enable_http1()
-            .build();
-        Client::builder(TokioExecutor::new()).build(https)
-    });
+/// Caps the number of requests each client IP may have in flight
+///
+/// Only IPs with at least one request in flight have an entry; the entry is
+/// removed when the last permit is dropped, so idle clients cost nothing.
+pub struct IpConcurrencyLimiter {
+    max: usize,
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Slot held by an in-flight request, released on drop
+pub struct ConcurrencyPermit {
+    limiter: Arc<IpConcurrencyLimiter>,
+    ip: IpAddr,
+}
+
+impl IpConcurrencyLimiter {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a slot for `ip`, or `None` if it already has `max` requests in flight
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConcurrencyPermit> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(ip).or_insert(0);
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(ConcurrencyPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Number of IPs with requests in flight
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.active.lock().unwrap().len()
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        let mut active = self.limiter.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// File contents kept by [`FileCache`], with the metadata to revalidate them
+///
+/// Nothing derived from the request path is stored, since one file can be
+/// served under several names (symlinks, pre-compressed siblings).
+pub struct CachedFile {
+    pub bytes: Bytes,
+    /// Modification time when read; files without one are never cached
+    pub modified: Option<SystemTime>,
+}
+
+/// In-memory LRU cache of small static files (`assets.memcache`)
+///
+/// Entries are keyed by canonical path and only returned while the file's
+/// modification time and size still match. Once the cached contents exceed
+/// `max_bytes`, the least recently used entries are evicted.
+pub struct FileCache {
+    max_bytes: u64,
+    max_file_size: u64,
+    inner: Mutex<FileCacheInner>,
+}
+
+#[derive(Default)]
+struct FileCacheInner {
+    /// Entries with the tick of their last use
+    entries: HashMap<PathBuf, (Arc<CachedFile>, u64)>,
+    /// Keys by last use, least recent first
+    recency: BTreeMap<u64, PathBuf>,
+    tick: u64,
+    bytes: u64,
+}
+
+impl FileCacheInner {
+    fn remove(&mut self, key: &Path) {
+        if let Some((file, used)) = self.entries.remove(key) {
+            self.recency.remove(&used);
+            self.bytes -= file.bytes.len() as u64;
+        }
+    }
+}
+
+impl FileCache {
+    pub fn new(max_bytes: u64, max_file_size: u64) -> Self {
+        Self {
+            max_bytes,
+            max_file_size,
+            inner: Mutex::new(FileCacheInner::default()),
+        }
+    }
+
+    /// Whether a file of `len` bytes may be cached
+    pub fn admits(&self, len: u64) -> bool {
+        len <= self.max_file_size && len <= self.max_bytes
+    }
+
+    /// The entry for `key`, if it still matches the file's current `meta`
+    ///
+    /// A stale entry is dropped.
+    pub fn get(&self, key: &Path, meta: &std::fs::Metadata) -> Option<Arc<CachedFile>> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let (file, used) = inner.entries.get_mut(key)?;
+        let current = file.modified.is_some()
+            && file.modified == meta.modified().ok()
+            && file.bytes.len() as u64 == meta.len();
+        if !current {
+            inner.remove(key);
+            return None;
+        }
+        let file = file.clone();
+        inner.tick += 1;
+        inner.recency.remove(used);
+        *used = inner.tick;
+        inner.recency.insert(inner.tick, key.to_path_buf());
+        Some(file)
+    }
+
+    /// Cache `file` under `key`, evicting the least recently used entries over budget
+    pub fn insert(&self, key: PathBuf, file: Arc<CachedFile>) {
+        let len = file.bytes.len() as u64;
+        if !self.admits(len) || file.modified.is_none() {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(&key);
+        inner.tick += 1;
+        let used = inner.tick;
+        inner.recency.insert(used, key.clone());
+        inner.entries.insert(key, (file, used));
+        inner.bytes += len;
+        while inner.bytes > self.max_bytes {
+            let Some((_, oldest)) = inner.recency.first_key_value() else {
+                break;
+            };
+            let oldest = oldest.clone();
+            inner.remove(&oldest);
+        }
+    }
+
+    /// Total size of the cached contents
+    #[cfg(test)]
+    pub fn bytes(&self) -> u64 {
+        self.inner.lock().unwrap().bytes
+    }
+}
+
+/// Default interval between rate limiter state sweeps
+pub const DEFAULT_LIMITER_GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Spawn a background task that periodically drops per-IP limiter state
+/// for keys whose quota has fully replenished, so the store doesn't grow
+/// without bound on long-running servers.
+pub fn spawn_limiter_gc(
+    limiter: Arc<IpLimiterInner>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    let interval = if interval == Duration::ZERO {
+        DEFAULT_LIMITER_GC_INTERVAL
+    } else {
+        interval
+    };
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately; skip it
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let before = limiter.len();
+            limiter.retain_recent();
+            limiter.shrink_to_fit();
+            debug!("rate limiter gc: {} -> {} keys", before, limiter.len());
+        }
+    })
+}
+
+/// Build the rate limiting quota from `security.rate_limit`
+///
+/// `requests` cells replenish evenly over `period`; `burst` caps how
+/// many can be spent at once. Zero requests is treated as one, like before
+/// `period` existed, but a zero period or burst is rejected.
+pub fn build_rate_limit_quota(rl: &RateLimit) -> anyhow::Result<Quota> {
+    if rl.period.is_zero() {
+        anyhow::bail!("rate_limit.period must be greater than zero");
+    }
+    let requests = rl.requests.max(1);
+    let burst = rl.burst.unwrap_or(requests);
+    let burst = NonZeroU32::new(burst)
+        .ok_or_else(|| anyhow::anyhow!("rate_limit.burst must be at least 1"))?;
+    let replenish = rl.period / requests;
+    let quota = Quota::with_period(replenish).ok_or_else(|| {
+        anyhow::anyhow!(
+            "rate_limit.period {} is too short for {requests} requests",
+            humantime::format_duration(rl.period)
+        )
+    })?;
+    Ok(quota.allow_burst(burst))
+}
+
+/// Build the static file read limiter from `server.max_concurrent_reads`
+///
+/// Zero is treated like unset, since a semaphore without permits would
+/// stall every request.
+pub fn build_read_limit(cfg: &Config) -> Option<Arc<Semaphore>> {
+    cfg.server
+        .max_concurrent_reads
+        .filter(|&n| n > 0)
+        .map(|n| Arc::new(Semaphore::new(n)))
+}
+
+/// Build the per-IP concurrency limiter from `security.per_ip_concurrency`
+///
+/// Zero is treated like unset, since it would reject every request.
+pub fn build_concurrency_limiter(cfg: &Config) -> Option<Arc<IpConcurrencyLimiter>> {
+    cfg.security
+        .per_ip_concurrency
+        .filter(|&n| n > 0)
+        .map(|n| Arc::new(IpConcurrencyLimiter::new(n)))
+}
+
+/// Build the in-memory file cache from `assets.memcache`
+///
+/// A zero budget is treated like disabled, since nothing would fit.
+pub fn build_file_cache(cfg: &Config) -> Option<Arc<FileCache>> {
+    let memcache = &cfg.assets.memcache;
+    (memcache.enabled && memcache.max_bytes.0 > 0)
+        .then(|| Arc::new(FileCache::new(memcache.max_bytes.0, memcache.max_file_size.0)))
+}
+
+/// Compile the configured path denylist
+///
+/// Fails on invalid patterns or statuses other than 403/404 so mistakes
+/// surface at startup rather than silently letting requests through.
+pub fn compile_deny_paths(cfg: &Config) -> anyhow::Result<Vec<(Regex, StatusCode)>> {
+    cfg.security
+        .deny_paths
+        .iter()
+        .map(|d| {
+            let re = Regex::new(&d.pattern)
+                .map_err(|e| anyhow::anyhow!("invalid deny_paths pattern '{}': {e}", d.pattern))?;
+            let status = match d.status {
+                403 => StatusCode::FORBIDDEN,
+                404 => StatusCode::NOT_FOUND,
+                other => {
+                    return Err(anyhow::anyhow!(
+                        "deny_paths status for '{}' must be 403 or 404, got {other}",
+                        d.pattern
+                    ))
+                }
+            };
+            Ok((re, status))
+        })
+        .collect()
+}
+
+/// Compiled `assets.rules`: path pattern and `Cache-Control` value, in order
+pub type CacheRules = Vec<(Regex, HeaderValue)>;
+
+/// Compile the configured per-path `Cache-Control` rules
+///
+/// Globs are translated with [`glob_to_regex`]; values must be valid header values.
+pub fn compile_cache_rules(cfg: &Config) -> anyhow::Result<CacheRules> {
+    cfg.assets
+        .rules
+        .iter()
+        .map(|rule| {
+            let re = Regex::new(&glob_to_regex(&rule.pattern)).map_err(|e| {
+                anyhow::anyhow!("invalid assets.rules pattern '{}': {e}", rule.pattern)
+            })?;
+            let value = HeaderValue::from_str(&rule.cache_control).map_err(|_| {
+                anyhow::anyhow!(
+                    "invalid cache_control '{}' for assets.rules pattern '{}'",
+                    rule.cache_control,
+                    rule.pattern
+                )
+            })?;
+            Ok((re, value))
+        })
+        .collect()
+}
+
+/// Compiled `security.ip_filter` ranges
+#[derive(Debug, Default)]
+pub struct IpRules {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    /// Deny wins over allow; an empty allowlist admits everyone not denied
+    ///
+    /// IPv4-mapped IPv6 addresses (from dual-stack sockets) are matched as IPv4.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Whether clients must match the allowlist
+    pub fn has_allowlist(&self) -> bool {
+        !self.allow.is_empty()
+    }
+}
+
+/// Compile `security.ip_filter`, or `None` if both lists are empty
+///
+/// Entries are CIDR ranges (`10.0.0.0/8`, `2001:db8::/32`) or bare addresses.
+pub fn compile_ip_filter(cfg: &Config) -> anyhow::Result<Option<Arc<IpRules>>> {
+    let filter = &cfg.security.ip_filter;
+    if filter.allow.is_empty() && filter.deny.is_empty() {
+        return Ok(None);
+    }
+    let parse = |entries: &[String]| {
+        entries
+            .iter()
+            .map(|entry| {
+                let entry = entry.trim();
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow::anyhow!("invalid ip_filter entry '{entry}'"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+    Ok(Some(Arc::new(IpRules {
+        allow: parse(&filter.allow)?,
+        deny: parse(&filter.deny)?,
+    })))
+}
+
+/// Lines queued for the access log writer before new ones are dropped
+const ACCESS_LOG_BUFFER: usize = 8192;
+
+enum AccessLogMsg {
+    Line(String),
+    /// Acknowledged once every earlier line has been written and flushed
+    Flush(SyncSender<()>),
+}
+
+/// Destination of the access log (`obs.access_log`)
+///
+/// Lines are written by a background thread, so requests never wait on the
+/// file (or stdout).
+pub struct AccessLogSink {
+    pub format: AccessLogFormat,
+    tx: SyncSender<AccessLogMsg>,
+}
+
+impl AccessLogSink {
+    pub fn new(format: AccessLogFormat, out: Box<dyn Write + Send>) -> Self {
+        let (tx, rx) = sync_channel(ACCESS_LOG_BUFFER);
+        std::thread::Builder::new()
+            .name("access-log".into())
+            .spawn(move || write_access_log(rx, out))
+            .expect("failed to spawn access log writer");
+        Self { format, tx }
+    }
+
+    /// Queue one line; failures are logged rather than failing the request
+    pub fn write_line(&self, line: &str) {
+        if self.tx.try_send(AccessLogMsg::Line(line.to_string())).is_err() {
+            warn!("access log writer is behind; dropping a line");
+        }
+    }
+
+    /// Block until every line queued so far has been written
+    pub fn flush(&self) {
+        let (done, wait) = sync_channel(1);
+        if self.tx.send(AccessLogMsg::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+/// Writer thread: drains the queue, flushing whenever it runs empty
+fn write_access_log(rx: Receiver<AccessLogMsg>, mut out: Box<dyn Write + Send>) {
+    while let Ok(mut msg) = rx.recv() {
+        loop {
+            match msg {
+                AccessLogMsg::Line(line) => {
+                    if let Err(e) = writeln!(out, "{line}") {
+                        warn!("failed to write access log: {e}");
+                    }
+                }
+                AccessLogMsg::Flush(done) => {
+                    let _ = out.flush();
+                    let _ = done.send(());
+                }
+            }
+            match rx.try_recv() {
+                Ok(next) => msg = next,
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = out.flush() {
+            warn!("failed to write access log: {e}");
+        }
+    }
+}
+
+/// Open the access log from `obs.access_log`, or `None` if disabled
+///
+/// Log files are opened for appending (and created if missing) at startup,
+/// so an unwritable path fails fast.
+pub fn build_access_log(cfg: &Config) -> anyhow::Result<Option<Arc<AccessLogSink>>> {
+    let access_log = &cfg.obs.access_log;
+    if !access_log.enabled {
+        return Ok(None);
+    }
+    let out: Box<dyn Write + Send> = match &access_log.path {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow::anyhow!("opening access log {}: {e}", path.display()))?;
+            Box::new(std::io::BufWriter::new(file))
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    Ok(Some(Arc::new(AccessLogSink::new(access_log.format, out))))
+}
+
+/// Hyper client used to reach proxy upstreams
+pub type HttpClient = Client<hyper_rustls::HttpsConnector<HttpConnector>, Body>;
+
+/// Build an upstream client from the `proxy_client` settings
+///
+/// `tcp_keepalive` enables TCP keepalive probes so dead upstream connections
+/// are detected and evicted from the pool.
+pub fn build_http_client(settings: &ProxyClient, tcp_keepalive: Option<Duration>) -> HttpClient {
+    client_with_tls(
+        HttpsConnectorBuilder::new().with_webpki_roots(),
+        settings,
+        tcp_keepalive,
+    )
+}
+
+/// Build an upstream client on top of the given TLS setup
+///
+/// HTTP/1.1 is always spoken. With `settings.http2`, TLS upstreams are also
+/// offered `h2` via ALPN and the connection uses whichever they pick; plain
+/// HTTP upstreams stay on HTTP/1.1.
+fn client_with_tls(
+    tls: HttpsConnectorBuilder<WantsSchemes>,
+    settings: &ProxyClient,
+    tcp_keepalive: Option<Duration>,
+) -> HttpClient {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_keepalive(tcp_keepalive);
+    http.set_connect_timeout(nonzero(settings.connect_timeout));
+    let tls = tls.https_or_http();
+    let https = if settings.http2 {
+        tls.enable_all_versions().wrap_connector(http)
+    } else {
+        tls.enable_http1().wrap_connector(http)
+    };
+    let mut builder = Client::builder(TokioExecutor::new());
+    builder
+        .pool_timer(TokioTimer::new())
+        .pool_idle_timeout(nonzero(settings.pool_idle_timeout))
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host.unwrap_or(usize::MAX));
+    builder.build(https)
+}
+
+/// `None` for a zero duration, which disables a timeout
+fn nonzero(d: Duration) -> Option<Duration> {
+    (!d.is_zero()).then_some(d)
+}
+
+/// Upstream clients by settings, so routes share one connection pool
+static HTTP_CLIENTS: Lazy<Mutex<Vec<(ProxyClient, HttpClient)>>> = Lazy::new(Mutex::default);
+
+/// The shared upstream client for `settings`, built on first use
+pub fn shared_http_client(settings: &ProxyClient) -> HttpClient {
+    let mut clients = HTTP_CLIENTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, client)) = clients.iter().find(|(s, _)| s == settings) {
+        return client.clone();
+    }
+    let client = build_http_client(settings, None);
+    clients.push((settings.clone(), client.clone()));
+    client
+}
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::config::Config;
 
     #[test]
     fn test_app_state_clone() {
-        let cfg = Arc::new(Config::default());
-        let state = AppState {
-            cfg: cfg.clone(),
-            root: PathBuf::from("."),
-            limiter: None,
-        };
+        let state = test_state(Config::default());
         let cloned = state.clone();
         assert_eq!(state.root, cloned.root);
         // Verify Arc is shared
@@ -52,19 +596,330 @@ mod tests {
 
     #[test]
     fn test_app_state_with_limiter() {
-        let cfg = Arc::new(Config::default());
         let limiter = Some(Arc::new(
             RateLimiter::keyed(governor::Quota::per_minute(
                 std::num::NonZeroU32::new(60).unwrap(),
             )),
         ));
         let state = AppState {
-            cfg,
             root: PathBuf::from("/tmp"),
             limiter: limiter.clone(),
+            ..test_state(Config::default())
         };
         assert!(state.limiter.is_some());
         assert_eq!(state.root, PathBuf::from("/tmp"));
     }
-}
 
+    #[tokio::test]
+    async fn test_limiter_gc_prunes_stale_keys() {
+        // High quota so a single cell replenishes within a millisecond
+        let limiter: Arc<IpLimiterInner> = Arc::new(RateLimiter::keyed(
+            governor::Quota::per_second(std::num::NonZeroU32::new(1000).unwrap()),
+        ));
+        limiter.check_key(&IpAddr::from([10, 0, 0, 1])).unwrap();
+        limiter.check_key(&IpAddr::from([10, 0, 0, 2])).unwrap();
+        assert_eq!(limiter.len(), 2);
+
+        let handle = spawn_limiter_gc(limiter.clone(), Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(limiter.len(), 0);
+        handle.abort();
+    }
+
+    #[test]
+    fn test_compile_deny_paths() {
+        let mut cfg = Config::default();
+        cfg.security.deny_paths = vec![
+            crate::config::DenyPath {
+                pattern: r"\.php$".into(),
+                status: 404,
+            },
+            crate::config::DenyPath {
+                pattern: "^/admin".into(),
+                status: 403,
+            },
+        ];
+        let compiled = compile_deny_paths(&cfg).unwrap();
+        assert_eq!(compiled.len(), 2);
+        assert!(compiled[0].0.is_match("/index.php"));
+        assert_eq!(compiled[1].1, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_file_cache() {
+        let dir = std::env::temp_dir().join(format!("statiker-file-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = FileCache::new(10, 6);
+        let put = |name: &str, content: &'static str| {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            let file = CachedFile {
+                bytes: Bytes::from_static(content.as_bytes()),
+                modified: std::fs::metadata(&path).unwrap().modified().ok(),
+            };
+            cache.insert(path.clone(), Arc::new(file));
+            path
+        };
+        let get = |path: &Path| {
+            let meta = std::fs::metadata(path).unwrap();
+            cache.get(path, &meta).map(|file| file.bytes.clone())
+        };
+
+        let a = put("a.txt", "aaaa");
+        assert_eq!(get(&a).as_deref(), Some(&b"aaaa"[..]));
+        // Files over max_file_size are not kept
+        let big = put("big.txt", "0123456");
+        assert_eq!(get(&big), None);
+
+        // Over budget, the least recently used entry goes first
+        let b = put("b.txt", "bbbb");
+        assert!(get(&a).is_some());
+        let c = put("c.txt", "cccc");
+        assert_eq!(get(&b), None);
+        assert!(get(&a).is_some() && get(&c).is_some());
+        assert_eq!(cache.bytes(), 8);
+
+        // A changed mtime makes the entry stale
+        let file = std::fs::File::options().write(true).open(&a).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert_eq!(get(&a), None);
+        assert_eq!(cache.bytes(), 4);
+    }
+
+    #[test]
+    fn test_compile_deny_paths_rejects_invalid() {
+        let mut cfg = Config::default();
+        cfg.security.deny_paths = vec![crate::config::DenyPath {
+            pattern: "(".into(),
+            status: 404,
+        }];
+        assert!(compile_deny_paths(&cfg).is_err());
+
+        cfg.security.deny_paths = vec![crate::config::DenyPath {
+            pattern: "x".into(),
+            status: 500,
+        }];
+        assert!(compile_deny_paths(&cfg).is_err());
+    }
+
+    #[test]
+    fn test_build_rate_limit_quota() {
+        let default = build_rate_limit_quota(&RateLimit::default()).unwrap();
+        assert_eq!(default, Quota::per_minute(NonZeroU32::new(60).unwrap()));
+
+        // 10 requests per second with a burst of 50
+        let rl = RateLimit {
+            requests: 10,
+            period: Duration::from_secs(1),
+            burst: Some(50),
+            ..RateLimit::default()
+        };
+        let quota = build_rate_limit_quota(&rl).unwrap();
+        assert_eq!(quota.replenish_interval(), Duration::from_millis(100));
+        assert_eq!(quota.burst_size().get(), 50);
+
+        let limiter = RateLimiter::direct(quota);
+        for _ in 0..50 {
+            assert!(limiter.check().is_ok());
+        }
+        assert!(limiter.check().is_err());
+    }
+
+    #[test]
+    fn test_build_rate_limit_quota_rejects_invalid() {
+        let zero_burst = RateLimit {
+            burst: Some(0),
+            ..RateLimit::default()
+        };
+        assert!(build_rate_limit_quota(&zero_burst).is_err());
+
+        let zero_period = RateLimit {
+            period: Duration::ZERO,
+            ..RateLimit::default()
+        };
+        assert!(build_rate_limit_quota(&zero_period).is_err());
+    }
+
+    #[test]
+    fn test_compile_ip_filter() {
+        let mut cfg = Config::default();
+        assert!(compile_ip_filter(&cfg).unwrap().is_none());
+
+        cfg.security.ip_filter.allow = vec!["10.0.0.0/8".into(), "::1".into()];
+        assert!(compile_ip_filter(&cfg).unwrap().is_some());
+
+        cfg.security.ip_filter.deny = vec!["10.0.0.0/33".into()];
+        assert!(compile_ip_filter(&cfg).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_limiter_per_ip() {
+        let limiter = Arc::new(IpConcurrencyLimiter::new(2));
+        let a = IpAddr::from([192, 0, 2, 1]);
+        let b = IpAddr::from([192, 0, 2, 2]);
+        let p1 = limiter.try_acquire(a).unwrap();
+        let p2 = limiter.try_acquire(a).unwrap();
+        assert!(limiter.try_acquire(a).is_none());
+        // Other clients have their own budget
+        let p3 = limiter.try_acquire(b).unwrap();
+        assert_eq!(limiter.len(), 2);
+
+        drop(p1);
+        let p4 = limiter.try_acquire(a).unwrap();
+        drop((p2, p3, p4));
+        // Idle IPs leave no state behind
+        assert_eq!(limiter.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_http_client_negotiates_http2() {
+        // TLS upstream offering h2 and http/1.1, echoing the version it was reached over
+        let pair = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let server_tls = axum_server::tls_rustls::RustlsConfig::from_pem(
+            pair.cert.pem().into_bytes(),
+            pair.key_pair.serialize_pem().into_bytes(),
+        )
+        .await
+        .unwrap();
+        let version = |req: http::Request<Body>| async move { format!("{:?}", req.version()) };
+        let app = axum::Router::new().route("/", axum::routing::get(version));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            axum_server::from_tcp_rustls(listener, server_tls).serve(app.into_make_service()),
+        );
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(pair.cert.der().clone()).unwrap();
+        for (http2, expected) in [(true, http::Version::HTTP_2), (false, http::Version::HTTP_11)] {
+            let tls = rustls::ClientConfig::builder()
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth();
+            let client = client_with_tls(
+                HttpsConnectorBuilder::new().with_tls_config(tls),
+                &ProxyClient {
+                    http2,
+                    ..ProxyClient::default()
+                },
+                None,
+            );
+            let req = http::Request::get(format!("https://localhost:{port}/"))
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await.unwrap();
+            assert_eq!(res.version(), expected);
+            let body = axum::body::to_bytes(Body::new(res.into_body()), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, format!("{expected:?}"));
+        }
+    }
+
+    #[test]
+    fn test_shared_http_client_per_settings() {
+        let h2 = ProxyClient::default();
+        let h1 = ProxyClient {
+            http2: false,
+            ..ProxyClient::default()
+        };
+        // Equal settings reuse the cached client instead of building another
+        let clients = |s: &ProxyClient| {
+            shared_http_client(s);
+            HTTP_CLIENTS.lock().unwrap().iter().filter(|(c, _)| c == s).count()
+        };
+        assert_eq!(clients(&h2), 1);
+        assert_eq!(clients(&h2), 1);
+        assert_eq!(clients(&h1), 1);
+    }
+
+    /// Send `count` requests `pause` apart and return how many connections they used
+    async fn connections_used(settings: &ProxyClient, count: usize, pause: Duration) -> usize {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let peer = |ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.to_string() };
+        let app = axum::Router::new().route("/", axum::routing::get(peer));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = app.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+
+        let client = build_http_client(settings, None);
+        let mut peers = std::collections::HashSet::new();
+        for _ in 0..count {
+            let req = http::Request::get(format!("http://{addr}/"))
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await.unwrap();
+            let body = axum::body::to_bytes(Body::new(res.into_body()), usize::MAX)
+                .await
+                .unwrap();
+            peers.insert(body);
+            tokio::time::sleep(pause).await;
+        }
+        peers.len()
+    }
+
+    #[tokio::test]
+    async fn test_http_client_pool_settings() {
+        let pause = Duration::from_millis(200);
+        // Defaults reuse the idle connection
+        assert_eq!(connections_used(&ProxyClient::default(), 3, pause).await, 1);
+
+        let no_idle = ProxyClient {
+            pool_max_idle_per_host: Some(0),
+            ..ProxyClient::default()
+        };
+        assert_eq!(connections_used(&no_idle, 3, Duration::ZERO).await, 3);
+
+        let short_idle = ProxyClient {
+            pool_idle_timeout: Duration::from_millis(50),
+            ..ProxyClient::default()
+        };
+        assert_eq!(connections_used(&short_idle, 3, pause).await, 3);
+    }
+
+    /// A listener that never accepts, with its backlog filled so that further
+    /// connection attempts hang instead of being refused
+    ///
+    /// Keep the returned values alive for as long as connects should hang.
+    pub(crate) async fn stalled_listener() -> (
+        std::net::SocketAddr,
+        tokio::net::TcpListener,
+        Vec<tokio::net::TcpStream>,
+    ) {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        for _ in 0..8 {
+            let connect = tokio::net::TcpStream::connect(addr);
+            match tokio::time::timeout(Duration::from_millis(100), connect).await {
+                Ok(stream) => backlog.push(stream.unwrap()),
+                Err(_) => break,
+            }
+        }
+        (addr, listener, backlog)
+    }
+
+    #[tokio::test]
+    async fn test_http_client_connect_timeout() {
+        let (addr, _listener, _backlog) = stalled_listener().await;
+        let settings = ProxyClient {
+            connect_timeout: Duration::from_millis(100),
+            ..ProxyClient::default()
+        };
+        let client = build_http_client(&settings, None);
+        let req = http::Request::get(format!("http://{addr}/"))
+            .body(Body::empty())
+            .unwrap();
+        let started = std::time::Instant::now();
+        let res = tokio::time::timeout(Duration::from_secs(5), client.request(req)).await;
+        let err = res.expect("connect_timeout should fail the request").unwrap_err();
+        assert!(err.is_connect());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -1,84 +1,527 @@
+use crate::config::{IpRange, Server};
+use axum::extract::ConnectInfo;
+use http::{
+    header::{ACCESS_CONTROL_REQUEST_METHOD, VARY},
+    HeaderMap, HeaderValue, Method,
+};
+use mime_guess::Mime;
+use percent_encoding::percent_decode_str;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+/// Determine the method a CORS policy applies to
+///
+/// For preflight requests this is the method from `Access-Control-Request-Method`,
+/// otherwise the request's own method.
+pub fn cors_request_method(method: &Method, headers: &HeaderMap) -> Option<Method> {
+    if method == Method::OPTIONS {
+        headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|s| Method::from_bytes(s.as_bytes()).ok())
+    } else {
+        Some(method.clone())
+    }
+}
+
+/// Pick the client address from an `X-Forwarded-For` value
+///
+/// With `hops == 0` the leftmost entry is used. Behind `hops` trusted reverse
+/// proxies, each of which appends the address it received the request from,
+/// the client is the `hops`-th entry counting from the right; anything further
+/// left may be spoofed. Shorter lists fall back to their leftmost entry.
+pub fn forwarded_client(xff: &str, hops: usize) -> Option<&str> {
+    let entries: Vec<&str> = xff.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+    let idx = match hops {
+        0 => 0,
+        n => entries.len().saturating_sub(n),
+    };
+    entries.get(idx).copied()
+}
+
+/// Percent-decoded, normalized request path, as the static handler resolves it
+///
+/// `%xx` escapes are decoded (invalid UTF-8 lossily), empty and `.` segments
+/// are dropped and `..` removes the previous segment. The result starts with
+/// `/` and keeps a trailing slash.
+pub fn normalize_path(raw: &str) -> String {
+    let decoded = percent_decode_str(raw).decode_utf8_lossy();
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut path = format!("/{}", segments.join("/"));
+    if decoded.ends_with('/') && !segments.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+/// Whether the normalized `path` is `prefix` or lies below it
+///
+/// Matches whole segments only: `/private` covers `/private/a` but not `/privateer`.
+pub fn path_under(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty()
+        || path == prefix
+        || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Socket address of the connected peer
+///
+/// Set by the server through [`ConnectInfo`]; absent when a request is built
+/// without one, as in tests.
+pub fn peer_addr<B>(req: &http::Request<B>) -> Option<SocketAddr> {
+    req.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr)
+}
+
+/// Client address of a request received from `peer`, behind `trusted` proxies
+///
+/// `X-Forwarded-For` is only honored when `peer` itself is trusted. Its
+/// entries are then walked from the right, skipping trusted proxies; the first
+/// other address is the client. A malformed entry ends the walk, and a chain
+/// made only of trusted proxies yields its leftmost entry.
+pub fn trusted_client(peer: IpAddr, xff: Option<&str>, trusted: &[IpRange]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+    let mut client = peer;
+    if !is_trusted(peer) {
+        return client;
+    }
+    let entries = xff.unwrap_or_default().rsplit(',').map(str::trim);
+    for ip in entries.map_while(|entry| entry.parse::<IpAddr>().ok()) {
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Request headers a response varies on
+///
+/// Negotiation features (compression, UA rules, client hints, CORS, ...) each
+/// contribute dimensions; they are merged case-insensitively and written back
+/// as a single `Vary` header so shared caches key on every dimension once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Vary {
+    dims: Vec<String>,
+    any: bool,
+}
+
+impl Vary {
+    /// Collect the dimensions from all `Vary` headers already present
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut vary = Self::default();
+        for value in headers.get_all(VARY) {
+            if let Ok(s) = value.to_str() {
+                s.split(',').for_each(|dim| vary.add(dim));
+            }
+        }
+        vary
+    }
+
+    /// Add a dimension, ignoring duplicates; `*` subsumes all others
+    pub fn add(&mut self, dim: &str) {
+        let dim = dim.trim();
+        if dim.is_empty() {
+            return;
+        }
+        if dim == "*" {
+            self.any = true;
+        } else if !self.dims.iter().any(|d| d.eq_ignore_ascii_case(dim)) {
+            self.dims.push(dim.to_string());
+        }
+    }
+
+    /// Replace any `Vary` headers with the combined value
+    pub fn write(&self, headers: &mut HeaderMap) {
+        headers.remove(VARY);
+        let value = if self.any {
+            "*".to_string()
+        } else if self.dims.is_empty() {
+            return;
+        } else {
+            self.dims.join(", ")
+        };
+        if let Ok(hv) = HeaderValue::from_str(&value) {
+            headers.insert(VARY, hv);
+        }
+    }
+}
+
+/// Translate a path glob into an anchored regex
+///
+/// `*` matches within one path segment, `**` across segments (`**/` also
+/// matches no directory at all) and `?` a single character other than `/`.
+/// Everything else is literal.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// Extensions treated as assets when `assets.extensions` is empty
+pub const DEFAULT_ASSET_EXTS: &[&str] = &[
+    "css", "js", "mjs", "map", "png", "jpg", "jpeg", "gif", "webp", "svg", "ico", "ttf", "otf",
+    "woff", "woff2", "mp4", "webm", "mp3",
+];
+
 /// Check if a path is an asset file based on extension
-pub fn is_asset_path(p: &str) -> bool {
-    const EXTS: &[&str] = &[
-        "css", "js", "mjs", "map", "png", "jpg", "jpeg", "gif", "webp", "svg", "ico", "ttf", "otf",
-        "woff", "woff2", "mp4", "webm", "mp3",
-    ];
-    p.rsplit('.')
-        .next()
-        .map(|e| EXTS.contains(&e))
-        .unwrap_or(false)
+///
+/// The extension of the last path segment is compared case-insensitively
+/// against `extensions` (a leading `.` is optional), or [`DEFAULT_ASSET_EXTS`]
+/// if that is empty. A query string or fragment is ignored.
+pub fn is_asset_path(p: &str, extensions: &[String]) -> bool {
+    let path = p.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let Some((_, ext)) = name.rsplit_once('.') else {
+        return false;
+    };
+    if extensions.is_empty() {
+        DEFAULT_ASSET_EXTS.iter().any(|e| e.eq_ignore_ascii_case(ext))
+    } else {
+        extensions
+            .iter()
+            .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(ext))
+    }
+}
+
+/// Content type of the file at `path`, by its extension
+///
+/// `server.mime_overrides` is consulted first (extensions compared
+/// case-insensitively, a leading `.` is optional); invalid override values
+/// are ignored. Otherwise the type is guessed from the extension, falling
+/// back to `server.default_mime` (or `application/octet-stream` if invalid).
+pub fn guess_mime(path: &Path, server: &Server) -> Mime {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let overridden = server
+        .mime_overrides
+        .iter()
+        .find(|(e, _)| !ext.is_empty() && e.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        .and_then(|(_, mime)| mime.parse().ok());
+    overridden
+        .or_else(|| mime_guess::from_path(path).first())
+        .or_else(|| server.default_mime.parse().ok())
+        .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM)
+}
+
+/// `Content-Type` value for `mime`, with `; charset=utf-8` added to textual types
+///
+/// Applies to `text/*`, `application/javascript`, `application/json` and
+/// `image/svg+xml` unless a charset is already given.
+pub fn with_charset(mime: &Mime) -> String {
+    let textual = mime.type_() == mime_guess::mime::TEXT
+        || matches!(
+            mime.essence_str(),
+            "application/javascript" | "application/json" | "image/svg+xml"
+        );
+    if textual && mime.get_param(mime_guess::mime::CHARSET).is_none() {
+        format!("{mime}; charset=utf-8")
+    } else {
+        mime.to_string()
+    }
+}
+
+/// `Content-Disposition` value offering `filename` as a download
+///
+/// The quoted `filename` is an ASCII fallback in which `"`, `\`, control and
+/// non-ASCII characters become `_`. Non-ASCII names are also sent in full as
+/// RFC 5987 `filename*`, which browsers prefer.
+pub fn attachment_disposition(filename: &str) -> HeaderValue {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            ' '..='~' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut value = format!("attachment; filename=\"{fallback}\"");
+    if !filename.is_ascii() {
+        value.push_str("; filename*=UTF-8''");
+        for b in filename.bytes() {
+            match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => value.push(b as char),
+                b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|'
+                | b'~' => value.push(b as char),
+                _ => value.push_str(&format!("%{b:02X}")),
+            }
+        }
+    }
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_cors_request_method() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(cors_request_method(&Method::GET, &headers), Some(Method::GET));
+        assert_eq!(cors_request_method(&Method::OPTIONS, &headers), None);
+        headers.insert(ACCESS_CONTROL_REQUEST_METHOD, "POST".parse().unwrap());
+        assert_eq!(cors_request_method(&Method::OPTIONS, &headers), Some(Method::POST));
+    }
+
+    #[test]
+    fn test_forwarded_client() {
+        let xff = "203.0.113.9, 198.51.100.1, 10.0.0.1";
+        assert_eq!(forwarded_client(xff, 0), Some("203.0.113.9"));
+        assert_eq!(forwarded_client(xff, 1), Some("10.0.0.1"));
+        assert_eq!(forwarded_client(xff, 2), Some("198.51.100.1"));
+        assert_eq!(forwarded_client(xff, 3), Some("203.0.113.9"));
+        assert_eq!(forwarded_client(xff, 5), Some("203.0.113.9"));
+        assert_eq!(forwarded_client("", 1), None);
+    }
+
+    #[test]
+    fn test_vary_merges_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(VARY, HeaderValue::from_static("DPR, Width"));
+        headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+        headers.append(VARY, HeaderValue::from_static("dpr, Accept-Encoding"));
+        let mut vary = Vary::from_headers(&headers);
+        vary.add("user-agent");
+        vary.write(&mut headers);
+        assert_eq!(headers.get_all(VARY).iter().count(), 1);
+        assert_eq!(
+            headers.get(VARY).unwrap(),
+            "DPR, Width, accept-encoding, user-agent"
+        );
+    }
+
+    #[test]
+    fn test_vary_star_and_empty() {
+        let mut headers = HeaderMap::new();
+        Vary::default().write(&mut headers);
+        assert!(headers.get(VARY).is_none());
+
+        let mut vary = Vary::default();
+        vary.add("accept-language");
+        vary.add("*");
+        vary.write(&mut headers);
+        assert_eq!(headers.get(VARY).unwrap(), "*");
+    }
+
     #[test]
     fn test_is_asset_path_css() {
-        assert!(is_asset_path("style.css"));
-        assert!(is_asset_path("app.min.css"));
+        assert!(is_asset_path("style.css", &[]));
+        assert!(is_asset_path("app.min.css", &[]));
     }
 
     #[test]
     fn test_is_asset_path_js() {
-        assert!(is_asset_path("script.js"));
-        assert!(is_asset_path("app.mjs"));
-        assert!(is_asset_path("bundle.js"));
+        assert!(is_asset_path("script.js", &[]));
+        assert!(is_asset_path("app.mjs", &[]));
+        assert!(is_asset_path("bundle.js", &[]));
     }
 
     #[test]
     fn test_is_asset_path_images() {
-        assert!(is_asset_path("image.png"));
-        assert!(is_asset_path("photo.jpg"));
-        assert!(is_asset_path("picture.jpeg"));
-        assert!(is_asset_path("icon.gif"));
-        assert!(is_asset_path("logo.webp"));
-        assert!(is_asset_path("vector.svg"));
-        assert!(is_asset_path("favicon.ico"));
+        assert!(is_asset_path("image.png", &[]));
+        assert!(is_asset_path("photo.jpg", &[]));
+        assert!(is_asset_path("picture.jpeg", &[]));
+        assert!(is_asset_path("icon.gif", &[]));
+        assert!(is_asset_path("logo.webp", &[]));
+        assert!(is_asset_path("vector.svg", &[]));
+        assert!(is_asset_path("favicon.ico", &[]));
     }
 
     #[test]
     fn test_is_asset_path_fonts() {
-        assert!(is_asset_path("font.ttf"));
-        assert!(is_asset_path("font.otf"));
-        assert!(is_asset_path("font.woff"));
-        assert!(is_asset_path("font.woff2"));
+        assert!(is_asset_path("font.ttf", &[]));
+        assert!(is_asset_path("font.otf", &[]));
+        assert!(is_asset_path("font.woff", &[]));
+        assert!(is_asset_path("font.woff2", &[]));
     }
 
     #[test]
     fn test_is_asset_path_media() {
-        assert!(is_asset_path("video.mp4"));
-        assert!(is_asset_path("video.webm"));
-        assert!(is_asset_path("audio.mp3"));
+        assert!(is_asset_path("video.mp4", &[]));
+        assert!(is_asset_path("video.webm", &[]));
+        assert!(is_asset_path("audio.mp3", &[]));
     }
 
     #[test]
     fn test_is_asset_path_not_assets() {
-        assert!(!is_asset_path("file.txt"));
-        assert!(!is_asset_path("README"));
-        assert!(!is_asset_path(""));
-        assert!(!is_asset_path("document.pdf"));
-        assert!(!is_asset_path("data.json"));
-        assert!(!is_asset_path("statiker.yaml"));
+        assert!(!is_asset_path("file.txt", &[]));
+        assert!(!is_asset_path("README", &[]));
+        assert!(!is_asset_path("", &[]));
+        assert!(!is_asset_path("document.pdf", &[]));
+        assert!(!is_asset_path("data.json", &[]));
+        assert!(!is_asset_path("statiker.yaml", &[]));
     }
 
     #[test]
     fn test_is_asset_path_with_path() {
-        assert!(is_asset_path("/assets/style.css"));
-        assert!(is_asset_path("static/js/app.js"));
-        assert!(is_asset_path("public/images/logo.png"));
-        assert!(!is_asset_path("/path/to/file.txt"));
-        assert!(!is_asset_path("docs/README.md"));
+        assert!(is_asset_path("/assets/style.css", &[]));
+        assert!(is_asset_path("static/js/app.js", &[]));
+        assert!(is_asset_path("public/images/logo.png", &[]));
+        assert!(!is_asset_path("/path/to/file.txt", &[]));
+        assert!(!is_asset_path("docs/README.md", &[]));
     }
 
     #[test]
-    fn test_is_asset_path_case_sensitive() {
-        // Extension matching is case-sensitive - lowercase only
-        assert!(!is_asset_path("file.CSS")); // Uppercase doesn't match
-        assert!(!is_asset_path("file.JS")); // Uppercase doesn't match
-        assert!(is_asset_path("file.css")); // Lowercase works
-        assert!(is_asset_path("file.js")); // Lowercase works
+    fn test_is_asset_path_case_insensitive() {
+        assert!(is_asset_path("file.CSS", &[]));
+        assert!(is_asset_path("file.JS", &[]));
+        assert!(is_asset_path("LOGO.PNG", &[]));
+        assert!(is_asset_path("Photo.JpEg", &[]));
+        assert!(is_asset_path("file.css", &[]));
+        assert!(!is_asset_path("NOTES.TXT", &[]));
     }
-}
 
+    #[test]
+    fn test_is_asset_path_query_and_fragment() {
+        assert!(is_asset_path("/app.js?v=123", &[]));
+        assert!(is_asset_path("/sprite.svg#icon", &[]));
+        assert!(is_asset_path("/style.css?v=1#top", &[]));
+        assert!(!is_asset_path("/page.html?file=app.js", &[]));
+        assert!(!is_asset_path("/download#x.png", &[]));
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let matches = |glob: &str, path: &str| {
+            regex::Regex::new(&glob_to_regex(glob)).unwrap().is_match(path)
+        };
+        assert!(matches("/index.html", "/index.html"));
+        assert!(!matches("/index.html", "/indexxhtml"));
+        assert!(!matches("/index.html", "/sub/index.html"));
+        assert!(matches("/static/*", "/static/app.3f2a.js"));
+        assert!(!matches("/static/*", "/static/js/app.js"));
+        assert!(matches("/static/**", "/static/js/app.js"));
+        assert!(matches("/**/*.html", "/index.html"));
+        assert!(matches("/**/*.html", "/docs/guide/intro.html"));
+        assert!(!matches("/**/*.html", "/docs/intro.htm"));
+        assert!(matches("/img/logo-?.png", "/img/logo-2.png"));
+        assert!(!matches("/img/logo-?.png", "/img/logo-/.png"));
+    }
+
+    #[test]
+    fn test_is_asset_path_custom_extensions() {
+        let exts = ["wasm".to_string(), ".JSON".to_string(), "css".to_string()];
+        assert!(is_asset_path("/app.wasm", &exts));
+        assert!(is_asset_path("/data/points.json", &exts));
+        assert!(is_asset_path("/style.CSS", &exts));
+        // The custom list replaces the defaults
+        assert!(!is_asset_path("/intro.mp4", &exts));
+        assert!(!is_asset_path("/app.js", &exts));
+        assert!(is_asset_path("/intro.mp4", &[]));
+        assert!(!is_asset_path("/app.wasm", &[]));
+    }
+
+    #[test]
+    fn test_is_asset_path_extension_of_last_segment_only() {
+        assert!(!is_asset_path("css", &[]));
+        assert!(!is_asset_path("/assets.js/readme", &[]));
+        assert!(!is_asset_path("/static/png", &[]));
+    }
+
+    #[test]
+    fn test_guess_mime_overrides() {
+        let mut server = Server::default();
+        server.mime_overrides.insert(".md".into(), "text/plain".into());
+        server.mime_overrides.insert("DATA".into(), "application/x-custom".into());
+        server.mime_overrides.insert("css".into(), "not a mime".into());
+
+        let mime = |p: &str| guess_mime(Path::new(p), &server).to_string();
+        assert_eq!(mime("docs/README.md"), "text/plain");
+        assert_eq!(mime("level.data"), "application/x-custom");
+        assert_eq!(mime("LEVEL.Data"), "application/x-custom");
+        // Invalid overrides and other extensions use the guess
+        assert_eq!(mime("style.css"), "text/css");
+        assert_eq!(mime("app.js"), "text/javascript");
+        assert_eq!(mime("Makefile"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_guess_mime_default() {
+        let mut server = Server {
+            default_mime: "text/plain".into(),
+            ..Server::default()
+        };
+        let mime = |p: &str| guess_mime(Path::new(p), &server).to_string();
+        assert_eq!(mime("LICENSE"), "text/plain");
+        assert_eq!(mime("notes.unknownext"), "text/plain");
+        assert_eq!(mime("logo.png"), "image/png");
+
+        server.default_mime = "nonsense".into();
+        let mime = guess_mime(Path::new("LICENSE"), &server);
+        assert_eq!(mime, mime_guess::mime::APPLICATION_OCTET_STREAM);
+    }
+
+    #[test]
+    fn test_with_charset() {
+        let charset = |m: &str| with_charset(&m.parse().unwrap());
+        assert_eq!(charset("text/html"), "text/html; charset=utf-8");
+        assert_eq!(charset("text/css"), "text/css; charset=utf-8");
+        assert_eq!(charset("text/javascript"), "text/javascript; charset=utf-8");
+        assert_eq!(charset("application/javascript"), "application/javascript; charset=utf-8");
+        assert_eq!(charset("application/json"), "application/json; charset=utf-8");
+        assert_eq!(charset("image/svg+xml"), "image/svg+xml; charset=utf-8");
+        // An explicit charset is kept
+        assert_eq!(charset("text/plain; charset=latin1"), "text/plain; charset=latin1");
+        // Binary types get none
+        assert_eq!(charset("image/png"), "image/png");
+        assert_eq!(charset("application/wasm"), "application/wasm");
+        assert_eq!(charset("application/octet-stream"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_attachment_disposition() {
+        let disposition = |name: &str| attachment_disposition(name).to_str().unwrap().to_string();
+        assert_eq!(disposition("report.pdf"), "attachment; filename=\"report.pdf\"");
+        assert_eq!(disposition("a \"b\"\\c.txt"), "attachment; filename=\"a _b__c.txt\"");
+        assert_eq!(
+            disposition("Résumé 2024.pdf"),
+            "attachment; filename=\"R_sum_ 2024.pdf\"; filename*=UTF-8''R%C3%A9sum%C3%A9%202024.pdf"
+        );
+        assert_eq!(
+            disposition("数据.csv"),
+            "attachment; filename=\"__.csv\"; filename*=UTF-8''%E6%95%B0%E6%8D%AE.csv"
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/%70rivate/secret.txt"), "/private/secret.txt");
+        assert_eq!(normalize_path("//private/./a/../b/"), "/private/b/");
+        assert_eq!(normalize_path("/private%2Fsecret"), "/private/secret");
+        assert_eq!(normalize_path("/../.."), "/");
+        assert_eq!(normalize_path("/caf%C3%A9"), "/café");
+    }
+
+    #[test]
+    fn test_path_under() {
+        assert!(path_under("/private", "/private"));
+        assert!(path_under("/private/a", "/private/"));
+        assert!(!path_under("/privateer", "/private"));
+        assert!(!path_under("/public", "/private"));
+        assert!(path_under("/anything", "/"));
+    }
+}
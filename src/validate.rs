@@ -0,0 +1,247 @@
+use crate::config::Config;
+use crate::router::build_router;
+use crate::server::validate_tls;
+use crate::state::{
+    build_rate_limit_quota, compile_cache_rules, compile_deny_paths, compile_ip_filter, AppState,
+};
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
+
+/// Outcome of `statiker validate`
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Problems that would stop the server from starting or serving correctly
+    pub errors: Vec<String>,
+    /// Suspicious settings the server accepts
+    pub warnings: Vec<String>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Human-readable report, one line per finding
+    pub fn lines(&self, path: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.extend(self.errors.iter().map(|e| format!("error: {e}")));
+        lines.extend(self.warnings.iter().map(|w| format!("warning: {w}")));
+        lines.push(match self.errors.len() {
+            0 => format!("{path}: configuration is valid"),
+            1 => format!("{path}: 1 error"),
+            n => format!("{path}: {n} errors"),
+        });
+        lines
+    }
+}
+
+/// Load and check the config file at `path` without binding any socket
+///
+/// Unlike a normal start, a missing file is an error rather than a fallback
+/// to the built-in defaults.
+pub async fn check_file(path: &str) -> Report {
+    let text = match tokio::fs::read_to_string(path).await {
+        Ok(text) => text,
+        Err(e) => {
+            return Report {
+                errors: vec![format!("reading {path}: {e}")],
+                ..Report::default()
+            }
+        }
+    };
+    match Config::from_yaml(&text) {
+        Ok(cfg) => check(&cfg).await,
+        Err(e) => Report {
+            errors: vec![format!("parsing YAML: {e}")],
+            ..Report::default()
+        },
+    }
+}
+
+/// Run the startup checks against a parsed config
+pub async fn check(cfg: &Config) -> Report {
+    let mut report = Report::default();
+
+    let addr = format!("{}:{}", cfg.server.host, cfg.server.port);
+    if addr.parse::<SocketAddr>().is_err() {
+        report.errors.push(format!("invalid host/port '{addr}'"));
+    }
+    if let Err(e) = validate_tls(cfg).await {
+        report.errors.push(e.to_string());
+    }
+    if cfg.security.rate_limit.enabled {
+        if let Err(e) = build_rate_limit_quota(&cfg.security.rate_limit) {
+            report.errors.push(e.to_string());
+        }
+    }
+    if let Err(e) = compile_deny_paths(cfg) {
+        report.errors.push(e.to_string());
+    }
+    if let Err(e) = compile_ip_filter(cfg) {
+        report.errors.push(e.to_string());
+    }
+    if let Err(e) = compile_cache_rules(cfg) {
+        report.errors.push(e.to_string());
+    }
+    for (ext, mime) in &cfg.server.mime_overrides {
+        if mime.parse::<mime_guess::Mime>().is_err() {
+            report
+                .errors
+                .push(format!("server.mime_overrides: invalid content type '{mime}' for '{ext}'"));
+        }
+    }
+    if cfg.server.default_mime.parse::<mime_guess::Mime>().is_err() {
+        report.errors.push(format!(
+            "server.default_mime: invalid content type '{}'",
+            cfg.server.default_mime
+        ));
+    }
+
+    // The router panics on these, so they're reported here and left out of
+    // the dry run below
+    let mut seen = HashSet::new();
+    let mut mountable = Vec::new();
+    for route in &cfg.routing {
+        let path = &route.path;
+        if !path.starts_with('/') {
+            report.errors.push(format!("route '{path}' must start with '/'"));
+        } else if !seen.insert(path.as_str()) {
+            report.errors.push(format!("route '{path}' is configured more than once"));
+        } else {
+            mountable.push(route.clone());
+        }
+        match (route.serve.as_deref(), &route.proxy) {
+            (Some("static"), Some(_)) => report.warnings.push(format!(
+                "route '{path}' has both 'serve: static' and 'proxy'; 'proxy' will be ignored"
+            )),
+            (Some("static"), None) => {
+                if let Some(root) = route.root.as_ref().filter(|root| !root.is_dir()) {
+                    report.warnings.push(format!(
+                        "route '{path}' root '{}' is not a directory",
+                        root.display()
+                    ));
+                }
+            }
+            (_, Some(_)) => {}
+            (Some(other), None) => report
+                .warnings
+                .push(format!("route '{path}' has unknown 'serve: {other}' and is skipped")),
+            (None, None) => report
+                .warnings
+                .push(format!("route '{path}' has neither 'serve' nor 'proxy' and is skipped")),
+        }
+    }
+
+    // Build the route table exactly like startup does (health path, proxy
+    // upstreams, rewrite patterns, ...) without binding anything
+    let dry_run = Config { routing: mountable, ..cfg.clone() };
+    let state = AppState {
+        root: dry_run.server.root.clone(),
+        cfg: Arc::new(dry_run),
+        limiter: None,
+        deny_paths: Arc::default(),
+        read_limit: None,
+        concurrency: None,
+        memcache: None,
+        live: None,
+    };
+    if let Err(e) = build_router(&state) {
+        report.errors.push(e.to_string());
+    }
+
+    if !cfg.server.root.is_dir() {
+        report.warnings.push(format!(
+            "server.root '{}' is not a directory",
+            cfg.server.root.display()
+        ));
+    }
+    report
+}
+
+/// `statiker validate`: print the report and fail if the config has errors
+pub async fn run(path: &str) -> anyhow::Result<()> {
+    let report = check_file(path).await;
+    for line in report.lines(path) {
+        println!("{line}");
+    }
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("configuration is invalid"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, yaml: &str) -> String {
+        let path = std::env::temp_dir().join(format!("statiker_test_validate_{name}.yaml"));
+        std::fs::write(&path, yaml).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_validate_good_config() {
+        let path = write_config(
+            "good",
+            "server:\n  host: 127.0.0.1\n  port: 8080\n  root: .\n  index: index.html\n\
+             routing:\n  - path: /\n    serve: static\n  - path: /api/*\n    proxy:\n      \
+             url: http://127.0.0.1:3000\n",
+        );
+        let report = check_file(&path).await;
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert!(run(&path).await.is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validate_bad_config() {
+        let path = write_config(
+            "bad",
+            "server:\n  host: not a host\n  port: 8080\n  root: .\n  index: index.html\n\
+             tls:\n  enabled: true\n  cert_path: /nonexistent/cert.pem\n  \
+             key_path: /nonexistent/key.pem\n\
+             routing:\n  - path: /api/*\n    proxy: {}\n  - path: /api/*\n    serve: static\n\
+             \x20   root: /nonexistent/docs\n\
+             security:\n  deny_paths:\n    - pattern: '('\n",
+        );
+        let report = check_file(&path).await;
+        assert_eq!(report.errors.len(), 5, "{:?}", report.errors);
+        assert!(report.errors.iter().any(|e| e.contains("host/port")));
+        assert!(report.errors.iter().any(|e| e.contains("TLS")));
+        assert!(report.errors.iter().any(|e| e.contains("no upstream url")));
+        assert!(report.errors.iter().any(|e| e.contains("more than once")));
+        assert!(report.errors.iter().any(|e| e.contains("deny_paths")));
+        assert!(report.warnings.iter().any(|w| w.contains("'/nonexistent/docs' is not a")));
+        assert_eq!(report.lines(&path).last().unwrap(), &format!("{path}: 5 errors"));
+        assert!(run(&path).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validate_runs_router_checks() {
+        let path = write_config(
+            "router",
+            "server:\n  host: 127.0.0.1\n  port: 8080\n  root: .\n  index: index.html\n\
+             routing:\n  - path: /api/*\n    proxy:\n      url: http://127.0.0.1:3000\n\
+             \x20     rewrite:\n        from: '('\n        to: ''\n",
+        );
+        let report = check_file(&path).await;
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].contains("invalid proxy rewrite pattern"));
+        std::fs::remove_file(&path).unwrap();
+
+        let mut cfg = Config::default();
+        cfg.obs.health.enabled = true;
+        cfg.obs.health.path = "healthz".into();
+        let report = check(&cfg).await;
+        assert!(report.errors.iter().any(|e| e.contains("obs.health.path")), "{:?}", report.errors);
+    }
+
+    #[tokio::test]
+    async fn test_validate_missing_file() {
+        let report = check_file("/nonexistent/statiker.yaml").await;
+        assert!(!report.is_ok());
+    }
+}